@@ -5,26 +5,18 @@ basedir="$(cd $(dirname $0); pwd)"
 . "$basedir/config"
 
 start_bin="$basedir/phoseum-start.sh"
-playlist_cmd="$basedir/playlist-cmd.sh"
-sleep_bin="$basedir/phoseum-sleep.sh"
-wakeup_bin="$basedir/phoseum-wakeup.sh"
-
-function on_calendars() {
-    echo "$1" | while read s; do
-        echo "OnCalendar=$s"
-    done
-}
 
 sudo tee /etc/systemd/system/phoseum.service <<EOF >/dev/null
 [Unit]
 Description=Photo and video slideshow
-Requires=network-online.target phoseum-pl-refresh.timer phoseum-pl-update.timer
+Requires=network-online.target
 After=network-online.target lightdm.service
 
 [Service]
 User=pi
 Group=pi
 ExecStart=$start_bin
+ExecReload=/bin/kill -HUP \$MAINPID
 Restart=always
 RestartSec=30
 KillMode=process
@@ -33,100 +25,13 @@ KillMode=process
 WantedBy=graphical.target
 EOF
 
-# Playlist refresh
-sudo tee /etc/systemd/system/phoseum-pl-refresh.service <<EOF >/dev/null
-[Unit]
-Description=Refresh phoseum playlist
-BindsTo=phoseum.service
-After=phoseum.service
-
-[Service]
-Type=oneshot
-ExecStart=$playlist_cmd refresh
-EOF
-sudo tee /etc/systemd/system/phoseum-pl-refresh.timer <<EOF >/dev/null
-[Unit]
-Description=Refresh phoseum playlist
-BindsTo=phoseum.service
-
-[Timer]
-$(on_calendars "$REFRESH_CAL")
-
-[Install]
-WantedBy=timers.target
-EOF
-
-# Playlist update
-sudo tee /etc/systemd/system/phoseum-pl-update.service <<EOF >/dev/null
-[Unit]
-Description=Update phoseum playlist
-BindsTo=phoseum.service
-After=phoseum.service
-
-[Service]
-Type=oneshot
-ExecStart=$playlist_cmd update
-EOF
-sudo tee /etc/systemd/system/phoseum-pl-update.timer <<EOF >/dev/null
-[Unit]
-Description=Update phoseum playlist
-BindsTo=phoseum.service
-
-[Timer]
-$(on_calendars "$UPDATE_CAL")
-
-[Install]
-WantedBy=timers.target
-EOF
-
-# Sleep
-sudo tee /etc/systemd/system/phoseum-sleep.service <<EOF >/dev/null
-[Unit]
-Description=Enter sleep mode
-
-[Service]
-Type=oneshot
-ExecStart=$sleep_bin
-EOF
-sudo tee /etc/systemd/system/phoseum-sleep.timer <<EOF >/dev/null
-[Unit]
-Description=Enter sleep mode
-
-[Timer]
-$(on_calendars "$SLEEP_CAL")
-
-[Install]
-WantedBy=timers.target
-EOF
-
-# Wake up
-sudo tee /etc/systemd/system/phoseum-wakeup.service <<EOF >/dev/null
-[Unit]
-Description=Back from sleep mode
-
-[Service]
-Type=oneshot
-ExecStart=$wakeup_bin
-EOF
-sudo tee /etc/systemd/system/phoseum-wakeup.timer <<EOF >/dev/null
-[Unit]
-Description=Back from sleep mode
-
-[Timer]
-$(on_calendars "$WAKEUP_CAL")
-
-[Install]
-WantedBy=timers.target
-EOF
+# Playlist update/refresh and sleep/wakeup are scheduled by phoseum itself, see [schedule] in phoseum.toml
+for timer in phoseum-pl-refresh phoseum-pl-update phoseum-sleep phoseum-wakeup; do
+    if [ -e /etc/systemd/system/$timer.timer ]; then
+        sudo systemctl disable --now $timer.timer
+        sudo rm -f /etc/systemd/system/$timer.timer /etc/systemd/system/$timer.service
+    fi
+done
 
 sudo systemctl daemon-reload
 sudo systemctl enable phoseum
-
-sudo systemctl enable phoseum-pl-refresh.timer
-sudo systemctl enable phoseum-pl-update.timer
-sudo systemctl enable phoseum-sleep.timer
-sudo systemctl enable phoseum-wakeup.timer
-sudo systemctl start phoseum-pl-refresh.timer
-sudo systemctl start phoseum-pl-update.timer
-sudo systemctl start phoseum-sleep.timer
-sudo systemctl start phoseum-wakeup.timer
@@ -8,6 +8,28 @@ media_dir="$basedir/photos"
 
 . "$basedir/config"
 
+# Google Photos settings used to be in ./config. Move any left there into the empty
+# placeholders of phoseum.toml
+migrate_setting() { # VARIABLE KEY
+    eval "value=\"\$$1\""
+    toml="$basedir/phoseum.toml"
+    if [ -n "$value" ] && grep -q "^$2 = \[\?\"\"" "$toml"; then
+        # Given by environment, as arguments are visible to other users in ps
+        key="$2" value="$value" awk '
+            index($0, ENVIRON["key"] " = \"\"") == 1 || index($0, ENVIRON["key"] " = [\"\"") == 1 {
+                i = index($0, "\"\"")
+                $0 = substr($0, 1, i) ENVIRON["value"] substr($0, i + 1)
+            }
+            { print }' "$toml" > "$toml.tmp"
+        cat "$toml.tmp" > "$toml" # Keeping permissions of the file
+        rm "$toml.tmp"
+        echo "Moved $1 to $2 of phoseum.toml, remove it from $basedir/config"
+    fi
+}
+migrate_setting GOOGLE_OAUTH_CLIENT_ID oauth_client_id
+migrate_setting GOOGLE_OAUTH_CLIENT_SECRET oauth_client_secret
+migrate_setting GOOGLE_PHOTOS_ALBUM_ID album_id
+
 if [ ! -d "$media_dir" ]; then
     echo "Creating media dir $media_dir"
     mkdir -p "$media_dir"
@@ -17,21 +39,6 @@ export DISPLAY # Value set in ./config
 export RUST_BACKTRACE=full
 export RUST_LOG="info,phoseum=debug"
 exec $PHOSEUM_BIN \
-     --googlephotos.oauth-client-id="$GOOGLE_OAUTH_CLIENT_ID" \
-     --googlephotos.oauth-client-secret="$GOOGLE_OAUTH_CLIENT_SECRET" \
-     --googlephotos.album-id "$GOOGLE_PHOTOS_ALBUM_ID" \
-     --slideshow.show-duration=30 \
-     --playlist.fresh-retention=$((3600 * 24 * 14)) \
-     --playlist.min-size=30 \
-     --playlist.max-size=100 \
+     --config="$basedir/phoseum.toml" \
      --storage.media-dir="$media_dir" \
-     --storage.capacity=$((10 * 1024 * 1024 * 1024)) \
-     --control.http-port="$CONTROL_HTTP_PORT" \
-     --control.player=gpio \
-     --control.gpio-dev=/dev/gpiochip0 \
-     --control.gpio-map=18:H:play_next:L \
-     --control.gpio-map=10:H:play_back:L \
-     --control.gpio-map=23:H:pause:L \
-     --control.gpio-map=23:L:resume:L \
-     --control.gpio-map=24:H:mute:L \
-     --control.gpio-map=24:L:unmute:L
+     --control.http-port="$CONTROL_HTTP_PORT"
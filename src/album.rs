@@ -1,8 +1,9 @@
 use failure::Fail;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::iter::Iterator;
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 pub trait Error: Fail {
     /// Return if this error is caused by fatal error such as local hardware
@@ -10,15 +11,19 @@ pub trait Error: Fail {
     fn is_fatal(&self) -> bool;
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum MediaType {
     PHOTO,
     VIDEO,
 }
 
-pub trait Album {
+/// Source of items to play.
+///
+/// Albums are shared with background workers preparing items concurrently,
+/// hence they have to be thread-safe.
+pub trait Album: Send + Sync {
     type E: Error + 'static;
-    type Item: AlbumItem + Eq + Debug + 'static;
+    type Item: AlbumItem + Eq + Debug + Send + Sync + 'static;
     type Items: Iterator<Item = Result<Self::Item, Self::E>>;
 
     /// Return the list of items as iterator.
@@ -44,4 +49,19 @@ pub trait AlbumItem: PartialEq + Debug {
 
     /// Return the creation time of this item.
     fn created_time(&self) -> SystemTime;
+
+    /// Return the description given to this item, if any.
+    fn description(&self) -> Option<&str> {
+        None
+    }
+
+    /// Return the original filename of this item, if known.
+    fn filename(&self) -> Option<&str> {
+        None
+    }
+
+    /// Return the playback duration of this item if it's a video and the duration is known.
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
 }
@@ -4,6 +4,7 @@ use googlephotos::api::{GPhotosApi, RetryConfig};
 use phoseum::googlephotos;
 use phoseum::oauth::TokenService;
 use std::env;
+use std::fs;
 use std::io;
 use std::io::BufRead;
 
@@ -11,12 +12,32 @@ fn main() {
     env_logger::init();
 
     let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} OAUTH_CLIENT_ID OAUTH_CLIENT_SECRET", args[0]);
+    if args.len() < 2 {
+        eprintln!("Usage: {} CONFIG_FILE", args[0]);
         std::process::exit(1);
     }
 
-    let auth_config = googlephotos::api::auth_config(args[1].clone(), args[2].clone());
+    // The OAuth client is read from the same config file as phoseum's
+    let config: toml::Value =
+        toml::from_str(&fs::read_to_string(&args[1]).expect("read config file"))
+            .expect("parse config file");
+    let oauth_value = |key: &str| {
+        let value = config
+            .get("googlephotos")
+            .and_then(|section| section.get(key))
+            .and_then(|value| value.as_str())
+            .unwrap_or_default();
+        if value.is_empty() {
+            eprintln!("googlephotos.{} is not set in {}", key, args[1]);
+            std::process::exit(1);
+        }
+        value.to_string()
+    };
+
+    let auth_config = googlephotos::api::auth_config(
+        oauth_value("oauth_client_id"),
+        oauth_value("oauth_client_secret"),
+    );
     let tokens = TokenService::new(auth_config).expect("oauth loading");
 
     let auth_url = tokens.start_new_authorization();
@@ -90,5 +111,5 @@ fn main() {
         }
     }
 
-    println!("Select one ID from the above albums and pass it to phoseum");
+    println!("Select one ID from the above albums and set it to googlephotos.album_id");
 }
@@ -1,21 +1,37 @@
-use clap::{App, Arg, ArgMatches};
+use chrono::format::{Item, StrftimeItems};
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind};
 use env_logger;
 use failure::{Error, Fail};
-use log::error;
+use log::{error, warn};
+use phoseum::album::{Album, AlbumItem};
+use phoseum::calendar::Calendar;
+use phoseum::composite::CompositeAlbum;
 use phoseum::console_control;
-use phoseum::control::PlayerCmd;
+use phoseum::control::{Command, PlayerCmd, PlaylistCmd};
+use phoseum::display::{
+    BacklightDisplay, CommandDisplay, DisplayPlayer, DisplayPower, DpmsDisplay,
+};
 use phoseum::googlephotos::{self, GPhotosAlbum};
 use phoseum::gpio_control;
 use phoseum::http_control;
+use phoseum::localfs::LocalAlbum;
+use phoseum::mqtt_control::MqttCommander;
 use phoseum::oauth::TokenService;
-use phoseum::player::SlideshowConfig;
+use phoseum::player::{CaptionConfig, Player, SlideshowConfig};
+use phoseum::player_mpv::{MpvConfig, MpvPlayer};
 use phoseum::player_vlc::{VlcConfig, VlcPlayer};
 use phoseum::playlist;
-use phoseum::slideshow::Slideshow;
+use phoseum::presence_control::{PresenceCommander, PresenceConfig};
+use phoseum::schedule_control::ScheduleCommander;
+use phoseum::signal_control::SignalCommander;
+use phoseum::slideshow::{PrefetchConfig, ReloadConfig, Slideshow};
 use phoseum::storage::Storage;
-use phoseum::Phoseum;
+use phoseum::{Phoseum, RestartPolicy};
 use signal_hook;
 use std::fmt::Debug;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
@@ -30,6 +46,247 @@ struct InvalidArgError {
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Arguments which are used only at startup, so changing them requires restart.
+/// Rest of arguments are applied to the running slideshow on reload.
+const RESTART_REQUIRED_ARGS: &[&str] = &[
+    "storage.media_dir",
+    "storage.capacity",
+    "storage.eviction",
+    "album",
+    "googlephotos.album_id",
+    "googlephotos.oauth_client_id",
+    "googlephotos.oauth_client_secret",
+    "local.dir",
+    "local.no_hard_link",
+    "player",
+    "player.max_restarts",
+    "vlc.http_port",
+    "vlc.bin",
+    "mpv.bin",
+    "mpv.ipc_socket",
+    "display.power",
+    "display.on_cmd",
+    "display.off_cmd",
+    "display.backlight",
+    "display.xset_bin",
+    "control.player",
+    "control.gpio_dev",
+    "control.http_port",
+    "control.http_host",
+    "mqtt.host",
+    "mqtt.port",
+    "mqtt.username",
+    "mqtt.password",
+    "mqtt.node_id",
+    "mqtt.discovery_prefix",
+    "presence.gpio_offset",
+    "presence.active_level",
+    "presence.idle_timeout",
+    "presence.wake_during_sleep",
+    "schedule.sleep",
+    "schedule.wakeup",
+    "schedule.update",
+    "schedule.refresh",
+];
+
+/// Settings given by command line arguments and the configuration file.
+///
+/// Argument `section.key` is looked up from `key` in the `[section]` table of the file.
+/// Values given in command line take precedence over those in the file, which take
+/// precedence over default values of arguments.
+#[derive(Clone)]
+struct Settings<'a> {
+    /// Definitions of arguments, which keys in the file are validated against
+    app: App<'a, 'a>,
+    matches: ArgMatches<'a>,
+    file: toml::value::Table,
+}
+
+impl<'a> Settings<'a> {
+    fn new(app: App<'a, 'a>, matches: ArgMatches<'a>) -> Result<Self> {
+        let file = match matches.value_of("config") {
+            Some(path) => Self::load_file(path)?,
+            None => toml::value::Table::new(),
+        };
+        Self::validate_file(&app, &file)?;
+        Ok(Settings { app, matches, file })
+    }
+
+    /// Check that every key in the file is a defined argument with a valid value,
+    /// by letting clap parse it as given in command line.
+    fn validate_file(app: &App<'a, 'a>, file: &toml::value::Table) -> Result<()> {
+        for (key, value) in file {
+            match value {
+                toml::Value::Table(table) => {
+                    for (sub_key, value) in table {
+                        Self::validate_value(app, &format!("{}.{}", key, sub_key), value)?;
+                    }
+                }
+                _ => Self::validate_value(app, key, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_value(app: &App<'a, 'a>, name: &str, value: &toml::Value) -> Result<()> {
+        let invalid = |reason: String| InvalidArgError {
+            name: "config",
+            reason: format!("{}: {}", name, reason),
+        };
+        let long = format!("--{}", name.replace('_', "-"));
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let mut args = vec!["phoseum".to_string()];
+        for value in values {
+            match value {
+                // Booleans are given to flags, which take no value
+                toml::Value::Boolean(_) => args.push(long.clone()),
+                value => {
+                    let value =
+                        Self::scalar_string("config", value).map_err(|e| invalid(e.to_string()))?;
+                    // Placeholders left empty would otherwise pass as given values
+                    if value.is_empty() {
+                        return Err(invalid("empty value".to_string()).into());
+                    }
+                    args.push(format!("{}={}", long, value));
+                }
+            }
+        }
+        let result = app
+            .clone()
+            .setting(AppSettings::ColorNever)
+            .get_matches_from_safe(args);
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind == ErrorKind::UnknownArgument => {
+                Err(invalid("unknown key in config file".to_string()).into())
+            }
+            Err(e) => {
+                // First paragraph of the message without the usage
+                let message = e
+                    .message
+                    .split("\n\n")
+                    .next()
+                    .unwrap_or_default()
+                    .trim_start_matches("error: ")
+                    .replace('\n', " ");
+                Err(invalid(message).into())
+            }
+        }
+    }
+
+    fn load_file(path: &str) -> Result<toml::value::Table> {
+        let invalid = |reason: String| InvalidArgError {
+            name: "config",
+            reason,
+        };
+        let content = fs::read_to_string(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            warn!(
+                "Config file {} is accessible by other users, consider restricting it as it may contain secrets",
+                path
+            );
+        }
+        Ok(toml::from_str(&content).map_err(|e| invalid(format!("{}: {}", path, e)))?)
+    }
+
+    fn file_value(&self, name: &str) -> Option<&toml::Value> {
+        match name.find('.') {
+            Some(i) => self.file.get(&name[..i])?.get(&name[i + 1..]),
+            None => self.file.get(name),
+        }
+    }
+
+    /// Return the value in the file unless it's overridden by command line
+    fn file_value_to_use(&self, name: &str) -> Option<&toml::Value> {
+        if self.matches.occurrences_of(name) > 0 {
+            return None;
+        }
+        self.file_value(name)
+    }
+
+    fn scalar_string(name: &'static str, value: &toml::Value) -> Result<String> {
+        match value {
+            toml::Value::String(s) => Ok(s.clone()),
+            toml::Value::Integer(i) => Ok(i.to_string()),
+            toml::Value::Float(f) => Ok(f.to_string()),
+            toml::Value::Boolean(b) => Ok(b.to_string()),
+            _ => Err(InvalidArgError {
+                name,
+                reason: format!("unsupported value in config file: {}", value),
+            }
+            .into()),
+        }
+    }
+
+    fn value_of(&self, name: &'static str) -> Result<Option<String>> {
+        if let Some(value) = self.file_value_to_use(name) {
+            return Self::scalar_string(name, value).map(Some);
+        }
+        Ok(self.matches.value_of(name).map(String::from))
+    }
+
+    fn values_of(&self, name: &'static str) -> Result<Vec<String>> {
+        match self.file_value_to_use(name) {
+            Some(toml::Value::Array(values)) => values
+                .iter()
+                .map(|v| Self::scalar_string(name, v))
+                .collect(),
+            Some(value) => Ok(vec![Self::scalar_string(name, value)?]),
+            None => Ok(self
+                .matches
+                .values_of(name)
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect()),
+        }
+    }
+
+    fn is_present(&self, name: &'static str) -> Result<bool> {
+        match self.file_value_to_use(name) {
+            Some(toml::Value::Boolean(b)) => Ok(*b),
+            Some(_) => Err(InvalidArgError {
+                name,
+                reason: "must be a boolean".to_string(),
+            }
+            .into()),
+            None => Ok(self.matches.is_present(name)),
+        }
+    }
+
+    fn required_value_of(&self, name: &'static str) -> Result<String> {
+        self.value_of(name)?.ok_or_else(|| missing_arg(name))
+    }
+
+    fn required_values_of(&self, name: &'static str) -> Result<Vec<String>> {
+        let values = self.values_of(name)?;
+        if values.is_empty() {
+            return Err(missing_arg(name));
+        }
+        Ok(values)
+    }
+}
+
+fn missing_arg(name: &'static str) -> Error {
+    InvalidArgError {
+        name,
+        reason: "is missing".to_string(),
+    }
+    .into()
+}
+
+fn unknown_value(name: &'static str, value: &str) -> Error {
+    InvalidArgError {
+        name,
+        reason: format!("unknown value: {}", value),
+    }
+    .into()
+}
+
 fn register_for_signal() -> Arc<AtomicBool> {
     let term = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(signal_hook::SIGTERM, Arc::clone(&term)).unwrap();
@@ -37,12 +294,12 @@ fn register_for_signal() -> Arc<AtomicBool> {
     term
 }
 
-fn parse_value<T>(matches: &ArgMatches, name: &'static str) -> Result<Option<T>>
+fn parse_value<T>(settings: &Settings, name: &'static str) -> Result<Option<T>>
 where
     T: FromStr,
     <T as FromStr>::Err: std::fmt::Display,
 {
-    if let Some(val) = matches.value_of(name) {
+    if let Some(val) = settings.value_of(name)? {
         return match val.parse::<T>() {
             Ok(v) => Ok(Some(v)),
             Err(e) => Err(InvalidArgError {
@@ -55,55 +312,172 @@ where
     Ok(None)
 }
 
-fn create_album(matches: &ArgMatches) -> GPhotosAlbum {
-    let album_id = matches.value_of("googlephotos.album_id").expect("album_id");
-    let client_id = matches
-        .value_of("googlephotos.oauth_client_id")
-        .expect("oauth id");
-    let client_secret = matches
-        .value_of("googlephotos.oauth_client_secret")
-        .expect("oauth secret");
-    let auth_config = googlephotos::api::auth_config(client_id, client_secret);
+fn create_gphotos_albums(settings: &Settings) -> Result<Vec<GPhotosAlbum>> {
+    let album_ids = settings.required_values_of("googlephotos.album_id")?;
+    let client_id = settings.required_value_of("googlephotos.oauth_client_id")?;
+    let client_secret = settings.required_value_of("googlephotos.oauth_client_secret")?;
+    let auth_config = googlephotos::api::auth_config(&client_id, &client_secret);
     let tokens = TokenService::new(auth_config).expect("error loading token servie");
 
-    googlephotos::new_gphotos_album(album_id, tokens)
+    Ok(googlephotos::new_gphotos_albums(album_ids, tokens))
+}
+
+fn create_local_albums(settings: &Settings) -> Result<Vec<LocalAlbum>> {
+    let hard_link = !settings.is_present("local.no_hard_link")?;
+    Ok(settings
+        .required_values_of("local.dir")?
+        .into_iter()
+        .map(|dir| LocalAlbum::new(dir).hard_link(hard_link))
+        .collect())
 }
 
-fn create_pl_builder(matches: &ArgMatches) -> Result<playlist::PlaylistBuilder> {
+fn create_pl_builder<I: AlbumItem + 'static>(
+    settings: &Settings,
+) -> Result<playlist::PlaylistBuilder<I>> {
     let mut builder = playlist::PlaylistBuilder::new();
-    if let Some(min_size) = parse_value(matches, "playlist.min_size")? {
+    if let Some(min_size) = parse_value(settings, "playlist.min_size")? {
         builder = builder.min_size(min_size);
     }
-    if let Some(max_size) = parse_value(matches, "playlist.max_size")? {
+    if let Some(max_size) = parse_value(settings, "playlist.max_size")? {
         builder = builder.max_size(max_size);
     }
-    if let Some(fresh_retention) = parse_value(matches, "playlist.fresh_retention")? {
+    if let Some(fresh_retention) = parse_value(settings, "playlist.fresh_retention")? {
         builder = builder.fresh_retention(Duration::from_secs(fresh_retention));
     }
+    if let Some(window_days) = parse_value(settings, "playlist.on_this_day_window")? {
+        let max_items = parse_value(settings, "playlist.on_this_day_max_items")?
+            .expect("playlist.on_this_day_max_items");
+        builder = builder.on_this_day(window_days, max_items);
+    }
+    if let Some(max_videos) = parse_value(settings, "playlist.max_videos")? {
+        builder = builder.max_videos(max_videos);
+    }
+    if let Some(fraction) = parse_value::<f64>(settings, "playlist.max_video_fraction")? {
+        if fraction < 0.0 || fraction > 1.0 {
+            return Err(InvalidArgError {
+                name: "playlist.max_video_fraction",
+                reason: "value must be in range between 0.0 and 1.0".to_string(),
+            }
+            .into());
+        }
+        builder = builder.max_video_fraction(fraction);
+    }
+    if let Some(budget) = parse_value(settings, "playlist.video_duration_budget")? {
+        builder = builder.video_duration_budget(Duration::from_secs(budget));
+    }
+    if let Some(duration) = parse_value(settings, "playlist.default_video_duration")? {
+        builder = builder.default_video_duration(Duration::from_secs(duration));
+    }
+    if let Some(seed) = parse_value(settings, "playlist.seed")? {
+        builder = builder.seed(seed);
+    }
+    for stage in settings.values_of("playlist.selectors")? {
+        builder = parse_selector_stage(builder, &stage)?;
+    }
     Ok(builder)
 }
 
-fn create_player(matches: &ArgMatches) -> Result<VlcPlayer> {
-    let http_port = parse_value(matches, "vlc.http_port")?;
-    let vlc_bin = matches.value_of("vlc.bin").map(String::from);
+/// Append a stage of the selector pipeline given in the format of NAME[:QUOTA]
+fn parse_selector_stage<I: AlbumItem + 'static>(
+    builder: playlist::PlaylistBuilder<I>,
+    stage: &str,
+) -> Result<playlist::PlaylistBuilder<I>> {
+    let invalid = |reason: String| InvalidArgError {
+        name: "playlist.selectors",
+        reason,
+    };
+    let mut parts = stage.splitn(2, ':');
+    let name = parts.next().expect("selector name");
+    let quota = match parts.next() {
+        Some(quota) => Some(
+            quota
+                .parse()
+                .map_err(|e| invalid(format!("invalid quota of {}: {}", stage, e)))?,
+        ),
+        None => None,
+    };
+    builder.builtin_selector(name, quota).ok_or_else(|| {
+        invalid(format!(
+            "unknown selector {}, must be one of {}",
+            name,
+            playlist::selector::BUILTIN_NAMES.join(", ")
+        ))
+        .into()
+    })
+}
+
+fn create_vlc_player(settings: &Settings) -> Result<VlcPlayer> {
+    let http_port = parse_value(settings, "vlc.http_port")?;
+    let vlc_bin = settings.value_of("vlc.bin")?;
 
     Ok(VlcPlayer::new(VlcConfig { http_port, vlc_bin }))
 }
 
-fn create_storage(matches: &ArgMatches) -> Result<Storage> {
-    let media_dir = matches
-        .value_of("storage.media_dir")
-        .expect("storage.media_dir");
-    let capacity: u64 = parse_value(matches, "storage.capacity")?.expect("storage.capacity");
-    Ok(Storage::open(media_dir, capacity)?)
+fn create_mpv_player(settings: &Settings) -> Result<MpvPlayer> {
+    let mpv_bin = settings.value_of("mpv.bin")?;
+    let ipc_socket = settings.value_of("mpv.ipc_socket")?.map(PathBuf::from);
+
+    Ok(MpvPlayer::new(MpvConfig {
+        mpv_bin,
+        ipc_socket,
+    }))
+}
+
+/// Create display power control if enabled
+fn create_display_power(settings: &Settings) -> Result<Option<Box<dyn DisplayPower + Send>>> {
+    let display: Box<dyn DisplayPower + Send> =
+        match settings.required_value_of("display.power")?.as_str() {
+            "none" => return Ok(None),
+            "command" => Box::new(CommandDisplay::new(
+                settings.required_value_of("display.on_cmd")?,
+                settings.required_value_of("display.off_cmd")?,
+            )),
+            "backlight" => Box::new(BacklightDisplay::new(
+                settings.required_value_of("display.backlight")?,
+            )),
+            "dpms" => {
+                let mut dpms = DpmsDisplay::default();
+                if let Some(xset_bin) = settings.value_of("display.xset_bin")? {
+                    dpms = dpms.xset_bin(xset_bin);
+                }
+                Box::new(dpms)
+            }
+            unknown => return Err(unknown_value("display.power", unknown)),
+        };
+    Ok(Some(display))
+}
+
+fn create_storage(settings: &Settings) -> Result<Storage> {
+    let media_dir = settings.required_value_of("storage.media_dir")?;
+    let capacity: u64 = parse_value(settings, "storage.capacity")?.expect("storage.capacity");
+    let eviction = parse_value(settings, "storage.eviction")?.expect("storage.eviction");
+    Ok(Storage::open(media_dir, capacity)?.eviction_policy(eviction))
 }
 
-fn create_slideshow_config(matches: &ArgMatches) -> Result<SlideshowConfig> {
+fn create_prefetch_config(settings: &Settings) -> Result<PrefetchConfig> {
+    let mut conf = PrefetchConfig::default();
+    if let Some(workers) = parse_value(settings, "prefetch.workers")? {
+        if workers == 0 {
+            return Err(InvalidArgError {
+                name: "prefetch.workers",
+                reason: "must be greater than 0".to_string(),
+            }
+            .into());
+        }
+        conf.workers = workers;
+    }
+    if let Some(initial_items) = parse_value(settings, "prefetch.initial_items")? {
+        conf.initial_items = initial_items;
+    }
+    Ok(conf)
+}
+
+fn create_slideshow_config(settings: &Settings) -> Result<SlideshowConfig> {
     let mut conf = SlideshowConfig::default();
-    if let Some(seconds) = parse_value(matches, "slideshow.show_duration")? {
+    if let Some(seconds) = parse_value(settings, "slideshow.show_duration")? {
         conf.show_duration = Duration::from_secs(seconds);
     }
-    if let Some(volume) = parse_value::<f32>(matches, "slideshow.audio_volume")? {
+    if let Some(volume) = parse_value::<f32>(settings, "slideshow.audio_volume")? {
         if volume < 0.0 || volume > 1.0 {
             return Err(InvalidArgError {
                 name: "slideshow.audio_volume",
@@ -113,10 +487,35 @@ fn create_slideshow_config(matches: &ArgMatches) -> Result<SlideshowConfig> {
         }
         conf.audio_volume = volume;
     }
-    if matches.is_present("slideshow.no-fullscreen") {
+    if settings.is_present("slideshow.no_fullscreen")? {
         conf.fullscreen = false;
     }
+    if settings.is_present("slideshow.caption")? {
+        conf.caption = Some(create_caption_config(settings)?);
+    }
+
+    Ok(conf)
+}
 
+fn create_caption_config(settings: &Settings) -> Result<CaptionConfig> {
+    let mut conf = CaptionConfig::default();
+    if let Some(position) = parse_value(settings, "slideshow.caption_position")? {
+        conf.position = position;
+    }
+    if let Some(format) = settings.value_of("slideshow.caption_format")? {
+        conf.format = format;
+    }
+    if let Some(date_format) = settings.value_of("slideshow.caption_date_format")? {
+        let invalid = StrftimeItems::new(&date_format).any(|item| item == Item::Error);
+        if invalid {
+            return Err(InvalidArgError {
+                name: "slideshow.caption_date_format",
+                reason: format!("invalid strftime format: {}", date_format),
+            }
+            .into());
+        }
+        conf.date_format = date_format;
+    }
     Ok(conf)
 }
 
@@ -135,74 +534,291 @@ fn parse_pin_state(s: &str) -> Result<bool> {
     }
 }
 
-fn create_gpio_commander(matches: &ArgMatches) -> Result<gpio_control::GpioCommander> {
+fn parse_gpio_mapping(settings: &Settings) -> Result<Vec<gpio_control::PinMap>> {
+    let invalid = |reason: String| InvalidArgError {
+        name: "control.gpio_map",
+        reason,
+    };
     let mut pin_mapping = Vec::new();
-    for map in matches.values_of("control.gpio_map").into_iter().flatten() {
-        match map.splitn(4, ':').collect::<Vec<_>>().as_slice() {
-            [offset, high_low, cmd_name, default] => {
-                let offset = offset.parse::<u32>().map_err(|e| InvalidArgError {
-                    name: "control.gpio_map",
-                    reason: e.to_string(),
-                })?;
-                let edge_high = parse_pin_state(high_low)?;
-                let cmd = PlayerCmd::from_name(cmd_name).ok_or_else(|| InvalidArgError {
-                    name: "control.gpio_map",
-                    reason: format!("no such command: {}", cmd_name),
-                })?;
-                let default_state = parse_pin_state(default)?;
-                pin_mapping.push(gpio_control::PinMap::new(
-                    offset,
-                    edge_high,
-                    default_state,
-                    cmd,
-                ));
+    for map in settings.values_of("control.gpio_map")? {
+        let fields: Vec<_> = map.split(':').collect();
+        let (offset, high_low, cmd_name, default, options) = match fields.as_slice() {
+            [offset, high_low, cmd_name, default, options @ ..] if options.len() <= 2 => {
+                (offset, high_low, cmd_name, default, options)
             }
             _ => {
-                return Err(InvalidArgError {
-                    name: "control.gpio_map",
-                    reason: "not in form of OFFSET:[HL]:COMMAND:[HL]".to_string(),
-                }
+                return Err(invalid(
+                    "not in form of OFFSET:[HL]:COMMAND:[HL][:GESTURE[:DEBOUNCE_MS]]".to_string(),
+                )
                 .into())
             }
+        };
+        let offset = offset.parse::<u32>().map_err(|e| invalid(e.to_string()))?;
+        let edge_high = parse_pin_state(high_low)?;
+        let cmd = Command::from_name(cmd_name)
+            .ok_or_else(|| invalid(format!("no such command: {}", cmd_name)))?;
+        let default_state = parse_pin_state(default)?;
+        let mut pin_map = gpio_control::PinMap::new(offset, edge_high, default_state, cmd);
+        if let Some(gesture) = options.first() {
+            pin_map = pin_map.gesture(
+                gpio_control::Gesture::from_name(gesture)
+                    .ok_or_else(|| invalid(format!("no such gesture: {}", gesture)))?,
+            );
+        }
+        if let Some(debounce) = options.get(1) {
+            let millis = debounce
+                .parse::<u64>()
+                .map_err(|e| invalid(e.to_string()))?;
+            pin_map = pin_map.debounce(Duration::from_millis(millis));
+        }
+        pin_mapping.push(pin_map);
+    }
+    Ok(pin_mapping)
+}
+
+fn create_gpio_commander(settings: &Settings) -> Result<gpio_control::GpioCommander> {
+    let pin_mapping = parse_gpio_mapping(settings)?;
+    let gpio_dev = settings.required_value_of("control.gpio_dev")?;
+    Ok(gpio_control::GpioCommander::create(&gpio_dev, pin_mapping)?)
+}
+
+/// Create presence commander if the motion sensor's pin is given
+fn create_presence_commander(
+    settings: &Settings,
+    schedules: Vec<(Calendar, PlayerCmd)>,
+) -> Result<Option<PresenceCommander>> {
+    let offset = match parse_value(settings, "presence.gpio_offset")? {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+    let active_high = parse_pin_state(&settings.required_value_of("presence.active_level")?)?;
+    let gpio_dev = settings.required_value_of("control.gpio_dev")?;
+    let mut config = PresenceConfig::default();
+    if let Some(seconds) = parse_value(settings, "presence.idle_timeout")? {
+        config.idle_timeout = Duration::from_secs(seconds);
+    }
+    config.wake_during_sleep = settings.is_present("presence.wake_during_sleep")?;
+
+    let has_schedule = |f: fn(&PlayerCmd) -> bool| schedules.iter().any(|(_, cmd)| f(cmd));
+    if !has_schedule(|cmd| matches!(cmd, PlayerCmd::Sleep))
+        || !has_schedule(|cmd| matches!(cmd, PlayerCmd::Wakeup))
+    {
+        warn!(
+            "presence.gpio_offset is set without schedule.sleep and schedule.wakeup; \
+             motion will wake the slideshow at any time of day"
+        );
+    }
+
+    let lines = gpio_control::open_lines(&gpio_dev, vec![offset])?;
+    Ok(Some(
+        PresenceCommander::new(lines, active_high, config).schedules(schedules),
+    ))
+}
+
+fn create_restart_policy(settings: &Settings) -> Result<RestartPolicy> {
+    let mut policy = RestartPolicy::default();
+    if let Some(max_restarts) = parse_value(settings, "player.max_restarts")? {
+        policy.max_restarts = max_restarts;
+    }
+    Ok(policy)
+}
+
+fn create_http_commander(settings: &Settings) -> Result<http_control::HttpCommander> {
+    let http_port: u32 = parse_value(settings, "control.http_port")?.expect("control.http_port");
+    let http_host = settings
+        .value_of("control.http_host")?
+        .expect("control.http_host");
+    Ok(http_control::HttpCommander::new(http_port).host(&http_host))
+}
+
+/// Create MQTT commander if broker host is given
+fn create_mqtt_commander(settings: &Settings) -> Result<Option<MqttCommander>> {
+    let host = match settings.value_of("mqtt.host")? {
+        Some(host) => host,
+        None => return Ok(None),
+    };
+    let port = parse_value(settings, "mqtt.port")?.expect("mqtt.port");
+    let node_id = settings.required_value_of("mqtt.node_id")?;
+    let discovery_prefix = settings.required_value_of("mqtt.discovery_prefix")?;
+    Ok(Some(
+        MqttCommander::new(&host, port, &node_id)
+            .credentials(
+                settings.value_of("mqtt.username")?,
+                settings.value_of("mqtt.password")?,
+            )
+            .discovery_prefix(&discovery_prefix),
+    ))
+}
+
+/// Parse calendars given to each argument into pairs of calendar and the command to send
+fn parse_schedules<C: Copy>(
+    settings: &Settings,
+    args: &[(&'static str, C)],
+) -> Result<Vec<(Calendar, C)>> {
+    let mut schedules = Vec::new();
+    for (name, cmd) in args {
+        for expr in settings.values_of(name)? {
+            let calendar = Calendar::from_str(&expr).map_err(|e| InvalidArgError {
+                name,
+                reason: e.to_string(),
+            })?;
+            schedules.push((calendar, *cmd));
+        }
+    }
+    Ok(schedules)
+}
+
+/// Return names of arguments requiring restart whose values differ between settings
+fn changed_settings(old: &Settings, new: &Settings) -> Result<Vec<String>> {
+    let mut changed = Vec::new();
+    for name in RESTART_REQUIRED_ARGS {
+        if old.values_of(name)? != new.values_of(name)? {
+            changed.push(name.to_string());
+        }
+    }
+    Ok(changed)
+}
+
+/// Create a function which reads the configuration file again and returns
+/// settings to apply to the running slideshow.
+///
+/// GPIO mapping is applied directly through the handle if it's given.
+fn create_reloader<I: AlbumItem + 'static>(
+    initial: Settings<'static>,
+    gpio_mapping: Option<gpio_control::PinMappingHandle>,
+) -> impl Fn() -> Result<ReloadConfig<I>> {
+    move || {
+        let settings = Settings::new(initial.app.clone(), initial.matches.clone())?;
+        let mut restart_required = changed_settings(&initial, &settings)?;
+        let pl_builder = create_pl_builder(&settings)?;
+        let slideshow = create_slideshow_config(&settings)?;
+        let prefetch = create_prefetch_config(&settings)?;
+        // Applied last as it takes effect immediately, so a rejected reload changes nothing
+        if let Some(handle) = &gpio_mapping {
+            if !handle.update(parse_gpio_mapping(&settings)?) {
+                restart_required.push("control.gpio_map".to_string());
+            }
         }
+        Ok(ReloadConfig {
+            pl_builder,
+            slideshow,
+            prefetch,
+            restart_required,
+        })
+    }
+}
+
+fn run(app: App<'static, 'static>, matches: ArgMatches<'static>) -> Result<()> {
+    let settings = Settings::new(app, matches)?;
+    match settings.required_value_of("album")?.as_str() {
+        "googlephotos" => run_albums(&settings, create_gphotos_albums(&settings)?),
+        "local" => run_albums(&settings, create_local_albums(&settings)?),
+        unknown => Err(unknown_value("album", unknown)),
+    }
+}
+
+/// Run slideshow for the album, merging them into one if multiple albums are given.
+fn run_albums<A: Album + 'static>(settings: &Settings<'static>, mut albums: Vec<A>) -> Result<()> {
+    if albums.len() == 1 {
+        return run_slideshow(settings, albums.pop().expect("album"));
     }
+    // Albums are namespaced by their position, so reordering them in arguments
+    // results in downloading all items again.
+    let composite = albums
+        .into_iter()
+        .enumerate()
+        .fold(CompositeAlbum::new(), |composite, (i, album)| {
+            composite.add(i.to_string(), album)
+        });
+    run_slideshow(settings, composite)
+}
 
-    let gpio_dev = matches
-        .value_of("control.gpio_dev")
-        .ok_or_else(|| InvalidArgError {
-            name: "control.gpio_dev",
-            reason: "is missing".to_string(),
-        })?;
-    Ok(gpio_control::GpioCommander::create(gpio_dev, pin_mapping)?)
+fn run_slideshow<A: Album + 'static>(settings: &Settings<'static>, album: A) -> Result<()> {
+    match settings.required_value_of("player")?.as_str() {
+        "vlc" => run_player(settings, create_vlc_player(settings)?, album),
+        "mpv" => run_player(settings, create_mpv_player(settings)?, album),
+        unknown => Err(unknown_value("player", unknown)),
+    }
 }
 
-fn create_http_commander(matches: &ArgMatches) -> Result<http_control::HttpCommander> {
-    let http_port: u32 = parse_value(matches, "control.http_port")?.expect("control.http_port");
-    Ok(http_control::HttpCommander::new(http_port))
+/// Run the player, turning the display off while sleeping if configured.
+fn run_player<P, A>(settings: &Settings<'static>, player: P, album: A) -> Result<()>
+where
+    P: Player + Send + 'static,
+    A: Album + 'static,
+{
+    match create_display_power(settings)? {
+        Some(display) => run_app(settings, DisplayPlayer::new(player, display), album),
+        None => run_app(settings, player, album),
+    }
 }
 
-fn run(matches: ArgMatches<'_>) -> Result<()> {
+fn run_app<P, A>(settings: &Settings<'static>, player: P, album: A) -> Result<()>
+where
+    P: Player + Send + 'static,
+    A: Album + 'static,
+{
+    // GPIO commander is created first to reload its mapping, None means console
+    let gpio_commander = match settings.required_value_of("control.player")?.as_str() {
+        "gpio" => Some(create_gpio_commander(settings)?),
+        "console" => None,
+        unknown => return Err(unknown_value("control.player", unknown)),
+    };
+    let gpio_mapping = gpio_commander.as_ref().map(|c| c.mapping_handle());
+
     let slideshow = Slideshow::new(
-        create_album(&matches),
-        create_player(&matches)?,
-        create_pl_builder(&matches)?,
-        create_storage(&matches)?,
-        create_slideshow_config(&matches)?,
-    );
-
-    let mut app = Phoseum::new(slideshow);
-    let http_commander = create_http_commander(&matches)?;
+        album,
+        player,
+        create_pl_builder(settings)?,
+        create_storage(settings)?,
+        create_slideshow_config(settings)?,
+    )
+    .prefetch_config(create_prefetch_config(settings)?)
+    .reloader(create_reloader(settings.clone(), gpio_mapping));
+
+    let http_commander = create_http_commander(settings)?.status_source(slideshow.status_handle());
+    let mqtt_commander = create_mqtt_commander(settings)?
+        .map(|commander| commander.status_source(slideshow.status_handle()));
+    let mut app = Phoseum::new(slideshow).restart_policy(create_restart_policy(settings)?);
     app.add_player_commander(http_commander.clone());
     app.add_playlist_commander(http_commander);
-    match matches.value_of("control.player").expect("control.player") {
-        "gpio" => {
-            app.add_player_commander(create_gpio_commander(&matches)?);
-        }
-        "console" => {
-            app.add_player_commander(console_control::ConsoleCommander::default());
+    if let Some(commander) = mqtt_commander {
+        app.add_player_commander(commander.clone());
+        app.add_playlist_commander(commander);
+    }
+    match gpio_commander {
+        Some(commander) => {
+            app.add_player_commander(commander.clone());
+            app.add_playlist_commander(commander);
         }
-        unknown => panic!("unknown player control: {}", unknown),
-    };
+        None => app.add_player_commander(console_control::ConsoleCommander::default()),
+    }
+    app.add_playlist_commander(SignalCommander::new()?);
+
+    let player_schedules = parse_schedules(
+        settings,
+        &[
+            ("schedule.sleep", PlayerCmd::Sleep),
+            ("schedule.wakeup", PlayerCmd::Wakeup),
+        ],
+    )?;
+    if let Some(commander) = create_presence_commander(settings, player_schedules.clone())? {
+        app.add_player_commander(commander);
+    }
+    if !player_schedules.is_empty() {
+        // Start in the state of the last event, e.g. sleeping if launched at night
+        app.add_player_commander(ScheduleCommander::new(player_schedules).catch_up(true));
+    }
+    let playlist_schedules = parse_schedules(
+        settings,
+        &[
+            ("schedule.update", PlaylistCmd::Update),
+            ("schedule.refresh", PlaylistCmd::Refresh),
+        ],
+    )?;
+    if !playlist_schedules.is_empty() {
+        app.add_playlist_commander(ScheduleCommander::new(playlist_schedules));
+    }
 
     let terminate = register_for_signal();
     app.run(terminate)?;
@@ -212,12 +828,17 @@ fn run(matches: ArgMatches<'_>) -> Result<()> {
 fn main() {
     env_logger::init();
 
-    let matches = App::new("Photo Museum")
+    let app = App::new("Photo Museum")
         .version("0.1")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("Path to TOML file to read settings from. Argument section.key is read from key in [section]. Command line arguments take precedence"),
+        )
         .arg(
             Arg::with_name("storage.media_dir")
                 .long("storage.media-dir")
-                .required(true)
                 .takes_value(true)
                 .help("Path to directory that used as local storage of media files"),
         )
@@ -228,27 +849,53 @@ fn main() {
                 .default_value("10737418240")
                 .help("Size in bytes to limit total size of files kept in local filesystem"),
         )
+        .arg(
+            Arg::with_name("storage.eviction")
+                .long("storage.eviction")
+                .takes_value(true)
+                .possible_values(&["smallest-first", "lru", "lfu"])
+                .default_value("smallest-first")
+                .help("Policy to choose files to evict when local storage is full"),
+        )
+        .arg(
+            Arg::with_name("album")
+                .long("album")
+                .takes_value(true)
+                .possible_values(&["googlephotos", "local"])
+                .default_value("googlephotos")
+                .help("Source of photos and videos to show"),
+        )
         .arg(
             Arg::with_name("googlephotos.album_id")
                 .long("googlephotos.album-id")
-                .required(true)
                 .takes_value(true)
-                .help("Album ID of Google Photos"),
+                .multiple(true)
+                .help("Album ID of Google Photos. Items of all albums are merged when specified multiple times"),
         )
         .arg(
             Arg::with_name("googlephotos.oauth_client_id")
                 .long("googlephotos.oauth-client-id")
-                .required(true)
                 .takes_value(true)
                 .help("OAuth client ID to access API"),
         )
         .arg(
             Arg::with_name("googlephotos.oauth_client_secret")
                 .long("googlephotos.oauth-client-secret")
-                .required(true)
                 .takes_value(true)
                 .help("OAuth client secret to access API"),
         )
+        .arg(
+            Arg::with_name("local.dir")
+                .long("local.dir")
+                .takes_value(true)
+                .multiple(true)
+                .help("Path to directory containing photos and videos, scanned recursively. Items of all directories are merged when specified multiple times"),
+        )
+        .arg(
+            Arg::with_name("local.no_hard_link")
+                .long("local.no-hard-link")
+                .help("Always copy files into the storage instead of hard-linking them"),
+        )
         .arg(
             Arg::with_name("playlist.min_size")
                 .long("playlist.min-size")
@@ -273,6 +920,69 @@ fn main() {
                     "Retention in seconds to decide whether an item is new or not. Items created since this retention ago are considered as fresh",
                 ),
         )
+        .arg(
+            Arg::with_name("playlist.on_this_day_window")
+                .long("playlist.on-this-day-window")
+                .takes_value(true)
+                .help("Window in days around today's date to pick up items created on this day in previous years, before old items. Disabled if not given"),
+        )
+        .arg(
+            Arg::with_name("playlist.on_this_day_max_items")
+                .long("playlist.on-this-day-max-items")
+                .takes_value(true)
+                .default_value("10")
+                .help("Maximum number of items created on this day in previous years to put in the playlist"),
+        )
+        .arg(
+            Arg::with_name("playlist.max_videos")
+                .long("playlist.max-videos")
+                .takes_value(true)
+                .help("Maximum number of videos in the playlist"),
+        )
+        .arg(
+            Arg::with_name("playlist.max_video_fraction")
+                .long("playlist.max-video-fraction")
+                .takes_value(true)
+                .help("Maximum fraction of videos in the playlist expressed as value between 0.0 and 1.0"),
+        )
+        .arg(
+            Arg::with_name("playlist.video_duration_budget")
+                .long("playlist.video-duration-budget")
+                .takes_value(true)
+                .help("Maximum total duration in seconds of videos in the playlist"),
+        )
+        .arg(
+            Arg::with_name("playlist.default_video_duration")
+                .long("playlist.default-video-duration")
+                .takes_value(true)
+                .help("Duration in seconds counted against the video budget for videos of unknown duration, 60 by default"),
+        )
+        .arg(
+            Arg::with_name("playlist.selectors")
+                .long("playlist.selectors")
+                .takes_value(true)
+                .multiple(true)
+                .help("Pipeline of selectors choosing playlist items, in order of priority. Format: NAME[:QUOTA], NAME is one of fresh, on_this_day or old. Defaults to fresh, on_this_day if enabled, then old"),
+        )
+        .arg(
+            Arg::with_name("playlist.seed")
+                .long("playlist.seed")
+                .takes_value(true)
+                .default_value("random")
+                .help("Seed of randomness in playlists: random, daily to keep the playlist of the day across restarts, or an integer to reproduce a playlist. The seed used is logged"),
+        )
+        .arg(
+            Arg::with_name("prefetch.workers")
+                .long("prefetch.workers")
+                .takes_value(true)
+                .help("Number of items to download concurrently"),
+        )
+        .arg(
+            Arg::with_name("prefetch.initial_items")
+                .long("prefetch.initial-items")
+                .takes_value(true)
+                .help("Number of downloaded items to start playing new playlist with. The rest are appended as they become ready"),
+        )
         .arg(
             Arg::with_name("slideshow.show_duration")
                 .long("slideshow.show-duration")
@@ -290,6 +1000,45 @@ fn main() {
                 .long("slideshow.no-fullscreen")
                 .help("Turn off fullscreen (debug)"),
         )
+        .arg(
+            Arg::with_name("slideshow.caption")
+                .long("slideshow.caption")
+                .help("Show caption of the item being played, such as the date it was taken"),
+        )
+        .arg(
+            Arg::with_name("slideshow.caption_position")
+                .long("slideshow.caption-position")
+                .takes_value(true)
+                .possible_values(&["top-left", "top", "top-right", "bottom-left", "bottom", "bottom-right"])
+                .default_value("bottom-left")
+                .help("Position of the caption on screen"),
+        )
+        .arg(
+            Arg::with_name("slideshow.caption_format")
+                .long("slideshow.caption-format")
+                .takes_value(true)
+                .help("Text of the caption, in which {date}, {description} and {filename} are replaced by those of the item"),
+        )
+        .arg(
+            Arg::with_name("slideshow.caption_date_format")
+                .long("slideshow.caption-date-format")
+                .takes_value(true)
+                .help("Format of {date} in caption, in strftime syntax such as %Y-%m-%d"),
+        )
+        .arg(
+            Arg::with_name("player")
+                .long("player")
+                .takes_value(true)
+                .possible_values(&["vlc", "mpv"])
+                .default_value("vlc")
+                .help("Media player to run slideshow with"),
+        )
+        .arg(
+            Arg::with_name("player.max_restarts")
+                .long("player.max-restarts")
+                .takes_value(true)
+                .help("Number of consecutive restarts of the player to attempt before giving up when it stops working. 0 to exit immediately"),
+        )
         .arg(
             Arg::with_name("vlc.http_port")
                 .long("vlc.http-port")
@@ -302,6 +1051,50 @@ fn main() {
                 .takes_value(true)
                 .help("VLC player executable path"),
         )
+        .arg(
+            Arg::with_name("mpv.bin")
+                .long("mpv.bin")
+                .takes_value(true)
+                .help("mpv player executable path"),
+        )
+        .arg(
+            Arg::with_name("mpv.ipc_socket")
+                .long("mpv.ipc-socket")
+                .takes_value(true)
+                .help("Path to Unix socket for mpv player to listen for controlling it"),
+        )
+        .arg(
+            Arg::with_name("display.power")
+                .long("display.power")
+                .takes_value(true)
+                .possible_values(&["none", "command", "backlight", "dpms"])
+                .default_value("none")
+                .help("How to turn the display off while sleeping. command runs display.on-cmd/off-cmd, backlight writes to sysfs and dpms runs xset"),
+        )
+        .arg(
+            Arg::with_name("display.on_cmd")
+                .long("display.on-cmd")
+                .takes_value(true)
+                .help("Shell command to turn the display on e.g. '/usr/bin/tvservice --preferred'"),
+        )
+        .arg(
+            Arg::with_name("display.off_cmd")
+                .long("display.off-cmd")
+                .takes_value(true)
+                .help("Shell command to turn the display off e.g. '/usr/bin/tvservice --off'"),
+        )
+        .arg(
+            Arg::with_name("display.backlight")
+                .long("display.backlight")
+                .takes_value(true)
+                .help("Path to sysfs directory of the backlight e.g. /sys/class/backlight/rpi_backlight"),
+        )
+        .arg(
+            Arg::with_name("display.xset_bin")
+                .long("display.xset-bin")
+                .takes_value(true)
+                .help("Path to xset binary for dpms"),
+        )
         .arg(
             Arg::with_name("control.player")
                 .long("control.player")
@@ -321,7 +1114,7 @@ fn main() {
                 .long("control.gpio-map")
                 .takes_value(true)
                 .multiple(true)
-                .help("Mapping from each pin's state to command to produce. Format: PIN_OFFSET:[HL]:COMMAND:[HL](default)"),
+                .help("Mapping from each pin's state to command to produce. Format: PIN_OFFSET:[HL]:COMMAND:[HL](default)[:GESTURE[:DEBOUNCE_MS]]. GESTURE is one of edge(default), short, long or double, for which [HL] is the state while pressed"),
         )
         .arg(
             Arg::with_name("control.http_port")
@@ -330,9 +1123,109 @@ fn main() {
                 .default_value("8000")
                 .help("HTTP port to listen and expose playlist controlling API"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("control.http_host")
+                .long("control.http-host")
+                .takes_value(true)
+                .default_value("localhost")
+                .help("Host to listen HTTP on. Set 0.0.0.0 to open the web UI to other devices in the network"),
+        )
+        .arg(
+            Arg::with_name("mqtt.host")
+                .long("mqtt.host")
+                .takes_value(true)
+                .help("Host of MQTT broker to receive commands and publish states. MQTT is disabled if not given"),
+        )
+        .arg(
+            Arg::with_name("mqtt.port")
+                .long("mqtt.port")
+                .takes_value(true)
+                .default_value("1883")
+                .help("Port of MQTT broker"),
+        )
+        .arg(
+            Arg::with_name("mqtt.username")
+                .long("mqtt.username")
+                .takes_value(true)
+                .help("Username to authenticate to MQTT broker"),
+        )
+        .arg(
+            Arg::with_name("mqtt.password")
+                .long("mqtt.password")
+                .takes_value(true)
+                .help("Password to authenticate to MQTT broker"),
+        )
+        .arg(
+            Arg::with_name("mqtt.node_id")
+                .long("mqtt.node-id")
+                .takes_value(true)
+                .default_value("phoseum")
+                .help("ID to distinguish this slideshow in MQTT topics, phoseum/<node_id>/..."),
+        )
+        .arg(
+            Arg::with_name("mqtt.discovery_prefix")
+                .long("mqtt.discovery-prefix")
+                .takes_value(true)
+                .default_value("homeassistant")
+                .help("Topic prefix to publish Home Assistant discovery configs"),
+        )
+        .arg(
+            Arg::with_name("presence.gpio_offset")
+                .long("presence.gpio-offset")
+                .takes_value(true)
+                .help("GPIO line offset of the motion sensor to sleep while nobody is around. Read from the device given by control.gpio-dev"),
+        )
+        .arg(
+            Arg::with_name("presence.active_level")
+                .long("presence.active-level")
+                .takes_value(true)
+                .possible_values(&["H", "L"])
+                .default_value("H")
+                .help("State of the motion sensor's pin while detecting motion"),
+        )
+        .arg(
+            Arg::with_name("presence.idle_timeout")
+                .long("presence.idle-timeout")
+                .takes_value(true)
+                .default_value("600")
+                .help("Seconds without motion to enter sleep mode"),
+        )
+        .arg(
+            Arg::with_name("presence.wake_during_sleep")
+                .long("presence.wake-during-sleep")
+                .help("Back from sleep mode by motion even during scheduled sleep"),
+        )
+        .arg(
+            Arg::with_name("schedule.sleep")
+                .long("schedule.sleep")
+                .takes_value(true)
+                .multiple(true)
+                .help("Calendar event to enter sleep mode, in systemd's calendar event syntax e.g. 'Mon..Fri 23:00'"),
+        )
+        .arg(
+            Arg::with_name("schedule.wakeup")
+                .long("schedule.wakeup")
+                .takes_value(true)
+                .multiple(true)
+                .help("Calendar event to back from sleep mode"),
+        )
+        .arg(
+            Arg::with_name("schedule.update")
+                .long("schedule.update")
+                .takes_value(true)
+                .multiple(true)
+                .help("Calendar event to check and add new items in album into the playlist"),
+        )
+        .arg(
+            Arg::with_name("schedule.refresh")
+                .long("schedule.refresh")
+                .takes_value(true)
+                .multiple(true)
+                .help("Calendar event to regenerate playlist"),
+        );
+    let matches = app.clone().get_matches();
 
-    if let Err(e) = run(matches) {
+    if let Err(e) = run(app, matches) {
         if let Some(e) = e.downcast_ref::<InvalidArgError>() {
             eprintln!("Invalid argument {} - {}", e.name, e.reason);
         } else {
@@ -341,3 +1234,218 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App<'static, 'static> {
+        App::new("test")
+            .arg(
+                Arg::with_name("storage.capacity")
+                    .long("storage.capacity")
+                    .takes_value(true)
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::with_name("control.gpio_map")
+                    .long("control.gpio-map")
+                    .takes_value(true)
+                    .multiple(true),
+            )
+            .arg(Arg::with_name("slideshow.no_fullscreen").long("slideshow.no-fullscreen"))
+            .arg(
+                Arg::with_name("player")
+                    .long("player")
+                    .takes_value(true)
+                    .possible_values(&["vlc", "mpv"]),
+            )
+    }
+
+    fn settings(args: &[&str], file: &str) -> Settings<'static> {
+        let app = app();
+        let matches = app
+            .clone()
+            .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+        Settings {
+            app,
+            matches,
+            file: toml::from_str(file).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_settings_precedence() {
+        let file = r#"
+            player = "mpv"
+            [storage]
+            capacity = 20
+            [slideshow]
+            no_fullscreen = true
+        "#;
+
+        // Values in file take precedence over defaults
+        let s = settings(&[], file);
+        assert_eq!(
+            Some(20),
+            parse_value::<u64>(&s, "storage.capacity").unwrap()
+        );
+        assert_eq!("mpv", s.required_value_of("player").unwrap());
+        assert!(s.is_present("slideshow.no_fullscreen").unwrap());
+
+        // Command line takes precedence over file
+        let s = settings(&["--storage.capacity=30", "--player=vlc"], file);
+        assert_eq!(
+            Some(30),
+            parse_value::<u64>(&s, "storage.capacity").unwrap()
+        );
+        assert_eq!("vlc", s.required_value_of("player").unwrap());
+
+        // Defaults are used when neither is given
+        let s = settings(&[], "");
+        assert_eq!(
+            Some(10),
+            parse_value::<u64>(&s, "storage.capacity").unwrap()
+        );
+        assert!(!s.is_present("slideshow.no_fullscreen").unwrap());
+        assert!(s.required_value_of("player").is_err());
+    }
+
+    #[test]
+    fn test_settings_multiple_values() {
+        let file = r#"
+            [control]
+            gpio_map = ["18:H:play_next:L", "10:H:play_back:L"]
+        "#;
+        let s = settings(&[], file);
+        assert_eq!(
+            vec!["18:H:play_next:L", "10:H:play_back:L"],
+            s.values_of("control.gpio_map").unwrap()
+        );
+
+        let s = settings(&["--control.gpio-map=23:H:pause:L"], file);
+        assert_eq!(
+            vec!["23:H:pause:L"],
+            s.values_of("control.gpio_map").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_settings_invalid_value() {
+        let s = settings(
+            &[],
+            "[storage]\ncapacity = \"many\"\n[slideshow]\nno_fullscreen = 1",
+        );
+        let e = parse_value::<u64>(&s, "storage.capacity").unwrap_err();
+        assert_eq!(
+            "storage.capacity",
+            e.downcast_ref::<InvalidArgError>().unwrap().name
+        );
+        assert!(s.is_present("slideshow.no_fullscreen").is_err());
+
+        let s = settings(&[], "[storage]\ncapacity = [1, 2]");
+        assert!(s.value_of("storage.capacity").is_err());
+    }
+
+    #[test]
+    fn test_settings_validate_file() {
+        let validate = |file: &str| Settings::validate_file(&app(), &toml::from_str(file).unwrap());
+
+        validate(
+            r#"
+            player = "mpv"
+            [storage]
+            capacity = 20
+            [control]
+            gpio_map = ["18:H:play_next:L", "10:H:play_back:L"]
+            [slideshow]
+            no_fullscreen = true
+        "#,
+        )
+        .unwrap();
+
+        // Misspelled key
+        let e = validate("[storage]\ncapacty = 20").unwrap_err();
+        let e = e.downcast_ref::<InvalidArgError>().unwrap();
+        assert_eq!("config", e.name);
+        assert!(e.reason.contains("storage.capacty"));
+
+        // Key of unknown section
+        assert!(validate("[storag]\ncapacity = 20").is_err());
+
+        // Value out of possible ones
+        let e = validate("player = \"mplayer\"").unwrap_err();
+        let e = e.downcast_ref::<InvalidArgError>().unwrap();
+        assert!(e.reason.contains("player"));
+        assert!(e.reason.contains("mplayer"));
+
+        // Empty values, also in arrays
+        let e = validate("[googlephotos]\noauth_client_id = \"\"").unwrap_err();
+        let e = e.downcast_ref::<InvalidArgError>().unwrap();
+        assert!(e.reason.contains("googlephotos.oauth_client_id"));
+        assert!(validate("[googlephotos]\nalbum_id = [\"a\", \"\"]").is_err());
+    }
+
+    #[test]
+    fn test_changed_settings() {
+        let old = settings(&["--player=vlc"], "[storage]\ncapacity = 20");
+        // Arguments not requiring restart are not reported
+        let new = settings(
+            &["--player=vlc"],
+            "[storage]\ncapacity = 20\n[slideshow]\nno_fullscreen = true",
+        );
+        assert!(changed_settings(&old, &new).unwrap().is_empty());
+
+        // Command line arguments can't change across reloads, but values in file can
+        let new = settings(
+            &["--player=vlc"],
+            "player = \"mpv\"\n[storage]\ncapacity = 30",
+        );
+        assert_eq!(
+            vec!["storage.capacity".to_string()],
+            changed_settings(&old, &new).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_gpio_mapping() {
+        let file = r#"
+            [control]
+            gpio_map = ["18:H:play_next:L", "18:H:refresh:L:long", "10:L:play_back:H:short:50"]
+        "#;
+        assert_eq!(3, parse_gpio_mapping(&settings(&[], file)).unwrap().len());
+
+        for map in &[
+            "18:H:play_next",
+            "18:H:play_next:L:press",
+            "18:H:play_next:L:short:soon",
+            "18:H:dance:L",
+            "18:H:play_next:L:short:50:extra",
+        ] {
+            let s = settings(&[&format!("--control.gpio-map={}", map)], "");
+            assert!(parse_gpio_mapping(&s).is_err(), "{}", map);
+        }
+    }
+
+    #[test]
+    fn test_parse_schedules() {
+        let s = settings(&[], "[schedule]\nsleep = [\"00:00\", \"Sat 01:00\"]");
+        let schedules = parse_schedules(
+            &s,
+            &[
+                ("schedule.sleep", PlayerCmd::Sleep),
+                ("schedule.wakeup", PlayerCmd::Wakeup),
+            ],
+        )
+        .unwrap();
+        assert_eq!(2, schedules.len());
+        assert_eq!("Sat 01:00", schedules[1].0.to_string());
+
+        let s = settings(&[], "[schedule]\nsleep = \"24:00\"");
+        let e = parse_schedules(&s, &[("schedule.sleep", PlayerCmd::Sleep)]).unwrap_err();
+        assert_eq!(
+            "schedule.sleep",
+            e.downcast_ref::<InvalidArgError>().unwrap().name
+        );
+    }
+}
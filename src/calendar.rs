@@ -0,0 +1,393 @@
+use chrono::offset::LocalResult;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Utc};
+use failure::Fail;
+use std::fmt;
+use std::str::FromStr;
+
+/// Maximum number of days to look for an occurrence of the calendar
+const SEARCH_DAYS: i64 = 366 * 5;
+
+const WEEKDAYS: &[&str] = &[
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+#[derive(Debug, Fail)]
+#[fail(display = "Invalid calendar expression '{}': {}", expr, reason)]
+pub struct ParseError {
+    expr: String,
+    reason: String,
+}
+
+/// Values matched by a component of calendar expression,
+/// as the list of inclusive ranges with the step.
+#[derive(Debug, Clone, PartialEq)]
+struct Field(Vec<(u32, u32, u32)>);
+
+impl Field {
+    fn single(value: u32) -> Self {
+        Field(vec![(value, value, 1)])
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.0.iter().any(|&(first, last, step)| {
+            first <= value && value <= last && (value - first).rem_euclid(step) == 0
+        })
+    }
+
+    /// Return matching values in the range in ascending order, or descending if `!forward`
+    fn values(&self, min: u32, max: u32, forward: bool) -> Vec<u32> {
+        let mut values: Vec<_> = (min..=max).filter(|v| self.matches(*v)).collect();
+        if !forward {
+            values.reverse();
+        }
+        values
+    }
+
+    /// Parse comma separated list of `*`, `N`, `N..M` each optionally followed by `/STEP`
+    fn parse(s: &str, min: u32, max: u32) -> Result<Self, String> {
+        let number = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| format!("not a number: {}", s))
+                .and_then(|n| {
+                    if n < min || n > max {
+                        Err(format!("{} is out of range {}..{}", n, min, max))
+                    } else {
+                        Ok(n)
+                    }
+                })
+        };
+
+        let mut ranges = Vec::new();
+        for item in s.split(',') {
+            let (base, step) = match item.find('/') {
+                Some(i) => {
+                    let step = item[i + 1..]
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|s| *s > 0)
+                        .ok_or_else(|| format!("invalid repetition: {}", item))?;
+                    (&item[..i], Some(step))
+                }
+                None => (item, None),
+            };
+            let (first, last) = if base == "*" {
+                (min, max)
+            } else if let Some(i) = base.find("..") {
+                (number(&base[..i])?, number(&base[i + 2..])?)
+            } else {
+                let first = number(base)?;
+                (first, if step.is_some() { max } else { first })
+            };
+            if first > last {
+                return Err(format!("invalid range: {}", item));
+            }
+            ranges.push((first, last, step.unwrap_or(1)));
+        }
+        Ok(Field(ranges))
+    }
+
+    fn parse_weekdays(s: &str) -> Result<Self, String> {
+        let weekday = |s: &str| {
+            let lower = s.to_lowercase();
+            WEEKDAYS
+                .iter()
+                .position(|name| lower.len() >= 3 && name.starts_with(&lower))
+                .map(|i| i as u32)
+                .ok_or_else(|| format!("unknown weekday: {}", s))
+        };
+
+        let mut ranges = Vec::new();
+        for item in s.split(',') {
+            let (first, last) = match item.find("..") {
+                Some(i) => (weekday(&item[..i])?, weekday(&item[i + 2..])?),
+                None => {
+                    let day = weekday(item)?;
+                    (day, day)
+                }
+            };
+            if first > last {
+                return Err(format!("invalid range: {}", item));
+            }
+            ranges.push((first, last, 1));
+        }
+        Ok(Field(ranges))
+    }
+}
+
+/// Recurring calendar event in the subset of systemd's calendar event syntax.
+///
+/// The form is `[WEEKDAYS] [[YEAR-]MONTH-DAY] [HOUR:MINUTE[:SECOND]] [UTC]`, e.g.
+/// `Mon..Fri 07:30`, `*-*-01 00:00` or `*:0/10`. Each component accepts `*`,
+/// comma separated values, ranges `N..M` and repetitions `N/STEP`.
+/// Shorthands `minutely`, `hourly`, `daily` and `weekly` are also accepted.
+///
+/// Times are in the local timezone unless `UTC` is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    expr: String,
+    weekdays: Option<Field>,
+    years: Field,
+    months: Field,
+    days: Field,
+    hours: Field,
+    minutes: Field,
+    seconds: Field,
+    utc: bool,
+}
+
+impl Calendar {
+    /// Return the first occurrence strictly after the given time
+    pub fn next_after(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.utc {
+            self.search(&time.with_timezone(&Utc), true)
+                .map(|t| t.with_timezone(&Local))
+        } else {
+            self.search(time, true)
+        }
+    }
+
+    /// Return the last occurrence at or before the given time
+    pub fn last_before(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.utc {
+            self.search(&time.with_timezone(&Utc), false)
+                .map(|t| t.with_timezone(&Local))
+        } else {
+            self.search(time, false)
+        }
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        date.year() >= 0
+            && self.years.matches(date.year() as u32)
+            && self.months.matches(date.month())
+            && self.days.matches(date.day())
+            && self
+                .weekdays
+                .as_ref()
+                .map(|w| w.matches(date.weekday().num_days_from_monday()))
+                .unwrap_or(true)
+    }
+
+    fn search<Tz: TimeZone>(&self, time: &DateTime<Tz>, forward: bool) -> Option<DateTime<Tz>> {
+        let tz = time.timezone();
+        let hours = self.hours.values(0, 23, forward);
+        let minutes = self.minutes.values(0, 59, forward);
+        let seconds = self.seconds.values(0, 59, forward);
+
+        let start = time.naive_local().date();
+        for i in 0..SEARCH_DAYS {
+            let date = if forward {
+                start + Duration::days(i)
+            } else {
+                start - Duration::days(i)
+            };
+            if !self.matches_date(date) {
+                continue;
+            }
+            for &hour in &hours {
+                for &minute in &minutes {
+                    for &second in &seconds {
+                        let naive = date.and_hms(hour, minute, second);
+                        // Skip times which don't exist due to DST transition
+                        let t = match tz.from_local_datetime(&naive) {
+                            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t,
+                            LocalResult::None => continue,
+                        };
+                        if (forward && t > *time) || (!forward && t <= *time) {
+                            return Some(t);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for Calendar {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| ParseError {
+            expr: s.to_string(),
+            reason,
+        };
+
+        let expanded = match s.trim() {
+            "minutely" => "*-*-* *:*:00",
+            "hourly" => "*-*-* *:00:00",
+            "daily" => "*-*-* 00:00:00",
+            "weekly" => "Mon *-*-* 00:00:00",
+            other => other,
+        };
+        let mut tokens: Vec<_> = expanded.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Err(error("empty expression".to_string()));
+        }
+        let utc = tokens.last() == Some(&"UTC");
+        if utc {
+            tokens.pop();
+        }
+        let mut tokens = tokens.into_iter().peekable();
+
+        let weekdays = match tokens.peek() {
+            Some(t) if t.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                Some(Field::parse_weekdays(tokens.next().unwrap()).map_err(error)?)
+            }
+            _ => None,
+        };
+
+        let (mut years, mut months, mut days) = (
+            Field::parse("*", 0, 9999).unwrap(),
+            Field::parse("*", 1, 12).unwrap(),
+            Field::parse("*", 1, 31).unwrap(),
+        );
+        if let Some(date) = tokens.next_if(|t| t.contains('-')) {
+            let parts: Vec<_> = date.split('-').collect();
+            let (y, m, d) = match parts.as_slice() {
+                [y, m, d] => (Some(*y), *m, *d),
+                [m, d] => (None, *m, *d),
+                _ => return Err(error(format!("invalid date: {}", date))),
+            };
+            if let Some(y) = y {
+                years = Field::parse(y, 0, 9999).map_err(error)?;
+            }
+            months = Field::parse(m, 1, 12).map_err(error)?;
+            days = Field::parse(d, 1, 31).map_err(error)?;
+        }
+
+        let (mut hours, mut minutes, mut seconds) =
+            (Field::single(0), Field::single(0), Field::single(0));
+        if let Some(time) = tokens.next_if(|t| t.contains(':')) {
+            let parts: Vec<_> = time.split(':').collect();
+            let (h, m, s) = match parts.as_slice() {
+                [h, m, s] => (*h, *m, Some(*s)),
+                [h, m] => (*h, *m, None),
+                _ => return Err(error(format!("invalid time: {}", time))),
+            };
+            hours = Field::parse(h, 0, 23).map_err(error)?;
+            minutes = Field::parse(m, 0, 59).map_err(error)?;
+            if let Some(s) = s {
+                seconds = Field::parse(s, 0, 59).map_err(error)?;
+            }
+        }
+
+        if let Some(token) = tokens.next() {
+            return Err(error(format!("unexpected token: {}", token)));
+        }
+        Ok(Calendar {
+            expr: s.to_string(),
+            weekdays,
+            years,
+            months,
+            days,
+            hours,
+            minutes,
+            seconds,
+            utc,
+        })
+    }
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Local> {
+        Local.ymd(y, mo, d).and_hms(h, mi, s)
+    }
+
+    fn cal(s: &str) -> Calendar {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_next_after() {
+        let now = local(2020, 1, 1, 10, 5, 30); // Wednesday
+        assert_eq!(
+            Some(local(2020, 1, 2, 0, 0, 0)),
+            cal("00:00").next_after(&now)
+        );
+        assert_eq!(
+            Some(local(2020, 1, 1, 10, 10, 0)),
+            cal("*:0/10").next_after(&now)
+        );
+        assert_eq!(
+            Some(local(2020, 1, 1, 11, 0, 0)),
+            cal("00/1:00").next_after(&now)
+        );
+        assert_eq!(
+            Some(local(2020, 1, 3, 7, 30, 0)),
+            cal("Fri..Sun 07:30").next_after(&now)
+        );
+        assert_eq!(
+            Some(local(2020, 2, 1, 0, 0, 0)),
+            cal("*-*-01").next_after(&now)
+        );
+        assert_eq!(
+            Some(local(2021, 1, 1, 0, 0, 0)),
+            cal("2021-1-1 00:00:00").next_after(&now)
+        );
+        assert_eq!(
+            Some(local(2020, 1, 6, 0, 0, 0)),
+            cal("weekly").next_after(&now)
+        );
+        // * The time exactly on the event isn't the next one
+        assert_eq!(
+            Some(local(2020, 1, 1, 10, 5, 31)),
+            cal("*:*:*").next_after(&now)
+        );
+        // * Events which never happen don't hang
+        assert_eq!(None, cal("2-30").next_after(&now));
+    }
+
+    #[test]
+    fn test_last_before() {
+        let now = local(2020, 1, 1, 2, 0, 0);
+        assert_eq!(
+            Some(local(2020, 1, 1, 0, 0, 0)),
+            cal("00:00").last_before(&now)
+        );
+        assert_eq!(
+            Some(local(2019, 12, 31, 10, 0, 0)),
+            cal("10:00").last_before(&now)
+        );
+        // * The time exactly on the event is the last one
+        assert_eq!(Some(now), cal("02:00").last_before(&now));
+    }
+
+    #[test]
+    fn test_utc() {
+        let now = Utc.ymd(2020, 1, 1).and_hms(10, 0, 0).with_timezone(&Local);
+        let next = cal("12:00 UTC").next_after(&now).unwrap();
+        assert_eq!(Utc.ymd(2020, 1, 1).and_hms(12, 0, 0), next);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        for expr in &[
+            "",
+            "25:00",
+            "*:0/0",
+            "Someday 10:00",
+            "10:00 extra",
+            "1-2-3-4",
+            "5..3:00",
+        ] {
+            assert!(expr.parse::<Calendar>().is_err(), "{}", expr);
+        }
+        assert_eq!("Mon 10:00", cal("Mon 10:00").to_string());
+    }
+}
@@ -0,0 +1,348 @@
+use crate::album::{self, Album, AlbumItem, MediaType};
+use failure::Fail;
+use log::warn;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Error from one of child albums, annotated with the name of the album.
+#[derive(Debug)]
+pub struct Error<E: album::Error> {
+    album: String,
+    fatal: bool,
+    cause: E,
+}
+
+impl<E: album::Error> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error in album {}: {}", self.album, self.cause)
+    }
+}
+
+impl<E: album::Error> Fail for Error<E> {
+    fn cause(&self) -> Option<&dyn Fail> {
+        Some(&self.cause)
+    }
+}
+
+impl<E: album::Error> album::Error for Error<E> {
+    fn is_fatal(&self) -> bool {
+        self.fatal
+    }
+}
+
+/// Album merging items from several child albums.
+///
+/// Items are interleaved one by one from each child so that a child having
+/// lots of items doesn't dominate the head of the list.
+/// IDs and filenames of items are prefixed by the name of the child to avoid
+/// collisions among children.
+///
+/// A fatal error from a child stops listing only that child. Listing is
+/// considered fatal only when all children failed fatally.
+pub struct CompositeAlbum<A: Album> {
+    children: Vec<(String, A)>,
+}
+
+impl<A: Album> CompositeAlbum<A> {
+    pub fn new() -> Self {
+        CompositeAlbum {
+            children: Vec::new(),
+        }
+    }
+
+    /// Add a child album with the name used to namespace its items.
+    ///
+    /// The name must consist of only alphanumerics and underscores.
+    pub fn add<S: Into<String>>(mut self, name: S, album: A) -> Self {
+        let name = name.into();
+        assert!(
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "invalid album name: {}",
+            name
+        );
+        self.children.push((name, album));
+        self
+    }
+}
+
+impl<A: Album> Default for CompositeAlbum<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Album> Album for CompositeAlbum<A> {
+    type E = Error<A::E>;
+    type Item = CompositeAlbumItem<A::Item>;
+    type Items = CompositeAlbumItems<A>;
+
+    fn items(&self) -> Self::Items {
+        CompositeAlbumItems {
+            children: self
+                .children
+                .iter()
+                .map(|(name, album)| (name.clone(), Some(album.items())))
+                .collect(),
+            cursor: 0,
+            failed: 0,
+        }
+    }
+
+    fn prepare_item<P: AsRef<Path>>(&self, item: &Self::Item, path: P) -> Result<(), Self::E> {
+        let (name, album) = &self.children[item.child];
+        album
+            .prepare_item(&item.inner, path)
+            .map_err(|cause| Error {
+                album: name.clone(),
+                fatal: album::Error::is_fatal(&cause),
+                cause,
+            })
+    }
+}
+
+pub struct CompositeAlbumItems<A: Album> {
+    /// Pairs of child name and its items, None once it's exhausted
+    children: Vec<(String, Option<A::Items>)>,
+    cursor: usize,
+    /// Number of children which stopped by fatal error
+    failed: usize,
+}
+
+impl<A: Album> Iterator for CompositeAlbumItems<A> {
+    type Item = Result<CompositeAlbumItem<A::Item>, Error<A::E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.children.iter().any(|(_, items)| items.is_some()) {
+            let child = self.cursor;
+            self.cursor = (self.cursor + 1) % self.children.len();
+
+            let (name, items) = &mut self.children[child];
+            let next = match items {
+                Some(items) => items.next(),
+                None => continue,
+            };
+            match next {
+                Some(Ok(inner)) => {
+                    return Some(Ok(CompositeAlbumItem::new(child, name, inner)));
+                }
+                Some(Err(cause)) => {
+                    let fatal = album::Error::is_fatal(&cause);
+                    if fatal {
+                        warn!("Stop listing album {} by fatal error: {}", name, cause);
+                        *items = None;
+                        self.failed += 1;
+                    }
+                    return Some(Err(Error {
+                        album: name.clone(),
+                        fatal: fatal && self.failed == self.children.len(),
+                        cause,
+                    }));
+                }
+                None => *items = None,
+            }
+        }
+        None
+    }
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub struct CompositeAlbumItem<I> {
+    child: usize,
+    id: String,
+    path: PathBuf,
+    inner: I,
+}
+
+impl<I: AlbumItem> CompositeAlbumItem<I> {
+    fn new(child: usize, name: &str, inner: I) -> Self {
+        CompositeAlbumItem {
+            child,
+            id: format!("{}:{}", name, inner.id()),
+            path: PathBuf::from(format!("{}-{}", name, inner.path().display())),
+            inner,
+        }
+    }
+}
+
+impl<I: AlbumItem> AlbumItem for CompositeAlbumItem<I> {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn media_type(&self) -> MediaType {
+        self.inner.media_type()
+    }
+
+    fn created_time(&self) -> SystemTime {
+        self.inner.created_time()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.inner.description()
+    }
+
+    fn filename(&self) -> Option<&str> {
+        self.inner.filename()
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.inner.duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    struct MockAlbum {
+        items: Vec<Result<&'static str, bool>>,
+        prepared: Mutex<Vec<PathBuf>>,
+    }
+
+    impl MockAlbum {
+        fn new(items: Vec<Result<&'static str, bool>>) -> Self {
+            MockAlbum {
+                items,
+                prepared: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Album for MockAlbum {
+        type E = MockError;
+        type Item = MockAlbumItem;
+        type Items = Box<dyn Iterator<Item = Result<MockAlbumItem, MockError>>>;
+
+        fn items(&self) -> Self::Items {
+            Box::new(self.items.clone().into_iter().map(|r| {
+                r.map(|id| MockAlbumItem(id, PathBuf::from(format!("{}.jpg", id))))
+                    .map_err(MockError)
+            }))
+        }
+
+        fn prepare_item<P: AsRef<Path>>(
+            &self,
+            item: &Self::Item,
+            path: P,
+        ) -> Result<(), Self::E> {
+            assert_eq!(item.1, PathBuf::from(format!("{}.jpg", item.0)));
+            self.prepared
+                .lock()
+                .unwrap()
+                .push(path.as_ref().to_path_buf());
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct MockAlbumItem(&'static str, PathBuf);
+
+    impl AlbumItem for MockAlbumItem {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn path(&self) -> &Path {
+            &self.1
+        }
+
+        fn media_type(&self) -> MediaType {
+            MediaType::PHOTO
+        }
+
+        fn created_time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+    }
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "error")]
+    struct MockError(bool);
+
+    impl album::Error for MockError {
+        fn is_fatal(&self) -> bool {
+            self.0
+        }
+    }
+
+    fn listing<A: Album>(album: &A) -> Vec<Result<String, bool>> {
+        album
+            .items()
+            .map(|r| {
+                r.map(|item| item.id().to_string())
+                    .map_err(|e| album::Error::is_fatal(&e))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_items_interleaved() {
+        let album = CompositeAlbum::new()
+            .add("a", MockAlbum::new(vec![Ok("1"), Ok("2"), Ok("3")]))
+            .add("b", MockAlbum::new(vec![Ok("1")]));
+        // * Items should be taken from each child by turns
+        // * IDs should be namespaced by child name
+        assert_eq!(
+            vec![
+                Ok("a:1".to_string()),
+                Ok("b:1".to_string()),
+                Ok("a:2".to_string()),
+                Ok("a:3".to_string()),
+            ],
+            listing(&album)
+        );
+
+        let item = album.items().nth(1).unwrap().unwrap();
+        assert_eq!(Path::new("b-1.jpg"), item.path());
+    }
+
+    #[test]
+    fn test_items_fatal_error() {
+        let album = CompositeAlbum::new()
+            .add("a", MockAlbum::new(vec![Ok("1"), Err(true), Ok("2")]))
+            .add("b", MockAlbum::new(vec![Ok("1"), Err(false), Ok("2")]));
+        // * Fatal error in one child should not be fatal for the whole album
+        // * Child which faced fatal error should not be listed anymore
+        assert_eq!(
+            vec![
+                Ok("a:1".to_string()),
+                Ok("b:1".to_string()),
+                Err(false),
+                Err(false),
+                Ok("b:2".to_string()),
+            ],
+            listing(&album)
+        );
+
+        let album = CompositeAlbum::new()
+            .add("a", MockAlbum::new(vec![Err(true)]))
+            .add("b", MockAlbum::new(vec![Ok("1"), Err(true)]));
+        // * Error should be fatal once all children faced fatal error
+        assert_eq!(
+            vec![Err(false), Ok("b:1".to_string()), Err(true)],
+            listing(&album)
+        );
+    }
+
+    #[test]
+    fn test_prepare_item() {
+        let album = CompositeAlbum::new()
+            .add("a", MockAlbum::new(vec![Ok("1")]))
+            .add("b", MockAlbum::new(vec![Ok("1")]));
+        let item = album.items().nth(1).unwrap().unwrap();
+        album.prepare_item(&item, "dest").unwrap();
+        // * Preparation should be delegated to the child which the item came from
+        assert!(album.children[0].1.prepared.lock().unwrap().is_empty());
+        assert_eq!(
+            vec![PathBuf::from("dest")],
+            *album.children[1].1.prepared.lock().unwrap()
+        );
+    }
+}
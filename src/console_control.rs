@@ -1,4 +1,5 @@
 use crate::control::{Commander, PlayerCmd};
+use crate::metrics::METRICS;
 use log::{debug, info, warn};
 use std::io;
 use std::io::BufRead;
@@ -32,6 +33,7 @@ impl Commander<PlayerCmd> for ConsoleCommander {
                 warn!("Unknown command: {}", cmd_name);
                 continue;
             };
+            METRICS.commands.inc("console");
             if let Err(e) = sender.send(cmd) {
                 debug!("Breaking out loop facing error: {:?}", e);
                 break;
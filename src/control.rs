@@ -12,6 +12,22 @@ pub enum PlaylistCmd {
     Update,
     /// Regenerate playlist and replace the current one
     Refresh,
+    /// Reload configuration and apply it to the running slideshow
+    Reload,
+}
+
+impl PlaylistCmd {
+    /// Names of all commands accepted by `from_name`
+    pub const NAMES: &'static [&'static str] = &["update", "refresh", "reload"];
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "update" => Some(Self::Update),
+            "refresh" => Some(Self::Refresh),
+            "reload" => Some(Self::Reload),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -35,6 +51,18 @@ pub enum PlayerCmd {
 }
 
 impl PlayerCmd {
+    /// Names of all commands accepted by `from_name`
+    pub const NAMES: &'static [&'static str] = &[
+        "play_next",
+        "play_back",
+        "pause",
+        "resume",
+        "sleep",
+        "wakeup",
+        "mute",
+        "unmute",
+    ];
+
     pub fn from_name(s: &str) -> Option<Self> {
         match s {
             "play_next" => Some(Self::PlayNext),
@@ -50,13 +78,41 @@ impl PlayerCmd {
     }
 }
 
-pub fn handle_playlist_cmd<P: Player, A: Album>(
+/// Command to either the player or the playlist, for commanders producing both
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    Player(PlayerCmd),
+    Playlist(PlaylistCmd),
+}
+
+impl Command {
+    pub fn from_name(s: &str) -> Option<Self> {
+        PlayerCmd::from_name(s)
+            .map(Self::Player)
+            .or_else(|| PlaylistCmd::from_name(s).map(Self::Playlist))
+    }
+}
+
+impl From<PlayerCmd> for Command {
+    fn from(cmd: PlayerCmd) -> Self {
+        Command::Player(cmd)
+    }
+}
+
+impl From<PlaylistCmd> for Command {
+    fn from(cmd: PlaylistCmd) -> Self {
+        Command::Playlist(cmd)
+    }
+}
+
+pub fn handle_playlist_cmd<P: Player, A: Album + 'static>(
     slideshow: &mut Slideshow<P, A>,
     cmd: PlaylistCmd,
 ) -> Result<(), slideshow::Error> {
     match cmd {
         PlaylistCmd::Update => slideshow.update_playlist(),
         PlaylistCmd::Refresh => slideshow.refresh_playlist(),
+        PlaylistCmd::Reload => slideshow.reload(),
     }
 }
 
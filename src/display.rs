@@ -0,0 +1,340 @@
+use crate::player::{Player, PlayerStatus, PlaylistItem, Result, SlideshowConfig};
+use failure::format_err;
+use log::{debug, info, warn};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Value of `bl_power` to turn the backlight on, FB_BLANK_UNBLANK
+const BL_POWER_ON: &str = "0";
+/// Value of `bl_power` to turn the backlight off, FB_BLANK_POWERDOWN
+const BL_POWER_OFF: &str = "4";
+
+/// Power control of the display showing slideshow
+pub trait DisplayPower {
+    fn power_on(&mut self) -> Result<()>;
+    fn power_off(&mut self) -> Result<()>;
+}
+
+fn run_command(cmd: &mut Command) -> Result<()> {
+    debug!("Running {:?}", cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(format_err!("{:?} exited with {}", cmd, status));
+    }
+    Ok(())
+}
+
+/// Display controlled by arbitrary shell commands, e.g. `tvservice --off` for HDMI of Raspberry Pi
+#[derive(Debug, Clone)]
+pub struct CommandDisplay {
+    on_cmd: String,
+    off_cmd: String,
+}
+
+impl CommandDisplay {
+    pub fn new(on_cmd: String, off_cmd: String) -> Self {
+        CommandDisplay { on_cmd, off_cmd }
+    }
+}
+
+impl DisplayPower for CommandDisplay {
+    fn power_on(&mut self) -> Result<()> {
+        run_command(Command::new("sh").arg("-c").arg(&self.on_cmd))
+    }
+
+    fn power_off(&mut self) -> Result<()> {
+        run_command(Command::new("sh").arg("-c").arg(&self.off_cmd))
+    }
+}
+
+/// Display whose backlight is controlled through sysfs, e.g. /sys/class/backlight/rpi_backlight
+#[derive(Debug, Clone)]
+pub struct BacklightDisplay {
+    dir: PathBuf,
+}
+
+impl BacklightDisplay {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        BacklightDisplay { dir: dir.into() }
+    }
+
+    fn write_bl_power(&self, value: &str) -> Result<()> {
+        let path = self.dir.join("bl_power");
+        fs::write(&path, value).map_err(|e| format_err!("writing {:?}: {}", path, e))
+    }
+}
+
+impl DisplayPower for BacklightDisplay {
+    fn power_on(&mut self) -> Result<()> {
+        self.write_bl_power(BL_POWER_ON)
+    }
+
+    fn power_off(&mut self) -> Result<()> {
+        self.write_bl_power(BL_POWER_OFF)
+    }
+}
+
+/// Display of X server controlled by DPMS via xset, targeting the server of `DISPLAY`
+#[derive(Debug, Clone)]
+pub struct DpmsDisplay {
+    xset_bin: String,
+}
+
+impl Default for DpmsDisplay {
+    fn default() -> Self {
+        DpmsDisplay {
+            xset_bin: "xset".to_string(),
+        }
+    }
+}
+
+impl DpmsDisplay {
+    pub fn xset_bin(mut self, xset_bin: String) -> Self {
+        self.xset_bin = xset_bin;
+        self
+    }
+}
+
+impl DisplayPower for DpmsDisplay {
+    fn power_on(&mut self) -> Result<()> {
+        run_command(Command::new(&self.xset_bin).args(["dpms", "force", "on"]))
+    }
+
+    fn power_off(&mut self) -> Result<()> {
+        run_command(Command::new(&self.xset_bin).args(["dpms", "force", "off"]))
+    }
+}
+
+/// Player turning the display off while sleeping, regardless of the source of commands
+pub struct DisplayPlayer<P: Player> {
+    player: P,
+    display: Box<dyn DisplayPower + Send>,
+    /// Last power state set, None until set first
+    powered: Option<bool>,
+}
+
+impl<P: Player> DisplayPlayer<P> {
+    pub fn new(player: P, display: Box<dyn DisplayPower + Send>) -> Self {
+        DisplayPlayer {
+            player,
+            display,
+            powered: None,
+        }
+    }
+
+    /// Turn the display on or off following the player's sleeping state.
+    ///
+    /// Failures are only logged, as the player itself is working.
+    fn sync_power(&mut self) {
+        let on = !self.player.status().sleeping;
+        if self.powered == Some(on) {
+            return;
+        }
+        let state = if on { "on" } else { "off" };
+        info!("Turning display {}", state);
+        let result = if on {
+            self.display.power_on()
+        } else {
+            self.display.power_off()
+        };
+        match result {
+            Ok(()) => self.powered = Some(on),
+            Err(e) => warn!("Failed to turn display {}: {}", state, e),
+        }
+    }
+
+    /// Run the operation, then sync the display power with the result
+    fn with_sync<F>(&mut self, op: F) -> Result<()>
+    where
+        F: FnOnce(&mut P) -> Result<()>,
+    {
+        let result = op(&mut self.player);
+        self.sync_power();
+        result
+    }
+}
+
+impl<P: Player> Player for DisplayPlayer<P> {
+    fn start(&mut self, config: SlideshowConfig) -> Result<()> {
+        self.with_sync(|p| p.start(config))
+    }
+    fn stop(&mut self) {
+        self.player.stop()
+    }
+    fn play_next(&mut self) -> Result<()> {
+        self.player.play_next()
+    }
+    fn play_back(&mut self) -> Result<()> {
+        self.player.play_back()
+    }
+    fn sleep(&mut self) -> Result<()> {
+        self.with_sync(|p| p.sleep())
+    }
+    fn wakeup(&mut self) -> Result<()> {
+        self.with_sync(|p| p.wakeup())
+    }
+    fn pause(&mut self) -> Result<()> {
+        self.player.pause()
+    }
+    fn resume(&mut self) -> Result<()> {
+        // Resuming brings the player back from sleep as well
+        self.with_sync(|p| p.resume())
+    }
+    fn mute(&mut self) -> Result<()> {
+        self.player.mute()
+    }
+    fn unmute(&mut self) -> Result<()> {
+        self.player.unmute()
+    }
+    fn update_playlist(&mut self, playlist: Vec<PlaylistItem>) -> Result<()> {
+        self.player.update_playlist(playlist)
+    }
+    fn append_playlist(&mut self, items: Vec<PlaylistItem>) -> Result<()> {
+        self.player.append_playlist(items)
+    }
+    fn locked(&self) -> bool {
+        self.player.locked()
+    }
+    fn is_ok(&self) -> bool {
+        self.player.is_ok()
+    }
+    fn status(&self) -> PlayerStatus {
+        self.player.status()
+    }
+    fn reconfigure(&mut self, config: &SlideshowConfig) -> Result<Vec<&'static str>> {
+        self.player.reconfigure(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Player tracking only the sleeping state
+    #[derive(Default)]
+    struct MockPlayer {
+        sleeping: bool,
+    }
+
+    impl Player for MockPlayer {
+        fn start(&mut self, _config: SlideshowConfig) -> Result<()> {
+            Ok(())
+        }
+        fn stop(&mut self) {
+            self.sleeping = false;
+        }
+        fn play_next(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn play_back(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn sleep(&mut self) -> Result<()> {
+            self.sleeping = true;
+            Ok(())
+        }
+        fn wakeup(&mut self) -> Result<()> {
+            self.sleeping = false;
+            Ok(())
+        }
+        fn pause(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn resume(&mut self) -> Result<()> {
+            self.sleeping = false;
+            Ok(())
+        }
+        fn mute(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn unmute(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn update_playlist(&mut self, _playlist: Vec<PlaylistItem>) -> Result<()> {
+            Ok(())
+        }
+        fn append_playlist(&mut self, _items: Vec<PlaylistItem>) -> Result<()> {
+            Ok(())
+        }
+        fn locked(&self) -> bool {
+            self.sleeping
+        }
+        fn is_ok(&self) -> bool {
+            true
+        }
+        fn status(&self) -> PlayerStatus {
+            PlayerStatus {
+                sleeping: self.sleeping,
+                ..PlayerStatus::default()
+            }
+        }
+        fn reconfigure(&mut self, _config: &SlideshowConfig) -> Result<Vec<&'static str>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Display recording power states set
+    #[derive(Clone, Default)]
+    struct MockDisplay {
+        calls: Arc<Mutex<Vec<bool>>>,
+    }
+
+    impl DisplayPower for MockDisplay {
+        fn power_on(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push(true);
+            Ok(())
+        }
+        fn power_off(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push(false);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_display_player() {
+        let display = MockDisplay::default();
+        let mut player = DisplayPlayer::new(MockPlayer::default(), Box::new(display.clone()));
+        player.start(SlideshowConfig::default()).unwrap();
+        player.sleep().unwrap();
+        // * Power is set only on changes
+        player.sleep().unwrap();
+        player.pause().unwrap();
+        player.wakeup().unwrap();
+        player.sleep().unwrap();
+        // * Resume brings the display back as well as the player
+        player.resume().unwrap();
+        assert_eq!(
+            vec![true, false, true, false, true],
+            *display.calls.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_backlight_display() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut display = BacklightDisplay::new(dir.path());
+        display.power_off().unwrap();
+        assert_eq!(
+            "4",
+            fs::read_to_string(dir.path().join("bl_power")).unwrap()
+        );
+        display.power_on().unwrap();
+        assert_eq!(
+            "0",
+            fs::read_to_string(dir.path().join("bl_power")).unwrap()
+        );
+
+        assert!(BacklightDisplay::new(dir.path().join("missing"))
+            .power_on()
+            .is_err());
+    }
+
+    #[test]
+    fn test_command_display() {
+        let mut display = CommandDisplay::new("true".to_string(), "false".to_string());
+        assert!(display.power_on().is_ok());
+        assert!(display.power_off().is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::metrics::METRICS;
 use crate::oauth::{self, TokenService};
 use dirs;
 use failure::{self, format_err, Fail};
@@ -21,6 +22,7 @@ const API_ENDPOINT: &str = "https://photoslibrary.googleapis.com";
 const PATH_LIST_ALBUMS: &str = "v1/albums";
 const PATH_LIST_SHARED_ALBUMS: &str = "v1/sharedAlbums";
 const PATH_MEDIA_ITEMS_SEARCH: &str = "v1/mediaItems:search";
+const PATH_MEDIA_ITEMS_BATCH_GET: &str = "v1/mediaItems:batchGet";
 
 #[derive(Debug, Fail)]
 pub enum Error {
@@ -36,6 +38,9 @@ pub enum Error {
     /// IO error
     #[fail(display = "IO error in processing request: {}", _0)]
     IO(#[fail(cause)] io::Error),
+    /// Access to media content rejected by a client error status, e.g. 403 by expired base URL
+    #[fail(display = "Media access rejected by status: {}", _0)]
+    Rejected(u16),
 }
 
 impl From<oauth::Error> for Error {
@@ -92,6 +97,7 @@ pub struct GPhotosApi {
     tokens: TokenService,
     retry: RetryConfig,
     client: Client,
+    endpoint: String,
 }
 
 impl GPhotosApi {
@@ -100,9 +106,16 @@ impl GPhotosApi {
             tokens,
             retry: retry_config,
             client: reqwest::Client::new(),
+            endpoint: API_ENDPOINT.to_string(),
         }
     }
 
+    /// Set the base URL of the API, e.g. to access a local server in tests
+    pub fn endpoint<S: Into<String>>(mut self, endpoint: S) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
     fn request<Req, Res>(&self, method: Method, url: &str, data: Option<&Req>) -> Result<Res>
     where
         Req: Serialize,
@@ -111,6 +124,7 @@ impl GPhotosApi {
         let mut retry_count = 0;
         loop {
             let access_token = self.tokens.obtain_access_token()?;
+            METRICS.gphotos_requests.inc();
 
             let mut builder = self
                 .client
@@ -154,6 +168,7 @@ impl GPhotosApi {
             if retry_count > self.retry.max_retries {
                 return Err(err);
             }
+            METRICS.gphotos_retries.inc();
             thread::sleep(self.retry.backoff)
         }
     }
@@ -164,7 +179,7 @@ impl GPhotosApi {
             params.push(("pageToken", token));
         }
         let url =
-            Url::parse_with_params(&format!("{}/{}", API_ENDPOINT, PATH_LIST_ALBUMS), &params)
+            Url::parse_with_params(&format!("{}/{}", self.endpoint, PATH_LIST_ALBUMS), &params)
                 .expect("url parse");
 
         self.request(Method::GET, url.as_str(), None as Option<&()>)
@@ -176,7 +191,7 @@ impl GPhotosApi {
             params.push(("pageToken", token));
         }
         let url = Url::parse_with_params(
-            &format!("{}/{}", API_ENDPOINT, PATH_LIST_SHARED_ALBUMS),
+            &format!("{}/{}", self.endpoint, PATH_LIST_SHARED_ALBUMS),
             &params,
         )
         .expect("url parse");
@@ -190,11 +205,26 @@ impl GPhotosApi {
     ) -> Result<MediaItemsSearchResponse> {
         self.request(
             Method::POST,
-            &format!("{}/{}", API_ENDPOINT, PATH_MEDIA_ITEMS_SEARCH),
+            &format!("{}/{}", self.endpoint, PATH_MEDIA_ITEMS_SEARCH),
             Some(req),
         )
     }
 
+    /// Get multiple media items by their IDs at once.
+    ///
+    /// Items which are not accessible anymore are reported as per-item status
+    /// in the response rather than as an error.
+    pub fn media_items_batch_get(&self, ids: &[&str]) -> Result<MediaItemsBatchGetResponse> {
+        let params: Vec<_> = ids.iter().map(|id| ("mediaItemIds", *id)).collect();
+        let url = Url::parse_with_params(
+            &format!("{}/{}", self.endpoint, PATH_MEDIA_ITEMS_BATCH_GET),
+            &params,
+        )
+        .expect("url parse");
+
+        self.request(Method::GET, url.as_str(), None as Option<&()>)
+    }
+
     /// Download the content of given media item and save it into specified path.
     ///
     /// This is a simple HTTP access rather than Google Photos API access,
@@ -214,6 +244,9 @@ impl GPhotosApi {
         };
 
         let mut resp = self.client.get(&url).send()?;
+        if resp.status().is_client_error() {
+            return Err(Error::Rejected(resp.status().as_u16()));
+        }
         if !resp.status().is_success() {
             return Err(Error::Request(format_err!(
                 "bad status code: {}",
@@ -265,6 +298,26 @@ pub struct MediaItemsSearchResponse {
     pub next_page_token: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaItemsBatchGetResponse {
+    pub media_item_results: Vec<MediaItemResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaItemResult {
+    pub media_item: Option<MediaItem>,
+    pub status: Option<Status>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub code: Option<i32>,
+    pub message: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct MediaItem {
@@ -4,24 +4,43 @@ use crate::album::{self, Album, AlbumItem, MediaType};
 use crate::oauth::TokenService;
 use api::{GPhotosApi, MediaItem, MediaItemsSearchRequest, RetryConfig};
 use chrono::DateTime;
-use failure::{self, Fail};
+use failure::{self, format_err, Fail};
 use log::debug;
 use std::collections::VecDeque;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
-use std::rc::Rc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 const MEDIA_ITEMS_SEARCH_PAGE_SIZE: i64 = 100;
+/// Base URLs expire after 60 minutes. Refresh them a bit earlier than that.
+const BASE_URL_MAX_AGE: Duration = Duration::from_secs(50 * 60);
 const PHOTO_WIDTH: u32 = 1280;
 const PHOTO_HEIGHT: u32 = 800;
 
+/// Return whether the status rejecting a download tells its base URL has expired
+fn is_expired_status(status: u16) -> bool {
+    status == 403 || status == 410
+}
+
 pub fn new_gphotos_album<S: Into<String>>(album_id: S, tokens: TokenService) -> GPhotosAlbum {
     let api = GPhotosApi::new(tokens, RetryConfig::default());
     GPhotosAlbum::new(album_id, api)
 }
 
+pub fn new_gphotos_albums<I, S>(album_ids: I, tokens: TokenService) -> Vec<GPhotosAlbum>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let api = Arc::new(GPhotosApi::new(tokens, RetryConfig::default()));
+    album_ids
+        .into_iter()
+        .map(|id| GPhotosAlbum::with_shared_api(id, Arc::clone(&api)))
+        .collect()
+}
+
 #[derive(Debug, Fail)]
 pub enum Error {
     /// Metadata returned from Google Photos API was broken or unexpected
@@ -56,7 +75,7 @@ impl From<api::Error> for Error {
     fn from(e: api::Error) -> Self {
         match e {
             api::Error::IO(e) => Error::IO(e),
-            e @ api::Error::Request(_) => Error::RemoteFail(e),
+            e @ api::Error::Request(_) | e @ api::Error::Rejected(_) => Error::RemoteFail(e),
             e @ api::Error::Unauthorized(_) => Error::InvalidAuthConfig(e.into()),
             e @ api::Error::OAuthToken(_) => Error::InvalidAuthConfig(e.into()),
         }
@@ -66,15 +85,20 @@ impl From<api::Error> for Error {
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct GPhotosAlbum {
-    album_id: Rc<String>,
-    api: Rc<GPhotosApi>,
+    album_id: Arc<String>,
+    api: Arc<GPhotosApi>,
 }
 
 impl GPhotosAlbum {
     pub fn new<S: Into<String>>(album_id: S, api: GPhotosApi) -> GPhotosAlbum {
+        Self::with_shared_api(album_id, Arc::new(api))
+    }
+
+    /// Create an album sharing API client (and its OAuth tokens) with other albums.
+    pub fn with_shared_api<S: Into<String>>(album_id: S, api: Arc<GPhotosApi>) -> GPhotosAlbum {
         GPhotosAlbum {
-            album_id: Rc::new(album_id.into()),
-            api: Rc::new(api),
+            album_id: Arc::new(album_id.into()),
+            api,
         }
     }
 }
@@ -86,8 +110,8 @@ impl Album for GPhotosAlbum {
 
     fn items(&self) -> Self::Items {
         GPhotosAlbumItems {
-            api: Rc::clone(&self.api),
-            album_id: Rc::clone(&self.album_id),
+            api: Arc::clone(&self.api),
+            album_id: Arc::clone(&self.album_id),
             cur_batch: VecDeque::new(),
             next_token: None,
             end_of_stream: false,
@@ -96,21 +120,69 @@ impl Album for GPhotosAlbum {
 
     fn prepare_item<P: AsRef<Path>>(&self, item: &Self::Item, path: P) -> Result<()> {
         let is_video = item.media_type() == MediaType::VIDEO;
-        self.api.download_media_item(
-            path.as_ref(),
-            &item.mitem.base_url.as_ref().expect("base_url is missing"),
-            is_video,
-            PHOTO_WIDTH,
-            PHOTO_HEIGHT,
-        )?;
+        let mut refreshed = false;
+        let mut base_url = match item.base_url() {
+            Some(url) => url,
+            None => {
+                debug!("Base URL of {} is stale, obtaining new one", item.id());
+                refreshed = true;
+                self.refresh_base_url(item)?
+            }
+        };
+
+        loop {
+            match self.api.download_media_item(
+                path.as_ref(),
+                &base_url,
+                is_video,
+                PHOTO_WIDTH,
+                PHOTO_HEIGHT,
+            ) {
+                Ok(()) => return Ok(()),
+                Err(api::Error::Rejected(status)) if !refreshed && is_expired_status(status) => {
+                    debug!(
+                        "Download of {} rejected by status {}, retrying with new base URL",
+                        item.id(),
+                        status
+                    );
+                    refreshed = true;
+                    base_url = self.refresh_base_url(item)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl GPhotosAlbum {
+    /// Obtain a new base URL of the item and keep it in the item for later downloads
+    fn refresh_base_url(&self, item: &GPhotosAlbumItem) -> Result<String> {
+        let url = self.fresh_base_url(item.id())?;
+        item.set_base_url(url.clone());
+        Ok(url)
+    }
 
-        Ok(())
+    fn fresh_base_url(&self, id: &str) -> Result<String> {
+        let resp = self.api.media_items_batch_get(&[id])?;
+        let result = resp
+            .media_item_results
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::CorruptedMetadata("empty batchGet response".to_string()))?;
+        match result.media_item.and_then(|mitem| mitem.base_url) {
+            Some(url) => Ok(url),
+            None => Err(Error::RemoteFail(api::Error::Request(format_err!(
+                "cannot obtain media item {}: {:?}",
+                id,
+                result.status
+            )))),
+        }
     }
 }
 
 pub struct GPhotosAlbumItems {
-    api: Rc<GPhotosApi>,
-    album_id: Rc<String>,
+    api: Arc<GPhotosApi>,
+    album_id: Arc<String>,
     cur_batch: VecDeque<MediaItem>,
     next_token: Option<String>,
     end_of_stream: bool,
@@ -142,12 +214,27 @@ impl Iterator for GPhotosAlbumItems {
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Debug)]
 pub struct GPhotosAlbumItem {
     path: PathBuf,
     media_type: MediaType,
     created_time: SystemTime,
     mitem: MediaItem,
+    /// Time when `mitem` was obtained, which tells freshness of its base URL
+    listed_time: SystemTime,
+    /// Base URL obtained after listing and the time obtained, replacing one in `mitem`
+    refreshed_base_url: Mutex<Option<(String, SystemTime)>>,
+}
+
+impl Eq for GPhotosAlbumItem {}
+
+impl PartialEq for GPhotosAlbumItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+            && self.media_type == other.media_type
+            && self.created_time == other.created_time
+            && self.mitem == other.mitem
+    }
 }
 
 impl GPhotosAlbumItem {
@@ -176,9 +263,33 @@ impl GPhotosAlbumItem {
             media_type,
             created_time: created_time.into(),
             mitem,
+            listed_time: SystemTime::now(),
+            refreshed_base_url: Mutex::new(None),
         })
     }
 
+    /// Return the latest base URL, or None if it's missing or stale
+    fn base_url(&self) -> Option<String> {
+        let refreshed = self.refreshed_base_url.lock().expect("lock base URL");
+        let (url, obtained) = match &*refreshed {
+            Some((url, obtained)) => (url.as_str(), *obtained),
+            None => (self.mitem.base_url.as_deref()?, self.listed_time),
+        };
+        let stale = SystemTime::now()
+            .duration_since(obtained)
+            .map(|age| age >= BASE_URL_MAX_AGE)
+            .unwrap_or(false);
+        if stale {
+            None
+        } else {
+            Some(url.to_string())
+        }
+    }
+
+    fn set_base_url(&self, url: String) {
+        *self.refreshed_base_url.lock().expect("lock base URL") = Some((url, SystemTime::now()));
+    }
+
     /// Return media information from its MIME type
     ///
     /// The return type is (MediaType, FILE_EXTENSION)
@@ -217,4 +328,117 @@ impl AlbumItem for GPhotosAlbumItem {
     fn created_time(&self) -> SystemTime {
         self.created_time
     }
+
+    fn description(&self) -> Option<&str> {
+        self.mitem.description.as_deref()
+    }
+
+    fn filename(&self) -> Option<&str> {
+        self.mitem.filename.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth::AuthConfig;
+    use api::MediaMetadata;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Token service holding an access token which never expires
+    fn token_service(dir: &Path) -> TokenService {
+        let token_store = dir.join("tokens.json");
+        fs::write(
+            &token_store,
+            r#"{"accessToken": {"secret": "token", "createdDate": 0, "expireDate": null}}"#,
+        )
+        .unwrap();
+        TokenService::new(AuthConfig {
+            auth_url: "http://localhost/auth".to_string(),
+            token_url: "http://localhost/token".to_string(),
+            client_id: "id".to_string(),
+            client_secret: "secret".to_string(),
+            scopes: Vec::new(),
+            token_store,
+        })
+        .unwrap()
+    }
+
+    /// Start a server serving media at /fresh, rejecting /stale by 403 and /missing by 404.
+    /// Returns the base URL of the server and the counter of batchGet requests.
+    fn start_server() -> (String, Arc<AtomicUsize>) {
+        let batch_gets = Arc::new(AtomicUsize::new(0));
+        let base = Arc::new(Mutex::new(String::new()));
+        let server = {
+            let batch_gets = Arc::clone(&batch_gets);
+            let base = Arc::clone(&base);
+            rouille::Server::new("localhost:0", move |request| {
+                let url = request.url();
+                if url.starts_with("/v1/mediaItems:batchGet") {
+                    batch_gets.fetch_add(1, Ordering::SeqCst);
+                    let base = base.lock().unwrap();
+                    return rouille::Response::text(format!(
+                        r#"{{"mediaItemResults": [{{"mediaItem": {{"id": "a", "baseUrl": "{}/fresh"}}}}]}}"#,
+                        base
+                    ));
+                }
+                match url.split('=').next().unwrap() {
+                    "/fresh" => rouille::Response::text("content"),
+                    "/stale" => rouille::Response::text("").with_status_code(403),
+                    _ => rouille::Response::empty_404(),
+                }
+            })
+            .unwrap()
+        };
+        let url = format!("http://{}", server.server_addr());
+        *base.lock().unwrap() = url.clone();
+        thread::spawn(move || server.run());
+        (url, batch_gets)
+    }
+
+    fn album_item(id: &str, base_url: String) -> GPhotosAlbumItem {
+        GPhotosAlbumItem::new(MediaItem {
+            id: Some(id.to_string()),
+            description: None,
+            product_url: None,
+            base_url: Some(base_url),
+            mime_type: Some("image/jpeg".to_string()),
+            media_metadata: Some(MediaMetadata {
+                creation_time: Some("2020-01-01T00:00:00Z".to_string()),
+                width: None,
+                height: None,
+            }),
+            filename: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_prepare_item_refreshes_base_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, batch_gets) = start_server();
+        let api = GPhotosApi::new(token_service(dir.path()), RetryConfig::default()).endpoint(&url);
+        let album = GPhotosAlbum::new("album", api);
+
+        // * Expired base URL should be replaced with a new one to download
+        let item = album_item("a", format!("{}/stale", url));
+        let path = dir.path().join("a.jpg");
+        album.prepare_item(&item, &path).unwrap();
+        assert_eq!("content", fs::read_to_string(&path).unwrap());
+        assert_eq!(1, batch_gets.load(Ordering::SeqCst));
+
+        // * New base URL should be kept for later downloads
+        album.prepare_item(&item, &path).unwrap();
+        assert_eq!(1, batch_gets.load(Ordering::SeqCst));
+
+        // * Other rejections shouldn't be taken as expiration
+        let item = album_item("b", format!("{}/missing", url));
+        match album.prepare_item(&item, dir.path().join("b.jpg")) {
+            Err(Error::RemoteFail(api::Error::Rejected(404))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(1, batch_gets.load(Ordering::SeqCst));
+    }
 }
@@ -1,15 +1,30 @@
-use crate::control::{Commander, PlayerCmd};
+use crate::control::{Command, Commander, PlayerCmd, PlaylistCmd};
+use crate::metrics::METRICS;
 use failure::{self, format_err, Fail};
-use gpio_cdev::{Chip, LineRequestFlags, MultiLineHandle};
-use log::{debug, error, info};
+use gpio_cdev::{
+    Chip, EventRequestFlags, EventType, LineEventHandle, LineRequestFlags, MultiLineHandle,
+};
+use log::{debug, error, info, warn};
 use std::collections::{HashMap, HashSet};
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Interval to read pins when edge events aren't available
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Maximum duration to wait for events, to check for termination
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Duration to wait before retrying after failing to read pins
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(30);
+/// Minimum duration to hold a button to make it a long press
+const LONG_PRESS: Duration = Duration::from_millis(800);
+/// Maximum interval between releasing and pressing a button again to make it a double press
+const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(400);
 
 #[derive(Debug, Fail)]
 pub enum Error {
@@ -25,47 +40,469 @@ impl From<gpio_cdev::errors::Error> for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+type SharedSender<C> = Arc<Mutex<Option<mpsc::Sender<C>>>>;
+
+/// How the pin's state changes to trigger a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    /// On changing to the level, e.g. for toggle switches
+    Edge,
+    /// On releasing after pressed shortly
+    Short,
+    /// On being held pressed for a while
+    Long,
+    /// On releasing after pressed twice quickly
+    Double,
+}
+
+impl Gesture {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "edge" => Some(Self::Edge),
+            "short" => Some(Self::Short),
+            "long" => Some(Self::Long),
+            "double" => Some(Self::Double),
+            _ => None,
+        }
+    }
+}
+
 pub struct PinMap {
     /// Pin's line offset.
     offset: u32,
     /// True means on raising edge. Otherwse on falling edge.
+    /// For gestures other than `Edge`, the level of the pin while pressed.
     edge_high: bool,
     /// Default state
     default_high: bool,
     /// Command to execute.
-    cmd: PlayerCmd,
+    cmd: Command,
+    gesture: Gesture,
+    /// Duration which the pin must keep its level to be considered as changed
+    debounce: Duration,
 }
 
 impl PinMap {
-    pub fn new(offset: u32, edge_high: bool, default_high: bool, cmd: PlayerCmd) -> Self {
+    pub fn new<C: Into<Command>>(offset: u32, edge_high: bool, default_high: bool, cmd: C) -> Self {
         PinMap {
             offset,
             edge_high,
             default_high,
-            cmd,
+            cmd: cmd.into(),
+            gesture: Gesture::Edge,
+            debounce: DEFAULT_DEBOUNCE,
+        }
+    }
+
+    pub fn gesture(mut self, gesture: Gesture) -> Self {
+        self.gesture = gesture;
+        self
+    }
+
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+}
+
+/// Lookup table from pin's level and gesture to command
+#[derive(Debug, Default)]
+struct PinMapping {
+    commands: HashMap<(u32, bool, Gesture), Command>,
+    /// Debounce duration of each pin, the longest one among its mappings
+    debounce: HashMap<u32, Duration>,
+}
+
+impl PinMapping {
+    fn get(&self, offset: u32, level: bool, gesture: Gesture) -> Option<Command> {
+        self.commands.get(&(offset, level, gesture)).cloned()
+    }
+
+    /// Return the level of the pin while pressed if any gesture is mapped for it
+    fn pressed_level(&self, offset: u32) -> Option<bool> {
+        self.commands
+            .keys()
+            .find(|(off, _, gesture)| *off == offset && *gesture != Gesture::Edge)
+            .map(|(_, level, _)| *level)
+    }
+
+    fn debounce(&self, offset: u32) -> Duration {
+        self.debounce
+            .get(&offset)
+            .cloned()
+            .unwrap_or(DEFAULT_DEBOUNCE)
+    }
+
+    fn offsets(&self) -> HashSet<u32> {
+        self.commands.keys().map(|(off, _, _)| *off).collect()
+    }
+}
+
+/// Build lookup table from pin's edge to command and the default state of each pin
+fn build_mapping(pin_mapping: Vec<PinMap>) -> (PinMapping, HashMap<u32, bool>) {
+    let mut pinmap = PinMapping::default();
+    let mut default_states = HashMap::new();
+    for map in pin_mapping {
+        pinmap
+            .commands
+            .insert((map.offset, map.edge_high, map.gesture), map.cmd);
+        let debounce = pinmap.debounce.entry(map.offset).or_insert(map.debounce);
+        *debounce = (*debounce).max(map.debounce);
+        default_states.insert(map.offset, map.default_high);
+    }
+    (pinmap, default_states)
+}
+
+/// Debounced state of a pin, recognizing gestures from its changes
+struct PinTracker {
+    offset: u32,
+    /// Debounced level
+    level: bool,
+    /// The last read level and the time it started
+    raw: bool,
+    raw_since: Instant,
+    /// Time the current press started and whether it has been handled as a long press
+    pressed_at: Option<Instant>,
+    long_pressed: bool,
+    /// Time of releasing a short press, pending until it turns out not to be a double press
+    released_at: Option<Instant>,
+}
+
+impl PinTracker {
+    fn new(offset: u32, level: bool, now: Instant) -> Self {
+        PinTracker {
+            offset,
+            level,
+            raw: level,
+            raw_since: now,
+            pressed_at: None,
+            long_pressed: false,
+            released_at: None,
+        }
+    }
+
+    /// Feed the level read at the time, returning commands triggered
+    fn update(&mut self, raw: bool, now: Instant, mapping: &PinMapping) -> Vec<Command> {
+        // Settle the previous level first, as events may come in a batch
+        let mut cmds = self.advance(now, mapping);
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since = now;
+        }
+        cmds.extend(self.advance(now, mapping));
+        cmds
+    }
+
+    /// Advance the time without level changes, returning commands triggered
+    fn advance(&mut self, now: Instant, mapping: &PinMapping) -> Vec<Command> {
+        let mut cmds = Vec::new();
+        let offset = self.offset;
+        let pressed_level = mapping.pressed_level(offset);
+        let gesture_cmd = |gesture| pressed_level.and_then(|l| mapping.get(offset, l, gesture));
+
+        if self.raw != self.level
+            && now.saturating_duration_since(self.raw_since) >= mapping.debounce(self.offset)
+        {
+            self.level = self.raw;
+            debug!("Detect GPIO event: {:?}", (self.offset, self.level));
+            cmds.extend(mapping.get(self.offset, self.level, Gesture::Edge));
+
+            if Some(self.level) == pressed_level {
+                self.pressed_at = Some(now);
+                self.long_pressed = false;
+            } else if self.pressed_at.take().is_some() && !self.long_pressed {
+                match gesture_cmd(Gesture::Double) {
+                    Some(double) if self.released_at.take().is_some() => cmds.push(double),
+                    Some(_) => self.released_at = Some(now),
+                    None => cmds.extend(gesture_cmd(Gesture::Short)),
+                }
+            }
+        }
+
+        if let Some(pressed_at) = self.pressed_at {
+            if !self.long_pressed && now.saturating_duration_since(pressed_at) >= LONG_PRESS {
+                if let Some(long) = gesture_cmd(Gesture::Long) {
+                    self.long_pressed = true;
+                    // Short press preceding the long press isn't a part of double press
+                    if self.released_at.take().is_some() {
+                        cmds.extend(gesture_cmd(Gesture::Short));
+                    }
+                    cmds.push(long);
+                }
+            }
+        } else if let Some(released_at) = self.released_at {
+            if now.saturating_duration_since(released_at) > DOUBLE_PRESS_WINDOW {
+                self.released_at = None;
+                cmds.extend(gesture_cmd(Gesture::Short));
+            }
+        }
+        cmds
+    }
+
+    /// Return the time at which `advance` may trigger something without level changes
+    fn deadline(&self, mapping: &PinMapping) -> Option<Instant> {
+        let pressed_level = mapping.pressed_level(self.offset);
+        let has_gesture = |gesture| {
+            pressed_level
+                .and_then(|l| mapping.get(self.offset, l, gesture))
+                .is_some()
+        };
+
+        let settle = if self.raw != self.level {
+            Some(self.raw_since + mapping.debounce(self.offset))
+        } else {
+            None
+        };
+        let long = match self.pressed_at {
+            Some(at) if !self.long_pressed && has_gesture(Gesture::Long) => Some(at + LONG_PRESS),
+            _ => None,
+        };
+        let short = match (self.pressed_at, self.released_at) {
+            (None, Some(at)) => Some(at + DOUBLE_PRESS_WINDOW + Duration::from_millis(1)),
+            _ => None,
+        };
+        settle.into_iter().chain(long).chain(short).min()
+    }
+}
+
+/// Change of a pin's level
+#[derive(Debug, Clone, Copy)]
+pub struct PinEvent {
+    pub offset: u32,
+    pub level: bool,
+    pub at: Instant,
+}
+
+/// Source of GPIO pin levels
+pub trait LineSource: Send {
+    /// Line offsets of pins, in the order of values returned by `values`
+    fn offsets(&self) -> &[u32];
+
+    /// Read current levels of pins
+    fn values(&mut self) -> Result<Vec<bool>>;
+
+    /// Wait for level changes at most for the timeout, returning them in order of occurrence
+    fn wait(&mut self, timeout: Duration) -> Result<Vec<PinEvent>>;
+}
+
+/// Lines notifying level changes by kernel edge events
+struct EventLines {
+    offsets: Vec<u32>,
+    handles: Vec<LineEventHandle>,
+}
+
+impl EventLines {
+    fn request(chip: &mut Chip, offsets: &[u32]) -> Result<Self> {
+        let handles = offsets
+            .iter()
+            .map(|off| {
+                chip.get_line(*off)?.events(
+                    LineRequestFlags::INPUT,
+                    EventRequestFlags::BOTH_EDGES,
+                    "phoseum",
+                )
+            })
+            .collect::<gpio_cdev::errors::Result<_>>()?;
+        Ok(EventLines {
+            offsets: offsets.to_vec(),
+            handles,
+        })
+    }
+}
+
+impl LineSource for EventLines {
+    fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    fn values(&mut self) -> Result<Vec<bool>> {
+        self.handles
+            .iter()
+            .map(|h| Ok(h.get_value()? != 0))
+            .collect()
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<Vec<PinEvent>> {
+        let mut fds: Vec<_> = self
+            .handles
+            .iter()
+            .map(|h| libc::pollfd {
+                fd: h.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let ret = unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                timeout.as_millis() as libc::c_int,
+            )
+        };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(Vec::new());
+            }
+            return Err(Error::GpioDev(err.into()));
+        }
+
+        let now = Instant::now();
+        let mut events = Vec::new();
+        for (i, fd) in fds.iter().enumerate() {
+            if fd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+            let event = self.handles[i].get_event()?;
+            events.push(PinEvent {
+                offset: self.offsets[i],
+                level: event.event_type() == EventType::RisingEdge,
+                at: event_instant(event.timestamp(), now),
+            });
+        }
+        events.sort_by_key(|e| e.at);
+        Ok(events)
+    }
+}
+
+/// Convert the kernel timestamp of an event to `Instant`.
+///
+/// Timestamps are in CLOCK_REALTIME until Linux 5.7 and in CLOCK_MONOTONIC since then,
+/// which are easily told apart as the latter counts from the boot.
+fn event_instant(timestamp: u64, now: Instant) -> Instant {
+    let clock = |id| {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(id, &mut ts) };
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    };
+    let realtime = clock(libc::CLOCK_REALTIME);
+    let clock_now = if timestamp > realtime / 2 {
+        realtime
+    } else {
+        clock(libc::CLOCK_MONOTONIC)
+    };
+    let age = Duration::from_nanos(clock_now.saturating_sub(timestamp));
+    now.checked_sub(age).unwrap_or(now)
+}
+
+/// Lines read periodically, for devices not supporting edge events
+struct PollingLines {
+    offsets: Vec<u32>,
+    handle: MultiLineHandle,
+    last_values: Option<Vec<bool>>,
+}
+
+impl LineSource for PollingLines {
+    fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    fn values(&mut self) -> Result<Vec<bool>> {
+        let values: Vec<_> = self
+            .handle
+            .get_values()?
+            .into_iter()
+            .map(|v| v != 0)
+            .collect();
+        self.last_values = Some(values.clone());
+        Ok(values)
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<Vec<PinEvent>> {
+        std::thread::sleep(timeout.min(POLL_INTERVAL));
+        let prev = self.last_values.take();
+        let values = self.values()?;
+        let now = Instant::now();
+        Ok(match prev {
+            Some(prev) => self
+                .offsets
+                .iter()
+                .zip(prev.into_iter().zip(values))
+                .filter(|(_, (prev, current))| prev != current)
+                .map(|(offset, (_, level))| PinEvent {
+                    offset: *offset,
+                    level,
+                    at: now,
+                })
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+}
+
+/// Open the lines of the GPIO device as inputs, reading them by edge events if available
+pub fn open_lines<P: AsRef<Path>>(dev_path: P, offsets: Vec<u32>) -> Result<Box<dyn LineSource>> {
+    let mut chip = Chip::new(dev_path.as_ref())?;
+    match EventLines::request(&mut chip, &offsets) {
+        Ok(lines) => Ok(Box::new(lines)),
+        Err(e) => {
+            warn!(
+                "GPIO edge events unavailable, falling back to polling: {}",
+                e
+            );
+            let lines = chip.get_lines(&offsets)?;
+            // Despite of the document says the `default` argument is used to supply
+            // default values for `OUTPUT` pins, it returns error when the length isn't
+            // consistent to the number of lines even for `INPUT`.
+            let defaults = vec![0; offsets.len()];
+            let handle = lines.request(LineRequestFlags::INPUT, &defaults, "phoseum")?;
+            Ok(Box::new(PollingLines {
+                offsets,
+                handle,
+                last_values: None,
+            }))
         }
     }
 }
 
+/// Handle to replace the pin mapping of a running `GpioCommander`
+#[derive(Clone)]
+pub struct PinMappingHandle {
+    offsets: HashSet<u32>,
+    mapping: Arc<Mutex<PinMapping>>,
+}
+
+impl PinMappingHandle {
+    /// Replace the pin mapping.
+    ///
+    /// Returns false without applying the mapping if it contains pins not opened
+    /// by the commander, as it requires reopening the device.
+    pub fn update(&self, pin_mapping: Vec<PinMap>) -> bool {
+        let (pinmap, _) = build_mapping(pin_mapping);
+        if let Some(offset) = pinmap.offsets().difference(&self.offsets).next() {
+            info!("Not updating GPIO mapping as pin {} isn't opened", offset);
+            return false;
+        }
+        info!("Updating GPIO mapping to {:?}", pinmap);
+        *self.mapping.lock().expect("lock mapping") = pinmap;
+        true
+    }
+}
+
+/// Commander producing commands by GPIO inputs.
+///
+/// As pins can be mapped to both player and playlist commands, this commander
+/// should be added as both, sharing the same device among clones.
+#[derive(Clone)]
 pub struct GpioCommander {
-    pin_mapping: HashMap<(u32, bool), PlayerCmd>,
+    pin_mapping: Arc<Mutex<PinMapping>>,
     offsets: Vec<u32>,
-    pin_state: Vec<u8>,
-    lines_handle: MultiLineHandle,
+    default_states: Vec<bool>,
+    lines: Arc<Mutex<Box<dyn LineSource>>>,
+    player_sender: SharedSender<PlayerCmd>,
+    playlist_sender: SharedSender<PlaylistCmd>,
+    started: Arc<AtomicBool>,
 }
 
 impl GpioCommander {
     pub fn create<P: AsRef<Path>>(dev_path: P, pin_mapping: Vec<PinMap>) -> Result<Self> {
-        let mut pinmap = HashMap::new();
-        let mut default_states = HashMap::new();
-        for map in pin_mapping {
-            pinmap.insert((map.offset, map.edge_high), map.cmd);
-            default_states.insert(map.offset, map.default_high);
-        }
-        let offsets: Vec<_> = pinmap
-            .keys()
-            .map(|(off, _)| *off)
-            // Make distinct list of line offsets
+        // Make distinct list of line offsets
+        let offsets: Vec<_> = pin_mapping
+            .iter()
+            .map(|map| map.offset)
             .collect::<HashSet<_>>()
             .into_iter()
             .collect();
@@ -75,59 +512,396 @@ impl GpioCommander {
             dev_path.as_ref(),
             offsets
         );
-        let mut chip = Chip::new(dev_path.as_ref())?;
-        let lines = chip.get_lines(&offsets)?;
-        // Despite of the document says the `default` argument is used to supply
-        // default values for `OUTPUT` pins, it returns error when the length isn't
-        // consistent to the number of lines even for `INPUT`.
-        let defaults = vec![0; offsets.len()];
-        let lines_handle = lines.request(LineRequestFlags::INPUT, &defaults, "phoseum")?;
-
-        let pin_state = offsets
-            .iter()
-            .map(|off| if default_states[off] { 1 } else { 0 })
-            .collect();
+        let lines = open_lines(dev_path, offsets)?;
+        Ok(Self::with_lines(lines, pin_mapping))
+    }
+
+    /// Create a commander reading pins from the given source
+    pub fn with_lines(lines: Box<dyn LineSource>, pin_mapping: Vec<PinMap>) -> Self {
+        let (pinmap, default_states) = build_mapping(pin_mapping);
+        let offsets = lines.offsets().to_vec();
+        let default_states: Vec<_> = offsets.iter().map(|off| default_states[off]).collect();
         debug!(
             "Initial GPIO pins state: offsets={:?}, states={:?}",
-            offsets, pin_state
+            offsets, default_states
         );
-        Ok(GpioCommander {
-            pin_mapping: pinmap,
+        GpioCommander {
+            pin_mapping: Arc::new(Mutex::new(pinmap)),
             offsets,
-            pin_state,
-            lines_handle,
-        })
+            default_states,
+            lines: Arc::new(Mutex::new(lines)),
+            player_sender: Arc::new(Mutex::new(None)),
+            playlist_sender: Arc::new(Mutex::new(None)),
+            started: Arc::new(AtomicBool::new(false)),
+        }
     }
-}
 
-impl Commander<PlayerCmd> for GpioCommander {
-    fn run(&mut self, sender: mpsc::Sender<PlayerCmd>, terminate: Arc<AtomicBool>) {
+    /// Return a handle to replace the pin mapping while running
+    pub fn mapping_handle(&self) -> PinMappingHandle {
+        PinMappingHandle {
+            offsets: self.offsets.iter().cloned().collect(),
+            mapping: Arc::clone(&self.pin_mapping),
+        }
+    }
+
+    /// Send the command to the channel of its kind, returning false if the channel is closed
+    fn send(&self, cmd: Command) -> bool {
+        let result = match cmd {
+            Command::Player(cmd) => send_to(&self.player_sender, cmd),
+            Command::Playlist(cmd) => send_to(&self.playlist_sender, cmd),
+        };
+        match result {
+            Some(true) => {
+                METRICS.commands.inc("gpio");
+                true
+            }
+            Some(false) => {
+                debug!("Breaking out loop as command channel is closed");
+                false
+            }
+            None => {
+                warn!("Commander isn't ready, ignoring {:?}", cmd);
+                true
+            }
+        }
+    }
+
+    fn run(&self, terminate: &AtomicBool) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let mut lines = self.lines.lock().expect("lock lines");
+        let now = Instant::now();
+        let mut trackers: Vec<_> = self
+            .offsets
+            .iter()
+            .zip(&self.default_states)
+            .map(|(offset, default)| PinTracker::new(*offset, *default, now))
+            .collect();
+        // Pins differing from their default state are handled as changed at start
+        let mut events = match lines.values() {
+            Ok(values) => self
+                .offsets
+                .iter()
+                .zip(values)
+                .map(|(offset, level)| PinEvent {
+                    offset: *offset,
+                    level,
+                    at: now,
+                })
+                .collect(),
+            Err(e) => {
+                error!("Failed reading GPIO input: {}", e);
+                Vec::new()
+            }
+        };
         while !terminate.load(Ordering::Relaxed) {
-            let inputs = match self.lines_handle.get_values() {
-                Ok(inputs) => inputs,
-                Err(e) => {
-                    error!("Failed reading GPIO input: {}", e);
-                    continue;
+            let now = Instant::now();
+            let cmds: Vec<_> = {
+                let mapping = self.pin_mapping.lock().expect("lock mapping");
+                let mut cmds = Vec::new();
+                for event in events.drain(..) {
+                    if let Some(tracker) = trackers.iter_mut().find(|t| t.offset == event.offset) {
+                        cmds.extend(tracker.update(event.level, event.at, &mapping));
+                    }
+                }
+                for tracker in &mut trackers {
+                    cmds.extend(tracker.advance(now, &mapping));
                 }
+                cmds
             };
-
-            for (i, current) in inputs.into_iter().enumerate() {
-                let prev = self.pin_state[i];
-                if current == prev {
-                    continue;
+            for cmd in cmds {
+                if !self.send(cmd) {
+                    return;
                 }
-                let key = (self.offsets[i], current > prev);
-                debug!("Detect GPIO event: {:?}", key);
-                if let Some(cmd) = self.pin_mapping.get(&key) {
-                    if let Err(e) = sender.send(*cmd) {
-                        debug!("Breaking out loop facing error: {:?}", e);
-                        break;
-                    }
+            }
+
+            let timeout = {
+                let mapping = self.pin_mapping.lock().expect("lock mapping");
+                trackers
+                    .iter()
+                    .filter_map(|t| t.deadline(&mapping))
+                    .min()
+                    .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+                    .map_or(IDLE_TIMEOUT, |timeout| timeout.min(IDLE_TIMEOUT))
+            };
+            match lines.wait(timeout) {
+                Ok(new_events) => events = new_events,
+                Err(e) => {
+                    error!("Failed reading GPIO input: {}", e);
+                    std::thread::sleep(ERROR_BACKOFF);
                 }
-                self.pin_state[i] = current;
             }
+        }
+    }
+}
+
+/// Send the command, returning whether it's sent or None if the sender isn't set yet
+fn send_to<C>(sender: &Mutex<Option<mpsc::Sender<C>>>, cmd: C) -> Option<bool> {
+    sender
+        .lock()
+        .expect("lock sender")
+        .as_ref()
+        .map(|sender| sender.send(cmd).is_ok())
+}
+
+impl Commander<PlayerCmd> for GpioCommander {
+    fn run(&mut self, sender: mpsc::Sender<PlayerCmd>, terminate: Arc<AtomicBool>) {
+        self.player_sender
+            .lock()
+            .expect("lock sender")
+            .replace(sender);
+        GpioCommander::run(self, &terminate);
+    }
+}
+
+impl Commander<PlaylistCmd> for GpioCommander {
+    fn run(&mut self, sender: mpsc::Sender<PlaylistCmd>, terminate: Arc<AtomicBool>) {
+        self.playlist_sender
+            .lock()
+            .expect("lock sender")
+            .replace(sender);
+        GpioCommander::run(self, &terminate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Line source replaying batches of events, terminating the commander once exhausted
+    /// and no gesture is pending
+    struct MockLines {
+        offsets: Vec<u32>,
+        values: Vec<bool>,
+        batches: VecDeque<Vec<PinEvent>>,
+        terminate: Arc<AtomicBool>,
+    }
+
+    impl LineSource for MockLines {
+        fn offsets(&self) -> &[u32] {
+            &self.offsets
+        }
+
+        fn values(&mut self) -> Result<Vec<bool>> {
+            Ok(self.values.clone())
+        }
+
+        fn wait(&mut self, timeout: Duration) -> Result<Vec<PinEvent>> {
+            if let Some(events) = self.batches.pop_front() {
+                return Ok(events);
+            }
+            if timeout == IDLE_TIMEOUT {
+                self.terminate.store(true, Ordering::Relaxed);
+            } else {
+                std::thread::sleep(timeout);
+            }
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_update_mapping() {
+        let (pinmap, _) = build_mapping(vec![
+            PinMap::new(18, true, false, PlayerCmd::PlayNext),
+            PinMap::new(23, true, false, PlayerCmd::Pause),
+        ]);
+        let handle = PinMappingHandle {
+            offsets: [18, 23].iter().cloned().collect(),
+            mapping: Arc::new(Mutex::new(pinmap)),
+        };
 
-            std::thread::sleep(POLL_INTERVAL);
+        // Mapping among opened pins can be applied
+        assert!(handle.update(vec![
+            PinMap::new(18, true, false, PlayerCmd::PlayBack),
+            PinMap::new(23, false, true, PlayerCmd::Resume),
+        ]));
+        {
+            let mapping = handle.mapping.lock().unwrap();
+            assert_eq!(2, mapping.commands.len());
+            assert!(matches!(
+                mapping.get(18, true, Gesture::Edge),
+                Some(Command::Player(PlayerCmd::PlayBack))
+            ));
+            assert!(matches!(
+                mapping.get(23, false, Gesture::Edge),
+                Some(Command::Player(PlayerCmd::Resume))
+            ));
         }
+
+        // Mapping containing new pin can't be applied
+        assert!(!handle.update(vec![PinMap::new(24, true, false, PlayerCmd::Mute)]));
+        assert!(handle
+            .mapping
+            .lock()
+            .unwrap()
+            .get(18, true, Gesture::Edge)
+            .is_some());
+    }
+
+    /// Poll the tracker every millisecond until the time, with levels changing
+    /// at each (milliseconds since start, level)
+    fn track(mapping: Vec<PinMap>, changes: &[(u64, bool)], until: u64) -> Vec<String> {
+        let (mapping, _) = build_mapping(mapping);
+        let start = Instant::now();
+        let mut tracker = PinTracker::new(18, false, start);
+        let mut cmds = Vec::new();
+        for millis in 0..=until {
+            let level = changes
+                .iter()
+                .rev()
+                .find(|(at, _)| *at <= millis)
+                .map(|(_, level)| *level)
+                .unwrap_or(false);
+            let now = start + Duration::from_millis(millis);
+            cmds.extend(tracker.update(level, now, &mapping));
+        }
+        cmds.iter().map(|c| format!("{:?}", c)).collect()
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mapping = vec![
+            PinMap::new(18, true, false, PlayerCmd::PlayNext),
+            PinMap::new(18, false, false, PlayerCmd::PlayBack),
+        ];
+        // * Bouncing level within the window doesn't trigger commands
+        let changes = [
+            (0, true),
+            (5, false),
+            (10, true),
+            (100, false),
+            (110, true),
+            (120, false),
+        ];
+        assert_eq!(
+            vec!["Player(PlayNext)", "Player(PlayBack)"],
+            track(mapping, &changes, 200)
+        );
+
+        // * Debounce window is configurable per pin
+        let mapping =
+            vec![PinMap::new(18, true, false, PlayerCmd::PlayNext)
+                .debounce(Duration::from_millis(100))];
+        assert!(track(mapping, &[(0, true), (90, false)], 200).is_empty());
+    }
+
+    #[test]
+    fn test_gestures() {
+        let mapping = || {
+            vec![
+                PinMap::new(18, true, false, PlayerCmd::PlayNext).gesture(Gesture::Short),
+                PinMap::new(18, true, false, PlaylistCmd::Refresh).gesture(Gesture::Long),
+                PinMap::new(18, true, false, PlayerCmd::PlayBack).gesture(Gesture::Double),
+            ]
+        };
+
+        // * Short press is triggered once it turns out not to be a double press
+        let changes = [(0, true), (100, false)];
+        assert!(track(mapping(), &changes, 300).is_empty());
+        assert_eq!(vec!["Player(PlayNext)"], track(mapping(), &changes, 600));
+
+        // * Long press is triggered while being held, without short press on release
+        let changes = [(0, true), (1000, false)];
+        assert_eq!(vec!["Playlist(Refresh)"], track(mapping(), &changes, 900));
+        assert_eq!(vec!["Playlist(Refresh)"], track(mapping(), &changes, 2000));
+
+        // * Double press
+        let changes = [(0, true), (100, false), (250, true), (350, false)];
+        assert_eq!(vec!["Player(PlayBack)"], track(mapping(), &changes, 1000));
+
+        // * Short press is triggered on release without double press mapping
+        let mapping =
+            vec![PinMap::new(18, true, false, PlayerCmd::PlayNext).gesture(Gesture::Short)];
+        assert_eq!(
+            vec!["Player(PlayNext)"],
+            track(mapping, &[(0, true), (100, false)], 200)
+        );
+    }
+
+    #[test]
+    fn test_run_with_events() {
+        let terminate = Arc::new(AtomicBool::new(false));
+        // Events occurred a while ago so that their debounce windows have passed
+        let base = Instant::now() - Duration::from_secs(1);
+        let event = |offset, level, millis| PinEvent {
+            offset,
+            level,
+            at: base + Duration::from_millis(millis),
+        };
+        let lines = MockLines {
+            offsets: vec![18, 23, 24],
+            // Pin 24 differs from its default at start
+            values: vec![false, false, true],
+            batches: vec![
+                // Bouncing press of pin 18 and a whole press of pin 23 read at once
+                vec![
+                    event(18, true, 0),
+                    event(18, false, 5),
+                    event(18, true, 10),
+                    event(23, true, 20),
+                    event(23, false, 120),
+                    event(18, false, 200),
+                ],
+            ]
+            .into(),
+            terminate: Arc::clone(&terminate),
+        };
+        let mut commander = GpioCommander::with_lines(
+            Box::new(lines),
+            vec![
+                PinMap::new(18, true, false, PlayerCmd::PlayNext),
+                PinMap::new(23, true, false, PlaylistCmd::Refresh).gesture(Gesture::Short),
+                PinMap::new(24, true, false, PlayerCmd::Mute),
+            ],
+        );
+        let (player_tx, player_rx) = mpsc::channel();
+        let (playlist_tx, playlist_rx) = mpsc::channel();
+        commander
+            .playlist_sender
+            .lock()
+            .unwrap()
+            .replace(playlist_tx);
+        Commander::<PlayerCmd>::run(&mut commander, player_tx, terminate);
+
+        let player_cmds: Vec<_> = player_rx.try_iter().map(|c| format!("{:?}", c)).collect();
+        assert_eq!(vec!["PlayNext", "Mute"], player_cmds);
+        let playlist_cmds: Vec<_> = playlist_rx.try_iter().map(|c| format!("{:?}", c)).collect();
+        assert_eq!(vec!["Refresh"], playlist_cmds);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let (mapping, _) = build_mapping(vec![
+            PinMap::new(18, true, false, PlayerCmd::PlayNext).gesture(Gesture::Short),
+            PinMap::new(18, true, false, PlaylistCmd::Refresh).gesture(Gesture::Long),
+        ]);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut tracker = PinTracker::new(18, false, start);
+        assert_eq!(None, tracker.deadline(&mapping));
+
+        tracker.update(true, at(0), &mapping);
+        assert_eq!(Some(at(30)), tracker.deadline(&mapping));
+        tracker.advance(at(30), &mapping);
+        assert_eq!(Some(at(830)), tracker.deadline(&mapping));
+        assert_eq!(1, tracker.advance(at(830), &mapping).len());
+        assert_eq!(None, tracker.deadline(&mapping));
+    }
+
+    #[test]
+    fn test_event_instant() {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+        let monotonic = ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+        let now = Instant::now();
+        let at = event_instant(monotonic - 100_000_000, now);
+        let age = now - at;
+        assert!(age >= Duration::from_millis(100) && age < Duration::from_millis(200));
     }
 }
@@ -1,81 +1,240 @@
+use crate::album::MediaType;
 use crate::control::{Commander, PlayerCmd, PlaylistCmd};
+use crate::metrics::METRICS;
+use crate::slideshow::{ItemStatus, StatusSource};
+use chrono::{DateTime, Local};
+use log::warn;
 use rouille;
 use rouille::router;
+use serde::Serialize;
+use serde_json::json;
+use std::fs::File;
 use std::io;
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Clone)]
+type SharedSender<C> = Arc<Mutex<Option<mpsc::Sender<C>>>>;
+
+/// Page controlling the slideshow from browsers, served at `GET /`
+const UI_HTML: &str = include_str!("http_ui.html");
+/// Duration in seconds to let browsers cache media files, whose URL contains the item ID
+const MEDIA_CACHE_SECS: u64 = 3600;
+
+#[derive(Serialize, Debug)]
+struct CommandResponse<'a> {
+    command: &'a str,
+    accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistEntry<'a> {
+    index: usize,
+    id: &'a str,
+    media_type: MediaType,
+    /// Time the item was created in RFC3339
+    created_time: Option<String>,
+    description: Option<&'a str>,
+    filename: Option<&'a str>,
+}
+
+impl<'a> PlaylistEntry<'a> {
+    fn new(index: usize, item: &'a ItemStatus) -> Self {
+        PlaylistEntry {
+            index,
+            id: &item.id,
+            media_type: item.media_type,
+            created_time: item
+                .meta
+                .created_time
+                .map(|t| DateTime::<Local>::from(t).to_rfc3339()),
+            description: item.meta.description.as_deref(),
+            filename: item.meta.filename.as_deref(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct HttpCommander {
+    host: String,
     http_port: u32,
-    playlist_sender: Arc<Mutex<Option<mpsc::Sender<PlaylistCmd>>>>,
-    player_sender: Arc<Mutex<Option<mpsc::Sender<PlayerCmd>>>>,
+    playlist_sender: SharedSender<PlaylistCmd>,
+    player_sender: SharedSender<PlayerCmd>,
+    status_source: Option<Arc<dyn StatusSource>>,
     started: bool,
 }
 
 impl HttpCommander {
     pub fn new(http_port: u32) -> Self {
         Self {
+            host: "localhost".to_string(),
             http_port,
             playlist_sender: Arc::new(Mutex::new(None)),
             player_sender: Arc::new(Mutex::new(None)),
+            status_source: None,
             started: false,
         }
     }
 
-    fn with_sender<C, F>(sender: &Mutex<Option<mpsc::Sender<C>>>, handler: F) -> rouille::Response
+    /// Set the host to listen on, e.g. 0.0.0.0 to open the web UI to other devices
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Set the source of the state returned by `GET /status` and `GET /playlist`
+    pub fn status_source<S: StatusSource + 'static>(mut self, source: S) -> Self {
+        self.status_source = Some(Arc::new(source));
+        self
+    }
+
+    fn command_response(name: &str, status: u16, error: Option<&'static str>) -> rouille::Response {
+        rouille::Response::json(&CommandResponse {
+            command: name,
+            accepted: error.is_none(),
+            error,
+        })
+        .with_status_code(status)
+    }
+
+    /// Send a command parsed from given name through the sender.
+    ///
+    /// Commands are processed asynchronously, so the response only tells
+    /// whether the command is accepted or not.
+    fn send_command<C, F>(
+        sender: &Mutex<Option<mpsc::Sender<C>>>,
+        name: &str,
+        parse: F,
+    ) -> rouille::Response
     where
-        F: Fn(&mpsc::Sender<C>) -> rouille::Response,
+        F: Fn(&str) -> Option<C>,
     {
-        if let Some(sender_locked) = sender.lock().expect("lock sender").as_ref() {
-            handler(sender_locked)
-        } else {
-            rouille::Response::empty_404()
+        let cmd = match parse(name) {
+            Some(cmd) => cmd,
+            None => return Self::command_response(name, 404, Some("unknown command")),
+        };
+
+        match sender.lock().expect("lock sender").as_ref() {
+            Some(sender) => match sender.send(cmd) {
+                Ok(()) => {
+                    METRICS.commands.inc("http");
+                    Self::command_response(name, 202, None)
+                }
+                Err(_) => {
+                    warn!("Command channel is closed, rejecting {}", name);
+                    Self::command_response(name, 503, Some("command channel closed"))
+                }
+            },
+            None => Self::command_response(name, 503, Some("not ready")),
+        }
+    }
+
+    /// Serve the original file of the playlist item at the index.
+    ///
+    /// The ID is required so that another item taking the index after the playlist
+    /// changes isn't served and cached under the URL.
+    fn media_response(
+        status_source: &dyn StatusSource,
+        index: usize,
+        id: Option<&str>,
+    ) -> rouille::Response {
+        let path = match id.and_then(|id| status_source.media_file(index, id)) {
+            Some(path) => path,
+            None => return rouille::Response::empty_404(),
+        };
+        match File::open(&path) {
+            Ok(file) => {
+                let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                rouille::Response::from_file(rouille::extension_to_mime(ext), file)
+                    .with_public_cache(MEDIA_CACHE_SECS)
+            }
+            Err(e) => {
+                warn!("Failed to open media file {}: {}", path.display(), e);
+                rouille::Response::empty_404()
+            }
         }
     }
 
+    // Parameterized routes of rouille's router! macro expand to a manual prefix strip
+    #[allow(clippy::manual_strip)]
+    fn handle(
+        request: &rouille::Request,
+        playlist_sender: &Mutex<Option<mpsc::Sender<PlaylistCmd>>>,
+        player_sender: &Mutex<Option<mpsc::Sender<PlayerCmd>>>,
+        status_source: Option<&dyn StatusSource>,
+    ) -> rouille::Response {
+        router!(
+            request,
+            (GET) (/) => {
+                rouille::Response::html(UI_HTML)
+            },
+            (GET) (/commands) => {
+                rouille::Response::json(&json!({
+                    "player": PlayerCmd::NAMES,
+                    "playlist": PlaylistCmd::NAMES,
+                }))
+            },
+            (GET) (/playlist) => {
+                match status_source {
+                    Some(source) => {
+                        let playlist = source.playlist();
+                        let entries: Vec<_> = playlist
+                            .iter()
+                            .enumerate()
+                            .map(|(i, item)| PlaylistEntry::new(i, item))
+                            .collect();
+                        rouille::Response::json(&entries)
+                    }
+                    None => rouille::Response::empty_404(),
+                }
+            },
+            (GET) (/playlist/{index: usize}/media) => {
+                match status_source {
+                    Some(source) => {
+                        Self::media_response(source, index, request.get_param("id").as_deref())
+                    }
+                    None => rouille::Response::empty_404(),
+                }
+            },
+            (GET) (/metrics) => {
+                rouille::Response::from_data("text/plain; version=0.0.4", METRICS.render())
+            },
+            (GET) (/status) => {
+                match status_source {
+                    Some(source) => rouille::Response::json(&source.status()),
+                    None => rouille::Response::empty_404(),
+                }
+            },
+            (POST) (/playlist/{name: String}) => {
+                Self::send_command(playlist_sender, &name, PlaylistCmd::from_name)
+            },
+            (POST) (/player/{name: String}) => {
+                Self::send_command(player_sender, &name, PlayerCmd::from_name)
+            },
+            _ => rouille::Response::empty_404()
+        )
+    }
+
     fn run(&mut self) {
         if self.started {
             return;
         }
         self.started = true;
 
-        let listen_addr = format!("localhost:{}", self.http_port);
+        let listen_addr = format!("{}:{}", self.host, self.http_port);
         let playlist_sender = Arc::clone(&self.playlist_sender);
         let player_sender = Arc::clone(&self.player_sender);
+        let status_source = self.status_source.clone();
 
         rouille::start_server(listen_addr, move |request| {
             rouille::log(&request, io::stdout(), || {
-                router!(
+                Self::handle(
                     request,
-                    // Playlist commands
-                    (POST) (/playlist/update) => {
-                        Self::with_sender(&playlist_sender, |sender| {
-                            sender.send(PlaylistCmd::Update).expect("Sender::send playlist");
-                            rouille::Response::text("Update requested")
-                        })
-                    },
-                    (POST) (/playlist/refresh) => {
-                        Self::with_sender(&playlist_sender, |sender| {
-                        sender.send(PlaylistCmd::Refresh).expect("Sender::send playlist");
-                            rouille::Response::text("Refresh requested")
-                        })
-                    },
-                    // Player commands
-                    (POST) (/player/sleep) => {
-                        Self::with_sender(&player_sender, |sender| {
-                            sender.send(PlayerCmd::Sleep).expect("Sender::send player");
-                            rouille::Response::text("Player slept")
-                        })
-                    },
-                    (POST) (/player/wakeup) => {
-                        Self::with_sender(&player_sender, |sender| {
-                            sender.send(PlayerCmd::Wakeup).expect("Sender::send player");
-                            rouille::Response::text("Player woke up")
-                        })
-                    },
-                    _ => rouille::Response::empty_404()
+                    &playlist_sender,
+                    &player_sender,
+                    status_source.as_ref().map(|s| s.as_ref()),
                 )
             })
         });
@@ -109,3 +268,166 @@ impl Commander<PlayerCmd> for HttpCommander {
         self.run();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slideshow::MockStatusSource;
+    use serde_json::{self, Value};
+    use std::io::Read;
+
+    fn request(commander: &HttpCommander, method: &str, url: &str) -> (u16, Value) {
+        let request = rouille::Request::fake_http(method, url, vec![], vec![]);
+        let resp = HttpCommander::handle(
+            &request,
+            &commander.playlist_sender,
+            &commander.player_sender,
+            commander.status_source.as_ref().map(|s| s.as_ref()),
+        );
+        let mut body = String::new();
+        let (mut reader, _) = resp.data.into_reader_and_size();
+        reader.read_to_string(&mut body).unwrap();
+        let json = serde_json::from_str(&body).unwrap_or(Value::Null);
+        (resp.status_code, json)
+    }
+
+    fn post(commander: &HttpCommander, url: &str) -> (u16, Value) {
+        request(commander, "POST", url)
+    }
+
+    #[test]
+    fn test_player_commands() {
+        let commander = HttpCommander::new(0);
+        let (sender, receiver) = mpsc::channel();
+        commander.player_sender.lock().unwrap().replace(sender);
+
+        for name in &[
+            "play_next",
+            "play_back",
+            "pause",
+            "resume",
+            "mute",
+            "unmute",
+        ] {
+            let (status, body) = post(&commander, &format!("/player/{}", name));
+            assert_eq!(202, status);
+            assert_eq!(json!({"command": name, "accepted": true}), body);
+        }
+        assert_eq!(6, receiver.try_iter().count());
+
+        let (status, body) = post(&commander, "/player/dance");
+        assert_eq!(404, status);
+        assert_eq!(json!(false), body["accepted"]);
+    }
+
+    #[test]
+    fn test_playlist_commands() {
+        let commander = HttpCommander::new(0);
+        // Commander has not started yet
+        let (status, body) = post(&commander, "/playlist/refresh");
+        assert_eq!(503, status);
+        assert_eq!(json!(false), body["accepted"]);
+
+        let (sender, receiver) = mpsc::channel();
+        commander.playlist_sender.lock().unwrap().replace(sender);
+        let (status, _) = post(&commander, "/playlist/update");
+        assert_eq!(202, status);
+        assert!(receiver.try_recv().is_ok());
+
+        // Commands must be rejected rather than panicking once receiver is gone
+        drop(receiver);
+        let (status, body) = post(&commander, "/playlist/refresh");
+        assert_eq!(503, status);
+        assert_eq!(json!("command channel closed"), body["error"]);
+    }
+
+    #[test]
+    fn test_status() {
+        let (status, _) = request(&HttpCommander::new(0), "GET", "/status");
+        assert_eq!(404, status);
+
+        let commander = HttpCommander::new(0).status_source(MockStatusSource);
+        let (status, body) = request(&commander, "GET", "/status");
+        assert_eq!(200, status);
+        assert_eq!(json!(3), body["playlist_size"]);
+        assert_eq!(json!(true), body["player"]["paused"]);
+        assert_eq!(json!(null), body["player"]["current"]);
+        assert_eq!(json!({"using": 10, "capacity": 100}), body["storage"]);
+        assert_eq!(json!("2020-01-01T00:00:00+09:00"), body["last_refresh"]);
+    }
+
+    #[test]
+    fn test_ui() {
+        let commander = HttpCommander::new(0);
+        let (status, _) = request(&commander, "GET", "/");
+        assert_eq!(200, status);
+
+        // * Buttons are made for every command
+        let (status, body) = request(&commander, "GET", "/commands");
+        assert_eq!(200, status);
+        assert_eq!(json!(PlayerCmd::NAMES), body["player"]);
+        assert_eq!(json!(PlaylistCmd::NAMES), body["playlist"]);
+        for name in PlayerCmd::NAMES {
+            assert!(PlayerCmd::from_name(name).is_some());
+        }
+        for name in PlaylistCmd::NAMES {
+            assert!(PlaylistCmd::from_name(name).is_some());
+        }
+    }
+
+    #[test]
+    fn test_playlist() {
+        let (status, _) = request(&HttpCommander::new(0), "GET", "/playlist");
+        assert_eq!(404, status);
+
+        let commander = HttpCommander::new(0).status_source(MockStatusSource);
+        let (status, body) = request(&commander, "GET", "/playlist");
+        assert_eq!(200, status);
+        assert_eq!(1, body.as_array().unwrap().len());
+        assert_eq!(json!(0), body[0]["index"]);
+        assert_eq!(json!("a"), body[0]["id"]);
+        assert_eq!(json!("PHOTO"), body[0]["media_type"]);
+        assert_eq!(json!("beach"), body[0]["description"]);
+        assert!(body[0]["created_time"].is_string());
+
+        let (status, _) = request(&commander, "GET", "/playlist/0/media?id=a");
+        assert_eq!(200, status);
+        // * Missing files and indices out of the playlist aren't found
+        let (status, _) = request(&commander, "GET", "/playlist/1/media?id=b");
+        assert_eq!(404, status);
+        let (status, _) = request(&commander, "GET", "/playlist/2/media?id=c");
+        assert_eq!(404, status);
+        // * Items replaced by another playlist aren't found by the old ID
+        let (status, _) = request(&commander, "GET", "/playlist/0/media?id=b");
+        assert_eq!(404, status);
+        let (status, _) = request(&commander, "GET", "/playlist/0/media");
+        assert_eq!(404, status);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let commander = HttpCommander::new(0);
+        let (sender, _receiver) = mpsc::channel();
+        commander.player_sender.lock().unwrap().replace(sender);
+        post(&commander, "/player/pause");
+
+        let request = rouille::Request::fake_http("GET", "/metrics", vec![], vec![]);
+        let resp = HttpCommander::handle(
+            &request,
+            &commander.playlist_sender,
+            &commander.player_sender,
+            None,
+        );
+        assert_eq!(200, resp.status_code);
+        let mut body = String::new();
+        resp.data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        // Other tests may send commands concurrently, so the count isn't checked
+        assert!(body
+            .lines()
+            .any(|l| l.starts_with("phoseum_commands_total{commander=\"http\"}")));
+    }
+}
@@ -1,19 +1,31 @@
 pub mod album;
+pub mod calendar;
+pub mod composite;
 pub mod console_control;
 pub mod control;
+pub mod display;
 pub mod googlephotos;
 pub mod gpio_control;
 pub mod http_control;
+pub mod localfs;
+pub mod metrics;
+pub mod mqtt;
+pub mod mqtt_control;
 pub mod oauth;
 pub mod player;
+pub mod player_mpv;
 pub mod player_vlc;
 pub mod playlist;
+pub mod presence_control;
+pub mod schedule_control;
+pub mod signal_control;
 pub mod slideshow;
 pub mod storage;
 
 use album::Album;
 use control::{Commander, PlayerCmd, PlaylistCmd};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use metrics::METRICS;
 use player::Player;
 use slideshow::Slideshow;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -25,21 +37,64 @@ use std::time::{Duration, SystemTime};
 const POLL_TIMEOUT: Duration = Duration::from_millis(300);
 const HEALTHCHECK_INTERVAL: Duration = Duration::from_secs(60);
 
-pub struct Phoseum<P: Player + Send + 'static, A: Album> {
+/// Policy of restarting the player found not working by healthcheck
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Number of consecutive restarts to attempt before giving up.
+    /// The count is reset once the player passes a healthcheck.
+    pub max_restarts: u32,
+    /// Delay before the first restart, doubled on each consecutive restart
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Return the delay before the restart following the given number of
+    /// consecutive restarts, or None if no more restarts should be attempted.
+    fn backoff(&self, restarts: u32) -> Option<Duration> {
+        if restarts >= self.max_restarts {
+            return None;
+        }
+        let backoff = self
+            .initial_backoff
+            .checked_mul(2u32.checked_pow(restarts).unwrap_or(u32::MAX))
+            .unwrap_or(self.max_backoff);
+        Some(backoff.min(self.max_backoff))
+    }
+}
+
+pub struct Phoseum<P: Player + Send + 'static, A: Album + 'static> {
     slideshow: Slideshow<P, A>,
     pl_commanders: Vec<Box<dyn Commander<PlaylistCmd> + Send + 'static>>,
     player_commanders: Vec<Box<dyn Commander<PlayerCmd> + Send + 'static>>,
+    restart_policy: RestartPolicy,
 }
 
-impl<P: Player + Send + 'static, A: Album> Phoseum<P, A> {
+impl<P: Player + Send + 'static, A: Album + 'static> Phoseum<P, A> {
     pub fn new(slideshow: Slideshow<P, A>) -> Self {
         Self {
             slideshow,
             pl_commanders: Vec::new(),
             player_commanders: Vec::new(),
+            restart_policy: RestartPolicy::default(),
         }
     }
 
+    pub fn restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
     pub fn add_playlist_commander<C>(&mut self, commander: C)
     where
         C: Commander<PlaylistCmd> + Send + 'static,
@@ -112,16 +167,52 @@ impl<P: Player + Send + 'static, A: Album> Phoseum<P, A> {
         }
 
         let mut last_healthcheck = SystemTime::now();
+        let mut restarts = 0;
+        let mut restart_at = None;
         while !terminate.load(Ordering::Relaxed) {
             let now = SystemTime::now();
-            if now.duration_since(last_healthcheck).unwrap() >= HEALTHCHECK_INTERVAL {
-                if !self.slideshow.is_player_ok() {
-                    error!("Player seems to be not working well, aborting");
-                    terminate.store(true, Ordering::Relaxed);
-                    break;
+            let mut player_failed = false;
+            if restart_at.is_none()
+                && now.duration_since(last_healthcheck).unwrap() >= HEALTHCHECK_INTERVAL
+            {
+                if self.slideshow.is_player_ok() {
+                    METRICS.player_healthchecks.inc("ok");
+                    restarts = 0;
+                } else {
+                    METRICS.player_healthchecks.inc("failed");
+                    player_failed = true;
                 }
                 last_healthcheck = now;
             }
+            if restart_at.map(|at| now >= at).unwrap_or(false) {
+                restart_at = None;
+                restarts += 1;
+                last_healthcheck = now;
+                METRICS.player_restarts.inc();
+                if let Err(e) = self.slideshow.restart_player() {
+                    error!("Failed to restart player: {:?}", e);
+                    player_failed = true;
+                }
+            }
+            if player_failed {
+                match self.restart_policy.backoff(restarts) {
+                    Some(backoff) => {
+                        warn!(
+                            "Player seems to be not working well, restarting in {:?}",
+                            backoff
+                        );
+                        restart_at = Some(now + backoff);
+                    }
+                    None => {
+                        error!(
+                            "Player seems to be not working well after {} restarts, aborting",
+                            restarts
+                        );
+                        terminate.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
 
             match pl_recv.recv_timeout(POLL_TIMEOUT) {
                 Ok(cmd) => {
@@ -137,6 +228,16 @@ impl<P: Player + Send + 'static, A: Album> Phoseum<P, A> {
                     }
                 },
             }
+
+            // Items of new playlist are prepared in background, pass those ready to the player
+            if let Err(e) = self.slideshow.poll_prefetch() {
+                error!("Error preparing playlist items: {:?}", e);
+            }
+        }
+
+        // Resume from the current item on the next run
+        if let Err(e) = self.slideshow.save_playlist() {
+            error!("Failed to save playlist: {:?}", e);
         }
 
         info!("Waiting all threads to terminate...");
@@ -147,3 +248,35 @@ impl<P: Player + Send + 'static, A: Album> Phoseum<P, A> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_backoff() {
+        let policy = RestartPolicy {
+            max_restarts: 4,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+        };
+        // * Backoff should be doubled up to the max
+        // * No more restarts should be attempted after max restarts
+        assert_eq!(
+            vec![
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(3)),
+                Some(Duration::from_secs(3)),
+                None,
+            ],
+            (0..5).map(|i| policy.backoff(i)).collect::<Vec<_>>()
+        );
+
+        let policy = RestartPolicy {
+            max_restarts: u32::MAX,
+            ..RestartPolicy::default()
+        };
+        assert_eq!(Some(policy.max_backoff), policy.backoff(100));
+    }
+}
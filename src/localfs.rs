@@ -0,0 +1,584 @@
+use crate::album::{self, Album, AlbumItem, MediaType};
+use chrono::{FixedOffset, Local, NaiveDate, TimeZone};
+use exif::{In, Tag};
+use failure::Fail;
+use log::debug;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Number of leading bytes to read for detecting media type by magic bytes
+const MAGIC_BYTES_LEN: usize = 12;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    /// Album root directory is not accessible
+    #[fail(display = "Cannot read album directory {:?}: {}", path, cause)]
+    RootDir {
+        path: PathBuf,
+        #[fail(cause)]
+        cause: io::Error,
+    },
+    /// Failure in accessing a file or a subdirectory under the album
+    #[fail(display = "IO error on {:?}: {}", path, cause)]
+    IO {
+        path: PathBuf,
+        #[fail(cause)]
+        cause: io::Error,
+    },
+}
+
+impl album::Error for Error {
+    fn is_fatal(&self) -> bool {
+        match self {
+            Error::RootDir { .. } => true,
+            Error::IO { .. } => false,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn io_error<P: Into<PathBuf>>(path: P) -> impl FnOnce(io::Error) -> Error {
+    let path = path.into();
+    move |cause| Error::IO { path, cause }
+}
+
+/// Album backed by a local directory tree, such as a NAS mount or an USB stick.
+///
+/// All files under the root directory are listed recursively, except hidden
+/// ones and those which don't look like a photo or a video.
+pub struct LocalAlbum {
+    root: PathBuf,
+    hard_link: bool,
+}
+
+impl LocalAlbum {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        LocalAlbum {
+            root: root.into(),
+            hard_link: true,
+        }
+    }
+
+    /// Whether to prepare items by hard-linking instead of copying.
+    ///
+    /// Copying is used as a fallback when hard-linking fails, e.g, when the
+    /// album and the storage are on different filesystems.
+    pub fn hard_link(mut self, hard_link: bool) -> Self {
+        self.hard_link = hard_link;
+        self
+    }
+}
+
+impl Album for LocalAlbum {
+    type E = Error;
+    type Item = LocalAlbumItem;
+    type Items = LocalAlbumItems;
+
+    fn items(&self) -> Self::Items {
+        LocalAlbumItems {
+            root: self.root.clone(),
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    fn prepare_item<P: AsRef<Path>>(&self, item: &Self::Item, path: P) -> Result<()> {
+        let path = path.as_ref();
+        // Hard-linking refuses to overwrite leftovers from the previous attempt
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(io_error(path)(e));
+            }
+        }
+
+        if self.hard_link {
+            match fs::hard_link(&item.source, path) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!(
+                    "Failed to hard link {}, falling back to copy: {}",
+                    item.source.display(),
+                    e
+                ),
+            }
+        }
+        fs::copy(&item.source, path).map_err(io_error(&item.source))?;
+        Ok(())
+    }
+}
+
+pub struct LocalAlbumItems {
+    root: PathBuf,
+    /// Entries of directories being traversed, sorted in reverse order to pop
+    stack: Vec<Vec<PathBuf>>,
+    started: bool,
+}
+
+impl LocalAlbumItems {
+    fn read_dir_sorted(path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = Vec::new();
+        for dentry in fs::read_dir(path)? {
+            let path = dentry?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with('.'))
+                .unwrap_or(false);
+            if !hidden {
+                entries.push(path);
+            }
+        }
+        entries.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(entries)
+    }
+
+    fn visit(&mut self, path: PathBuf) -> Result<Option<LocalAlbumItem>> {
+        // Do not follow symlinks to directories as it could make loops
+        if fs::symlink_metadata(&path)
+            .map_err(io_error(&path))?
+            .is_dir()
+        {
+            self.stack
+                .push(Self::read_dir_sorted(&path).map_err(io_error(&path))?);
+            return Ok(None);
+        }
+
+        let meta = fs::metadata(&path).map_err(io_error(&path))?;
+        if !meta.is_file() {
+            return Ok(None);
+        }
+        LocalAlbumItem::new(&self.root, path, &meta)
+    }
+}
+
+impl Iterator for LocalAlbumItems {
+    type Item = Result<LocalAlbumItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            match Self::read_dir_sorted(&self.root) {
+                Ok(entries) => self.stack.push(entries),
+                Err(cause) => {
+                    return Some(Err(Error::RootDir {
+                        path: self.root.clone(),
+                        cause,
+                    }))
+                }
+            }
+        }
+
+        loop {
+            let path = match self.stack.last_mut()?.pop() {
+                Some(path) => path,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            match self.visit(path) {
+                Ok(Some(item)) => return Some(Ok(item)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub struct LocalAlbumItem {
+    id: String,
+    path: PathBuf,
+    source: PathBuf,
+    media_type: MediaType,
+    created_time: SystemTime,
+    duration: Option<Duration>,
+}
+
+impl LocalAlbumItem {
+    fn new(root: &Path, source: PathBuf, meta: &fs::Metadata) -> Result<Option<LocalAlbumItem>> {
+        let media_type = match Self::media_type(&source).map_err(io_error(&source))? {
+            Some(media_type) => media_type,
+            None => {
+                debug!("Skipping non-media file: {}", source.display());
+                return Ok(None);
+            }
+        };
+        let id = source
+            .strip_prefix(root)
+            .expect("path under album root")
+            .to_string_lossy()
+            .into_owned();
+        let created_time = match media_type {
+            MediaType::PHOTO => Self::exif_time(&source),
+            MediaType::VIDEO => None,
+        };
+        let created_time = match created_time {
+            Some(t) => t,
+            None => meta.modified().map_err(io_error(&source))?,
+        };
+        let duration = match media_type {
+            MediaType::PHOTO => None,
+            MediaType::VIDEO => Self::mp4_duration(&source),
+        };
+
+        Ok(Some(LocalAlbumItem {
+            path: Self::storage_filename(&id),
+            id,
+            source,
+            media_type,
+            created_time,
+            duration,
+        }))
+    }
+
+    /// Return the filename to store an item given its relative path in album.
+    ///
+    /// Storage only accepts plain filenames, so path separators are escaped
+    /// in the way that never causes collisions.
+    fn storage_filename(id: &str) -> PathBuf {
+        let mut name = String::with_capacity(id.len());
+        for c in id.chars() {
+            match c {
+                '%' => name.push_str("%25"),
+                '/' => name.push_str("%2F"),
+                c => name.push(c),
+            }
+        }
+        PathBuf::from(name)
+    }
+
+    fn media_type(path: &Path) -> io::Result<Option<MediaType>> {
+        let by_ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::media_type_by_extension);
+        if by_ext.is_some() {
+            return Ok(by_ext);
+        }
+
+        let mut header = Vec::with_capacity(MAGIC_BYTES_LEN);
+        File::open(path)?
+            .take(MAGIC_BYTES_LEN as u64)
+            .read_to_end(&mut header)?;
+        Ok(Self::media_type_by_magic(&header))
+    }
+
+    fn media_type_by_extension(ext: &str) -> Option<MediaType> {
+        match ext.to_ascii_lowercase().as_ref() {
+            "jpg" | "jpeg" | "png" | "apng" | "gif" | "svg" | "heif" | "heic" | "webp" => {
+                Some(MediaType::PHOTO)
+            }
+            "webm" | "ogg" | "ogv" | "mp4" | "m4v" | "mov" | "mkv" => Some(MediaType::VIDEO),
+            _ => None,
+        }
+    }
+
+    fn media_type_by_magic(header: &[u8]) -> Option<MediaType> {
+        if header.starts_with(b"\xFF\xD8\xFF")
+            || header.starts_with(b"\x89PNG")
+            || header.starts_with(b"GIF8")
+            || (header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP"))
+        {
+            return Some(MediaType::PHOTO);
+        }
+        if header.starts_with(b"\x1A\x45\xDF\xA3") || header.starts_with(b"OggS") {
+            return Some(MediaType::VIDEO);
+        }
+        // ISO base media file format, which is used by both HEIF and MP4
+        if header.get(4..8) == Some(b"ftyp") {
+            return match &header[8..] {
+                b"heic" | b"heix" | b"mif1" | b"msf1" => Some(MediaType::PHOTO),
+                _ => Some(MediaType::VIDEO),
+            };
+        }
+        None
+    }
+
+    /// Return the playback duration of an ISO base media file such as MP4 and MOV,
+    /// recorded in its `mvhd` box.
+    fn mp4_duration(path: &Path) -> Option<Duration> {
+        let mut reader = BufReader::new(File::open(path).ok()?);
+        let moov_len = Self::find_box(&mut reader, b"moov", u64::MAX)?;
+        Self::find_box(&mut reader, b"mvhd", moov_len)?;
+
+        let mut version = [0; 4];
+        reader.read_exact(&mut version).ok()?;
+        // Creation and modification times are followed by the timescale and the duration,
+        // each of which is 64 bits in version 1 except for the timescale
+        let (timescale, duration) = if version[0] == 1 {
+            let mut fields = [0; 28];
+            reader.read_exact(&mut fields).ok()?;
+            (be_uint(&fields[16..20]), be_uint(&fields[20..28]))
+        } else {
+            let mut fields = [0; 16];
+            reader.read_exact(&mut fields).ok()?;
+            (be_uint(&fields[8..12]), be_uint(&fields[12..16]))
+        };
+        if timescale == 0 {
+            debug!("Invalid timescale in {}", path.display());
+            return None;
+        }
+        Some(
+            Duration::from_secs(duration / timescale)
+                + Duration::from_nanos(duration % timescale * 1_000_000_000 / timescale),
+        )
+    }
+
+    /// Move the reader to the content of the first box of the type within `limit` bytes,
+    /// returning the length of the content.
+    fn find_box<R: Read + Seek>(reader: &mut R, box_type: &[u8; 4], limit: u64) -> Option<u64> {
+        let mut pos = 0;
+        while pos < limit {
+            let mut header = [0; 8];
+            reader.read_exact(&mut header).ok()?;
+            let mut header_len = 8;
+            let size = match be_uint(&header[0..4]) {
+                // Size in 64 bits follows the type
+                1 => {
+                    let mut large_size = [0; 8];
+                    reader.read_exact(&mut large_size).ok()?;
+                    header_len = 16;
+                    be_uint(&large_size)
+                }
+                // Box extends to the end
+                0 => limit - pos,
+                size => size,
+            };
+            if size < header_len {
+                return None;
+            }
+            if &header[4..8] == box_type {
+                return Some(size - header_len);
+            }
+            reader
+                .seek(SeekFrom::Current((size - header_len) as i64))
+                .ok()?;
+            pos += size;
+        }
+        None
+    }
+
+    /// Return the time when the photo was taken, recorded in its EXIF.
+    fn exif_time(path: &Path) -> Option<SystemTime> {
+        let mut reader = BufReader::new(File::open(path).ok()?);
+        let exif = match exif::Reader::new().read_from_container(&mut reader) {
+            Ok(exif) => exif,
+            Err(e) => {
+                debug!("No EXIF available in {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let (field, offset_tag) = match exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
+            Some(field) => (field, Tag::OffsetTimeOriginal),
+            None => (exif.get_field(Tag::DateTime, In::PRIMARY)?, Tag::OffsetTime),
+        };
+        let mut dt = match field.value {
+            exif::Value::Ascii(ref vals) if !vals.is_empty() => {
+                exif::DateTime::from_ascii(&vals[0]).ok()?
+            }
+            _ => return None,
+        };
+        if let Some(field) = exif.get_field(offset_tag, In::PRIMARY) {
+            if let exif::Value::Ascii(ref vals) = field.value {
+                if let Some(val) = vals.first() {
+                    let _ = dt.parse_offset(val);
+                }
+            }
+        }
+
+        let naive = NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?
+            .and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into())?;
+        // EXIF time is in local time of the place where the photo was taken
+        // and most cameras don't record its offset
+        match dt.offset {
+            Some(offset) => FixedOffset::east_opt(i32::from(offset) * 60)?
+                .from_local_datetime(&naive)
+                .single()
+                .map(SystemTime::from),
+            None => Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(SystemTime::from),
+        }
+    }
+}
+
+impl AlbumItem for LocalAlbumItem {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn media_type(&self) -> MediaType {
+        self.media_type
+    }
+
+    fn created_time(&self) -> SystemTime {
+        self.created_time
+    }
+
+    fn filename(&self) -> Option<&str> {
+        self.source.file_name().and_then(|name| name.to_str())
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+/// Return the value of big-endian unsigned integer
+fn be_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |n, &b| n << 8 | u64::from(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(content).unwrap();
+    }
+
+    fn ids(album: &LocalAlbum) -> Vec<String> {
+        album
+            .items()
+            .map(|item| item.unwrap().id().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_items() {
+        let dir = tempfile::tempdir().unwrap();
+        create_file(dir.path(), "b.jpg", b"");
+        create_file(dir.path(), "a/c.mp4", b"");
+        create_file(dir.path(), "a/d/e.PNG", b"");
+        // Hidden files and non-media files must be skipped
+        create_file(dir.path(), ".hidden.jpg", b"");
+        create_file(dir.path(), ".thumbs/f.jpg", b"");
+        create_file(dir.path(), "notes.txt", b"hello");
+
+        let album = LocalAlbum::new(dir.path());
+        assert_eq!(vec!["a/c.mp4", "a/d/e.PNG", "b.jpg"], ids(&album));
+
+        let items: Vec<_> = album.items().map(Result::unwrap).collect();
+        assert_eq!(MediaType::VIDEO, items[0].media_type());
+        assert_eq!(MediaType::PHOTO, items[1].media_type());
+        assert_eq!(Path::new("a%2Fd%2Fe.PNG"), items[1].path());
+    }
+
+    #[test]
+    fn test_items_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let album = LocalAlbum::new(dir.path().join("missing"));
+        let mut items = album.items();
+        match items.next() {
+            Some(Err(e)) => assert!(album::Error::is_fatal(&e)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn test_media_type_by_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        create_file(dir.path(), "photo", b"\xFF\xD8\xFF\xE0\x00\x10JFIF");
+        create_file(dir.path(), "video", b"\x00\x00\x00\x18ftypmp42");
+        create_file(dir.path(), "heif", b"\x00\x00\x00\x18ftypheic");
+
+        let album = LocalAlbum::new(dir.path());
+        let types: Vec<_> = album
+            .items()
+            .map(|item| item.unwrap().media_type())
+            .collect();
+        assert_eq!(
+            vec![MediaType::PHOTO, MediaType::PHOTO, MediaType::VIDEO],
+            types
+        );
+    }
+
+    /// Return an ISO base media file having `mvhd` box of the version with given fields
+    fn mp4(version: u8, timescale: u32, duration: u32) -> Vec<u8> {
+        let mut mvhd = vec![version, 0, 0, 0];
+        let time_len = if version == 1 { 8 } else { 4 };
+        mvhd.extend(vec![0; time_len * 2]);
+        mvhd.extend(&timescale.to_be_bytes());
+        mvhd.extend(vec![0; time_len - 4]);
+        mvhd.extend(&duration.to_be_bytes());
+
+        let mut content = Vec::new();
+        content.extend(b"\x00\x00\x00\x10ftypmp42\x00\x00\x00\x00");
+        content.extend(b"\x00\x00\x00\x0Cfree\x00\x00\x00\x00");
+        content.extend(&(mvhd.len() as u32 + 16).to_be_bytes());
+        content.extend(b"moov");
+        content.extend(&(mvhd.len() as u32 + 8).to_be_bytes());
+        content.extend(b"mvhd");
+        content.extend(mvhd);
+        content
+    }
+
+    #[test]
+    fn test_video_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        create_file(dir.path(), "a.mp4", &mp4(0, 1000, 12500));
+        create_file(dir.path(), "b.mov", &mp4(1, 600, 1800));
+        create_file(dir.path(), "c.mp4", &mp4(0, 0, 12500));
+        create_file(
+            dir.path(),
+            "d.mp4",
+            b"\x00\x00\x00\x10ftypmp42\x00\x00\x00\x00",
+        );
+        create_file(dir.path(), "e.jpg", b"");
+
+        let album = LocalAlbum::new(dir.path());
+        let durations: Vec<_> = album.items().map(|item| item.unwrap().duration()).collect();
+        assert_eq!(
+            vec![
+                Some(Duration::from_millis(12500)),
+                Some(Duration::from_secs(3)),
+                None,
+                None,
+                None
+            ],
+            durations
+        );
+    }
+
+    #[test]
+    fn test_storage_filename() {
+        assert_eq!(
+            PathBuf::from("a%2Fb.jpg"),
+            LocalAlbumItem::storage_filename("a/b.jpg")
+        );
+        // Must not collide with escaped separators
+        assert_eq!(
+            PathBuf::from("a%252Fb.jpg"),
+            LocalAlbumItem::storage_filename("a%2Fb.jpg")
+        );
+    }
+
+    #[test]
+    fn test_prepare_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = tempfile::tempdir().unwrap();
+        create_file(dir.path(), "a/b.jpg", b"content");
+
+        for &hard_link in &[true, false] {
+            let album = LocalAlbum::new(dir.path()).hard_link(hard_link);
+            let item = album.items().next().unwrap().unwrap();
+            let dest = storage.path().join("dest");
+            album.prepare_item(&item, &dest).unwrap();
+            assert_eq!(b"content".to_vec(), fs::read(&dest).unwrap());
+        }
+    }
+}
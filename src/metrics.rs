@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Metrics of the process, exposed in Prometheus text format by `GET /metrics`
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Gauge(AtomicU64::new(0))
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Number and total duration of observed events
+pub struct Timer {
+    count: AtomicU64,
+    micros: AtomicU64,
+}
+
+impl Timer {
+    const fn new() -> Self {
+        Timer {
+            count: AtomicU64::new(0),
+            micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Values distinguished by a label
+pub struct Family {
+    label: &'static str,
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Family {
+    const fn new(label: &'static str) -> Self {
+        Family {
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, label_value: &str) {
+        *self
+            .values
+            .lock()
+            .expect("lock metrics")
+            .entry(label_value.to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub fn set(&self, label_value: &str, value: u64) {
+        self.values
+            .lock()
+            .expect("lock metrics")
+            .insert(label_value.to_string(), value);
+    }
+
+    /// Replace all values, dropping label values not given
+    pub fn replace<'a, I: IntoIterator<Item = (&'a str, u64)>>(&self, values: I) {
+        *self.values.lock().expect("lock metrics") = values
+            .into_iter()
+            .map(|(label_value, value)| (label_value.to_string(), value))
+            .collect();
+    }
+
+    pub fn get(&self, label_value: &str) -> u64 {
+        self.values
+            .lock()
+            .expect("lock metrics")
+            .get(label_value)
+            .cloned()
+            .unwrap_or(0)
+    }
+}
+
+pub struct Metrics {
+    pub downloads: Counter,
+    pub download_failures: Counter,
+    pub download_bytes: Counter,
+    pub download_duration: Timer,
+    pub gphotos_requests: Counter,
+    pub gphotos_retries: Counter,
+    pub storage_used_bytes: Gauge,
+    pub storage_capacity_bytes: Gauge,
+    pub storage_evictions: Counter,
+    /// Results of player healthchecks by `result` of "ok" or "failed"
+    pub player_healthchecks: Family,
+    pub player_restarts: Counter,
+    /// Commands received by `commander`
+    pub commands: Family,
+    /// Items in the last built playlist by `selector` which chose them
+    pub playlist_items: Family,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Metrics {
+            downloads: Counter::new(),
+            download_failures: Counter::new(),
+            download_bytes: Counter::new(),
+            download_duration: Timer::new(),
+            gphotos_requests: Counter::new(),
+            gphotos_retries: Counter::new(),
+            storage_used_bytes: Gauge::new(),
+            storage_capacity_bytes: Gauge::new(),
+            storage_evictions: Counter::new(),
+            player_healthchecks: Family::new("result"),
+            player_restarts: Counter::new(),
+            commands: Family::new("commander"),
+            playlist_items: Family::new("selector"),
+        }
+    }
+
+    /// Render all metrics in Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let w = &mut out;
+        counter(
+            w,
+            "phoseum_downloads_total",
+            "Media items downloaded",
+            &self.downloads,
+        );
+        counter(
+            w,
+            "phoseum_download_failures_total",
+            "Media items failed to download",
+            &self.download_failures,
+        );
+        counter(
+            w,
+            "phoseum_download_bytes_total",
+            "Bytes of media items downloaded",
+            &self.download_bytes,
+        );
+        timer(
+            w,
+            "phoseum_download_duration_seconds",
+            "Time spent to download media items",
+            &self.download_duration,
+        );
+        counter(
+            w,
+            "phoseum_gphotos_requests_total",
+            "Requests sent to Google Photos API including retries",
+            &self.gphotos_requests,
+        );
+        counter(
+            w,
+            "phoseum_gphotos_retries_total",
+            "Retried requests to Google Photos API",
+            &self.gphotos_retries,
+        );
+        gauge(
+            w,
+            "phoseum_storage_used_bytes",
+            "Bytes used in local storage",
+            &self.storage_used_bytes,
+        );
+        gauge(
+            w,
+            "phoseum_storage_capacity_bytes",
+            "Capacity of local storage in bytes",
+            &self.storage_capacity_bytes,
+        );
+        counter(
+            w,
+            "phoseum_storage_evictions_total",
+            "Files evicted from local storage",
+            &self.storage_evictions,
+        );
+        family(
+            w,
+            "phoseum_player_healthchecks_total",
+            "Player healthchecks by result",
+            "counter",
+            &self.player_healthchecks,
+        );
+        counter(
+            w,
+            "phoseum_player_restarts_total",
+            "Restarts of the player",
+            &self.player_restarts,
+        );
+        family(
+            w,
+            "phoseum_commands_total",
+            "Commands received by commander",
+            "counter",
+            &self.commands,
+        );
+        family(
+            w,
+            "phoseum_playlist_items",
+            "Items in the current playlist by selector",
+            "gauge",
+            &self.playlist_items,
+        );
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {} {}", name, help).expect("write to string");
+    writeln!(out, "# TYPE {} {}", name, kind).expect("write to string");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    header(out, name, help, "counter");
+    writeln!(out, "{} {}", name, counter.get()).expect("write to string");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, gauge: &Gauge) {
+    header(out, name, help, "gauge");
+    writeln!(out, "{} {}", name, gauge.get()).expect("write to string");
+}
+
+fn timer(out: &mut String, name: &str, help: &str, timer: &Timer) {
+    header(out, name, help, "summary");
+    let micros = timer.micros.load(Ordering::Relaxed);
+    writeln!(out, "{}_sum {}", name, micros as f64 / 1_000_000.0).expect("write to string");
+    writeln!(
+        out,
+        "{}_count {}",
+        name,
+        timer.count.load(Ordering::Relaxed)
+    )
+    .expect("write to string");
+}
+
+fn family(out: &mut String, name: &str, help: &str, kind: &str, family: &Family) {
+    header(out, name, help, kind);
+    for (label_value, value) in family.values.lock().expect("lock metrics").iter() {
+        writeln!(
+            out,
+            "{}{{{}=\"{}\"}} {}",
+            name,
+            family.label,
+            escape(label_value),
+            value
+        )
+        .expect("write to string");
+    }
+}
+
+fn escape(label_value: &str) -> String {
+    label_value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.downloads.add(2);
+        metrics.storage_used_bytes.set(100);
+        metrics
+            .download_duration
+            .observe(Duration::from_millis(1500));
+        metrics.commands.inc("http");
+        metrics.commands.inc("http");
+        metrics.commands.inc("gp\"io");
+
+        let text = metrics.render();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines.contains(&"# TYPE phoseum_downloads_total counter"));
+        assert!(lines.contains(&"phoseum_downloads_total 2"));
+        assert!(lines.contains(&"phoseum_storage_used_bytes 100"));
+        assert!(lines.contains(&"phoseum_download_duration_seconds_sum 1.5"));
+        assert!(lines.contains(&"phoseum_download_duration_seconds_count 1"));
+        assert!(lines.contains(&"phoseum_commands_total{commander=\"http\"} 2"));
+        assert!(lines.contains(&"phoseum_commands_total{commander=\"gp\\\"io\"} 1"));
+        assert!(lines.contains(&"# TYPE phoseum_playlist_items gauge"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Minimal MQTT 3.1.1 client supporting only QoS 0, which is enough to
+//! exchange commands and states with home automation systems.
+use failure::Fail;
+use log::debug;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+const PROTOCOL_LEVEL: u8 = 4;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+const FLAG_RETAIN: u8 = 0x01;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "I/O error: {}", _0)]
+    IO(#[fail(cause)] io::Error),
+    #[fail(display = "Protocol error: {}", _0)]
+    Protocol(String),
+    #[fail(display = "Connection refused by broker with code {}", _0)]
+    Refused(u8),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::IO(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+impl Message {
+    pub fn new<T: Into<String>, P: Into<Vec<u8>>>(topic: T, payload: P, retain: bool) -> Self {
+        Message {
+            topic: topic.into(),
+            payload: payload.into(),
+            retain,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive: Duration,
+    /// Message published by the broker when the connection is lost
+    pub will: Option<Message>,
+}
+
+pub struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    keep_alive: Duration,
+    last_sent: Instant,
+}
+
+impl Client {
+    pub fn connect(addr: &str, options: &ConnectOptions) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let mut client = Client {
+            stream,
+            buf: Vec::new(),
+            keep_alive: options.keep_alive,
+            last_sent: Instant::now(),
+        };
+
+        let mut flags = 0x02; // Clean session
+        let mut payload = Vec::new();
+        put_str(&mut payload, &options.client_id);
+        if let Some(will) = &options.will {
+            flags |= 0x04;
+            if will.retain {
+                flags |= 0x20;
+            }
+            put_str(&mut payload, &will.topic);
+            put_bytes(&mut payload, &will.payload);
+        }
+        if let Some(username) = &options.username {
+            flags |= 0x80;
+            put_str(&mut payload, username);
+        }
+        if let Some(password) = &options.password {
+            flags |= 0x40;
+            put_str(&mut payload, password);
+        }
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        body.push(PROTOCOL_LEVEL);
+        body.push(flags);
+        body.extend(&(options.keep_alive.as_secs().min(0xffff) as u16).to_be_bytes());
+        body.extend(payload);
+        client.send(CONNECT, &body)?;
+
+        match client.read_packet(Some(options.keep_alive.max(Duration::from_secs(1))))? {
+            Some((CONNACK, body)) if body.len() == 2 => match body[1] {
+                0 => Ok(client),
+                code => Err(Error::Refused(code)),
+            },
+            Some((header, _)) => Err(Error::Protocol(format!(
+                "expected CONNACK but got {:#x}",
+                header
+            ))),
+            None => Err(Error::Protocol("no response to CONNECT".to_string())),
+        }
+    }
+
+    pub fn subscribe(&mut self, topics: &[&str]) -> Result<()> {
+        let mut body = vec![0, 1]; // Packet identifier
+        for topic in topics {
+            put_str(&mut body, topic);
+            body.push(0); // QoS 0
+        }
+        self.send(SUBSCRIBE, &body)
+    }
+
+    pub fn publish(&mut self, message: &Message) -> Result<()> {
+        let mut body = Vec::new();
+        put_str(&mut body, &message.topic);
+        body.extend(&message.payload);
+        let flags = if message.retain { FLAG_RETAIN } else { 0 };
+        self.send(PUBLISH | flags, &body)
+    }
+
+    /// Wait for a message up to the timeout, sending pings to keep the connection alive.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<Message>> {
+        if self.last_sent.elapsed() >= self.keep_alive / 2 {
+            self.send(PINGREQ, &[])?;
+        }
+        loop {
+            let (header, body) = match self.read_packet(Some(timeout))? {
+                Some(packet) => packet,
+                None => return Ok(None),
+            };
+            match header & 0xf0 {
+                PUBLISH => return Self::parse_publish(header, &body).map(Some),
+                SUBACK | PINGRESP => {}
+                _ => debug!("Ignoring MQTT packet {:#x}", header),
+            }
+        }
+    }
+
+    pub fn disconnect(mut self) -> Result<()> {
+        self.send(DISCONNECT, &[])
+    }
+
+    fn parse_publish(header: u8, body: &[u8]) -> Result<Message> {
+        if header & 0x06 != 0 {
+            return Err(Error::Protocol("QoS > 0 isn't supported".to_string()));
+        }
+        let (topic, rest) = take_str(body)?;
+        Ok(Message {
+            topic,
+            payload: rest.to_vec(),
+            retain: header & FLAG_RETAIN != 0,
+        })
+    }
+
+    fn send(&mut self, header: u8, body: &[u8]) -> Result<()> {
+        self.stream.write_all(&encode_packet(header, body))?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Read a whole packet, returning None if nothing arrived within the timeout
+    fn read_packet(&mut self, timeout: Option<Duration>) -> Result<Option<(u8, Vec<u8>)>> {
+        self.stream.set_read_timeout(timeout)?;
+        loop {
+            if let Some((header, body, len)) = decode_packet(&self.buf)? {
+                self.buf.drain(..len);
+                return Ok(Some((header, body)));
+            }
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(Error::IO(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed by broker",
+                    )))
+                }
+                Ok(n) => self.buf.extend(&chunk[..n]),
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend(&(bytes.len() as u16).to_be_bytes());
+    buf.extend(bytes);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_bytes(buf, s.as_bytes());
+}
+
+fn take_str(buf: &[u8]) -> Result<(String, &[u8])> {
+    if buf.len() < 2 {
+        return Err(Error::Protocol("truncated string".to_string()));
+    }
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if buf.len() < 2 + len {
+        return Err(Error::Protocol("truncated string".to_string()));
+    }
+    let s = String::from_utf8(buf[2..2 + len].to_vec())
+        .map_err(|_| Error::Protocol("string isn't UTF-8".to_string()))?;
+    Ok((s, &buf[2 + len..]))
+}
+
+pub(crate) fn encode_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+/// Decode a packet from the head of the buffer.
+/// Returns the header, body and the length consumed, or None if it's incomplete.
+pub(crate) fn decode_packet(buf: &[u8]) -> Result<Option<(u8, Vec<u8>, usize)>> {
+    let mut len = 0;
+    let mut pos = 1;
+    loop {
+        let byte = match buf.get(pos) {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+        len += ((byte & 0x7f) as usize) << (7 * (pos - 1));
+        pos += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if pos > 4 {
+            return Err(Error::Protocol("malformed remaining length".to_string()));
+        }
+    }
+    if buf.len() < pos + len {
+        return Ok(None);
+    }
+    Ok(Some((buf[0], buf[pos..pos + len].to_vec(), pos + len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_codec() {
+        let body = vec![7; 200];
+        let packet = encode_packet(PUBLISH, &body);
+        // Remaining length 200 takes 2 bytes
+        assert_eq!(&[PUBLISH, 0xc8, 0x01], &packet[..3]);
+        assert_eq!(None, decode_packet(&packet[..100]).unwrap());
+        let mut buf = packet.clone();
+        buf.extend(encode_packet(PINGRESP, &[]));
+        assert_eq!(
+            Some((PUBLISH, body, packet.len())),
+            decode_packet(&buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_publish() {
+        let mut body = Vec::new();
+        put_str(&mut body, "a/b");
+        body.extend(b"payload");
+        assert_eq!(
+            Message::new("a/b", "payload", true),
+            Client::parse_publish(PUBLISH | FLAG_RETAIN, &body).unwrap()
+        );
+        assert!(Client::parse_publish(PUBLISH | 0x02, &body).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use crate::control::{Commander, PlayerCmd, PlaylistCmd};
+use crate::metrics::METRICS;
+use crate::mqtt::{self, Client, ConnectOptions, Message};
+use crate::slideshow::StatusSource;
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type SharedSender<C> = Arc<Mutex<Option<mpsc::Sender<C>>>>;
+
+/// Maximum interval to check the terminate flag
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Interval to check the state of the slideshow changed by other than MQTT commands
+const STATE_INTERVAL: Duration = Duration::from_secs(5);
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Buttons exposed to Home Assistant as (command kind, command, name)
+const BUTTONS: &[(&str, &str, &str)] = &[
+    ("player", "play_next", "Play next"),
+    ("player", "play_back", "Play back"),
+    ("playlist", "update", "Update playlist"),
+    ("playlist", "refresh", "Refresh playlist"),
+];
+
+/// Switches exposed to Home Assistant as (state field, command on, command off, name)
+const SWITCHES: &[(&str, &str, &str, &str)] = &[
+    ("sleeping", "sleep", "wakeup", "Sleep"),
+    ("paused", "pause", "resume", "Pause"),
+    ("muted", "mute", "unmute", "Mute"),
+];
+
+/// Commander receiving commands from an MQTT broker.
+///
+/// Commands are accepted by their names as in `HttpCommander`, published to
+/// `phoseum/<node_id>/player/set` or `phoseum/<node_id>/playlist/set`.
+/// The state of the slideshow is published retained to `phoseum/<node_id>/state`,
+/// along with Home Assistant discovery configs so buttons and switches
+/// controlling the slideshow appear automatically.
+#[derive(Clone)]
+pub struct MqttCommander {
+    addr: String,
+    node_id: String,
+    username: Option<String>,
+    password: Option<String>,
+    discovery_prefix: String,
+    playlist_sender: SharedSender<PlaylistCmd>,
+    player_sender: SharedSender<PlayerCmd>,
+    status_source: Option<Arc<dyn StatusSource>>,
+    started: Arc<AtomicBool>,
+}
+
+impl MqttCommander {
+    pub fn new(host: &str, port: u16, node_id: &str) -> Self {
+        Self {
+            addr: format!("{}:{}", host, port),
+            node_id: node_id.to_string(),
+            username: None,
+            password: None,
+            discovery_prefix: "homeassistant".to_string(),
+            playlist_sender: Arc::new(Mutex::new(None)),
+            player_sender: Arc::new(Mutex::new(None)),
+            status_source: None,
+            started: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn credentials(mut self, username: Option<String>, password: Option<String>) -> Self {
+        self.username = username;
+        self.password = password;
+        self
+    }
+
+    /// Set the topic prefix of Home Assistant discovery configs
+    pub fn discovery_prefix(mut self, prefix: &str) -> Self {
+        self.discovery_prefix = prefix.to_string();
+        self
+    }
+
+    /// Set the source of the state published to the state topic
+    pub fn status_source<S: StatusSource + 'static>(mut self, source: S) -> Self {
+        self.status_source = Some(Arc::new(source));
+        self
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("phoseum/{}/{}", self.node_id, name)
+    }
+
+    fn command_topic(&self, kind: &str) -> String {
+        self.topic(&format!("{}/set", kind))
+    }
+
+    fn discovery_config(&self, component: &str, object_id: &str, mut config: Value) -> Message {
+        let unique_id = format!("phoseum_{}_{}", self.node_id, object_id);
+        let extra = json!({
+            "unique_id": unique_id,
+            "availability_topic": self.topic("availability"),
+            "device": {
+                "identifiers": [format!("phoseum_{}", self.node_id)],
+                "name": format!("Phoseum {}", self.node_id),
+                "model": "Phoseum",
+            },
+        });
+        for (key, value) in extra.as_object().expect("object") {
+            config[key] = value.clone();
+        }
+        Message::new(
+            format!(
+                "{}/{}/{}/config",
+                self.discovery_prefix, component, unique_id
+            ),
+            config.to_string(),
+            true,
+        )
+    }
+
+    fn discovery_configs(&self) -> Vec<Message> {
+        let mut configs = Vec::new();
+        for (kind, cmd, name) in BUTTONS {
+            configs.push(self.discovery_config(
+                "button",
+                cmd,
+                json!({
+                    "name": name,
+                    "command_topic": self.command_topic(kind),
+                    "payload_press": cmd,
+                }),
+            ));
+        }
+        for (field, on, off, name) in SWITCHES {
+            configs.push(self.discovery_config(
+                "switch",
+                field,
+                json!({
+                    "name": name,
+                    "command_topic": self.command_topic("player"),
+                    "payload_on": on,
+                    "payload_off": off,
+                    "state_topic": self.topic("state"),
+                    "value_template": format!("{{{{ 'ON' if value_json.{} else 'OFF' }}}}", field),
+                }),
+            ));
+        }
+        configs.push(self.discovery_config(
+            "sensor",
+            "current",
+            json!({
+                "name": "Current item",
+                "state_topic": self.topic("state"),
+                "value_template": "{{ value_json.current }}",
+            }),
+        ));
+        configs
+    }
+
+    fn state(&self) -> Option<Value> {
+        let status = self.status_source.as_ref()?.status();
+        let current = status.current.and_then(|item| {
+            item.path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+        Some(json!({
+            "paused": status.player.paused,
+            "sleeping": status.player.sleeping,
+            "muted": status.player.muted,
+            "current": current,
+            "playlist_size": status.playlist_size,
+        }))
+    }
+
+    fn send_command<C>(sender: &Mutex<Option<mpsc::Sender<C>>>, name: &str, cmd: Option<C>) {
+        let cmd = match cmd {
+            Some(cmd) => cmd,
+            None => {
+                warn!("Ignoring unknown command from MQTT: {}", name);
+                return;
+            }
+        };
+        match sender.lock().expect("lock sender").as_ref() {
+            Some(sender) => match sender.send(cmd) {
+                Ok(()) => METRICS.commands.inc("mqtt"),
+                Err(_) => warn!("Command channel is closed, rejecting {}", name),
+            },
+            None => warn!("Commander isn't ready, rejecting {}", name),
+        }
+    }
+
+    fn handle(&self, message: &Message) {
+        let name = String::from_utf8_lossy(&message.payload);
+        let name = name.trim();
+        debug!("Received MQTT message {}: {}", message.topic, name);
+        if message.topic == self.command_topic("player") {
+            Self::send_command(&self.player_sender, name, PlayerCmd::from_name(name));
+        } else if message.topic == self.command_topic("playlist") {
+            Self::send_command(&self.playlist_sender, name, PlaylistCmd::from_name(name));
+        }
+    }
+
+    fn connect(&self) -> mqtt::Result<Client> {
+        let options = ConnectOptions {
+            client_id: format!("phoseum-{}", self.node_id),
+            username: self.username.clone(),
+            password: self.password.clone(),
+            keep_alive: KEEP_ALIVE,
+            will: Some(Message::new(self.topic("availability"), "offline", true)),
+        };
+        let mut client = Client::connect(&self.addr, &options)?;
+        for config in self.discovery_configs() {
+            client.publish(&config)?;
+        }
+        client.publish(&Message::new(self.topic("availability"), "online", true))?;
+        let player_topic = self.command_topic("player");
+        let playlist_topic = self.command_topic("playlist");
+        client.subscribe(&[&player_topic, &playlist_topic])?;
+        Ok(client)
+    }
+
+    /// Serve commands and publish states until terminated or the connection fails
+    fn serve(&self, client: &mut Client, terminate: &AtomicBool) -> mqtt::Result<()> {
+        let mut last_state = None;
+        let mut next_state_check = Instant::now();
+        while !terminate.load(Ordering::Relaxed) {
+            if let Some(message) = client.poll(POLL_INTERVAL)? {
+                self.handle(&message);
+                // Checked on the next poll, giving the slideshow time to process the command
+                next_state_check = next_state_check.min(Instant::now() + POLL_INTERVAL);
+            }
+            if Instant::now() < next_state_check {
+                continue;
+            }
+            next_state_check = Instant::now() + STATE_INTERVAL;
+            if let Some(state) = self.state() {
+                if last_state.as_ref() != Some(&state) {
+                    let payload = state.to_string();
+                    client.publish(&Message::new(self.topic("state"), payload, true))?;
+                    last_state = Some(state);
+                }
+            }
+        }
+        client.publish(&Message::new(self.topic("availability"), "offline", true))
+    }
+
+    fn run(&self, terminate: &AtomicBool) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let mut backoff = MIN_RECONNECT_BACKOFF;
+        while !terminate.load(Ordering::Relaxed) {
+            match self.connect() {
+                Ok(mut client) => {
+                    info!("Connected to MQTT broker {}", self.addr);
+                    backoff = MIN_RECONNECT_BACKOFF;
+                    match self.serve(&mut client, terminate) {
+                        Ok(()) => {
+                            if let Err(e) = client.disconnect() {
+                                debug!("Error disconnecting from MQTT broker: {}", e);
+                            }
+                            break;
+                        }
+                        Err(e) => warn!("Lost connection to MQTT broker: {}", e),
+                    }
+                }
+                Err(e) => warn!("Failed to connect to MQTT broker {}: {}", self.addr, e),
+            }
+
+            info!("Reconnecting to MQTT broker in {:?}", backoff);
+            let mut waited = Duration::from_secs(0);
+            while waited < backoff && !terminate.load(Ordering::Relaxed) {
+                thread::sleep(POLL_INTERVAL);
+                waited += POLL_INTERVAL;
+            }
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+}
+
+impl Commander<PlaylistCmd> for MqttCommander {
+    fn run(&mut self, sender: mpsc::Sender<PlaylistCmd>, terminate: Arc<AtomicBool>) {
+        self.playlist_sender
+            .lock()
+            .expect("lock sender")
+            .replace(sender);
+        MqttCommander::run(self, &terminate);
+    }
+}
+
+impl Commander<PlayerCmd> for MqttCommander {
+    fn run(&mut self, sender: mpsc::Sender<PlayerCmd>, terminate: Arc<AtomicBool>) {
+        self.player_sender
+            .lock()
+            .expect("lock sender")
+            .replace(sender);
+        MqttCommander::run(self, &terminate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::{decode_packet, encode_packet};
+    use crate::slideshow::MockStatusSource;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Broker stand-in serving a single client, recording packets it receives
+    struct Broker {
+        stream: TcpStream,
+        buf: Vec<u8>,
+    }
+
+    impl Broker {
+        fn read_packet(&mut self) -> (u8, Vec<u8>) {
+            loop {
+                if let Some((header, body, len)) = decode_packet(&self.buf).unwrap() {
+                    self.buf.drain(..len);
+                    return (header, body);
+                }
+                let mut chunk = [0; 4096];
+                let n = self.stream.read(&mut chunk).unwrap();
+                assert!(n > 0, "connection closed");
+                self.buf.extend(&chunk[..n]);
+            }
+        }
+
+        /// Read packets until a PUBLISH to the topic arrives, returning its payload and flags
+        fn read_publish(&mut self, topic: &str) -> (String, u8) {
+            loop {
+                let (header, body) = self.read_packet();
+                if header & 0xf0 != 0x30 {
+                    continue;
+                }
+                let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                if &body[2..2 + len] == topic.as_bytes() {
+                    let payload = String::from_utf8(body[2 + len..].to_vec()).unwrap();
+                    return (payload, header & 0x0f);
+                }
+            }
+        }
+
+        fn publish(&mut self, topic: &str, payload: &str) {
+            let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+            body.extend(topic.as_bytes());
+            body.extend(payload.as_bytes());
+            self.stream.write_all(&encode_packet(0x30, &body)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_discovery_configs() {
+        let commander = MqttCommander::new("localhost", 1883, "living");
+        let configs = commander.discovery_configs();
+        assert_eq!(BUTTONS.len() + SWITCHES.len() + 1, configs.len());
+        assert!(configs.iter().all(|c| c.retain));
+
+        let pause = configs
+            .iter()
+            .find(|c| c.topic == "homeassistant/switch/phoseum_living_paused/config")
+            .unwrap();
+        let config: Value = serde_json::from_slice(&pause.payload).unwrap();
+        assert_eq!(json!("phoseum/living/player/set"), config["command_topic"]);
+        assert_eq!(json!("pause"), config["payload_on"]);
+        assert_eq!(json!("resume"), config["payload_off"]);
+        assert_eq!(
+            json!("{{ 'ON' if value_json.paused else 'OFF' }}"),
+            config["value_template"]
+        );
+        assert_eq!(json!(["phoseum_living"]), config["device"]["identifiers"]);
+    }
+
+    #[test]
+    fn test_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commander = MqttCommander::new("127.0.0.1", port, "test")
+            .discovery_prefix("ha")
+            .status_source(MockStatusSource);
+
+        let terminate = Arc::new(AtomicBool::new(false));
+        let (player_send, player_recv) = mpsc::channel();
+        let (pl_send, pl_recv) = mpsc::channel();
+        let mut threads = Vec::new();
+        let mut player_commander = commander.clone();
+        let term_copy = Arc::clone(&terminate);
+        threads.push(thread::spawn(move || {
+            Commander::<PlayerCmd>::run(&mut player_commander, player_send, term_copy)
+        }));
+        let mut pl_commander = commander.clone();
+        let term_copy = Arc::clone(&terminate);
+        threads.push(thread::spawn(move || {
+            Commander::<PlaylistCmd>::run(&mut pl_commander, pl_send, term_copy)
+        }));
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut broker = Broker {
+            stream,
+            buf: Vec::new(),
+        };
+        let (header, connect) = broker.read_packet();
+        assert_eq!(0x10, header);
+        // * Last will is set to mark the slideshow offline
+        assert!(String::from_utf8_lossy(&connect).contains("phoseum/test/availability"));
+        broker.stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+
+        let (_, flags) = broker.read_publish("ha/button/phoseum_test_play_next/config");
+        assert_eq!(1, flags & 0x01);
+        assert_eq!("online", broker.read_publish("phoseum/test/availability").0);
+        let (header, subscribe) = broker.read_packet();
+        assert_eq!(0x82, header);
+        assert!(String::from_utf8_lossy(&subscribe).contains("phoseum/test/playlist/set"));
+
+        // * State is published retained
+        let (state, flags) = broker.read_publish("phoseum/test/state");
+        assert_eq!(1, flags & 0x01);
+        let state: Value = serde_json::from_str(&state).unwrap();
+        assert_eq!(json!(true), state["muted"]);
+        assert_eq!(json!(3), state["playlist_size"]);
+
+        // Wait both commanders to pass their senders before sending commands
+        while commander.playlist_sender.lock().unwrap().is_none()
+            || commander.player_sender.lock().unwrap().is_none()
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        broker.publish("phoseum/test/player/set", "pause");
+        broker.publish("phoseum/test/playlist/set", "refresh");
+        broker.publish("phoseum/test/player/set", "dance");
+        let timeout = Duration::from_secs(5);
+        assert!(matches!(
+            player_recv.recv_timeout(timeout),
+            Ok(PlayerCmd::Pause)
+        ));
+        assert!(matches!(
+            pl_recv.recv_timeout(timeout),
+            Ok(PlaylistCmd::Refresh)
+        ));
+
+        terminate.store(true, Ordering::Relaxed);
+        assert_eq!(
+            "offline",
+            broker.read_publish("phoseum/test/availability").0
+        );
+        for th in threads {
+            th.join().unwrap();
+        }
+        assert!(player_recv.try_recv().is_err());
+    }
+}
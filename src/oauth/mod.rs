@@ -8,8 +8,8 @@ use oauth2::{
     self, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
     PkceCodeVerifier, RedirectUrl, RefreshToken, Scope, TokenResponse, TokenUrl,
 };
-use std::cell::RefCell;
 use std::path::PathBuf;
+use std::sync::Mutex;
 use store::TokenStore;
 
 #[derive(Debug, Fail)]
@@ -44,7 +44,7 @@ pub struct TokenService {
     oauth2_client: BasicClient,
     auth_scopes: Vec<String>,
 
-    authing_context: RefCell<Option<AuthenticatingContext>>,
+    authing_context: Mutex<Option<AuthenticatingContext>>,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -84,7 +84,7 @@ impl TokenService {
             oauth2_client,
             auth_scopes: config.scopes.into_iter().map(Into::into).collect(),
 
-            authing_context: RefCell::new(None),
+            authing_context: Mutex::new(None),
         })
     }
 
@@ -100,7 +100,7 @@ impl TokenService {
         }
 
         let (authorize_url, _) = req.url();
-        *self.authing_context.borrow_mut() = Some(AuthenticatingContext {
+        *self.authing_context.lock().expect("lock context") = Some(AuthenticatingContext {
             pkce_verifier: pkce_code_verifier,
         });
 
@@ -111,7 +111,8 @@ impl TokenService {
     pub fn complete_authorization(&self, auth_code: String) -> Result<()> {
         let pkce_verifier = self
             .authing_context
-            .borrow_mut()
+            .lock()
+            .expect("lock context")
             .take()
             .ok_or(Error::AuthNotStarted)?
             .pkce_verifier;
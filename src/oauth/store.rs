@@ -3,10 +3,10 @@ use log::debug;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json;
-use std::cell::RefCell;
 use std::fs;
 use std::io;
 use std::path::PathBuf;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::SystemTime;
 
@@ -34,7 +34,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct TokenStore {
     path: PathBuf,
-    entry: RefCell<StoreEntry>,
+    entry: Mutex<StoreEntry>,
 }
 
 impl TokenStore {
@@ -55,7 +55,7 @@ impl TokenStore {
 
         Ok(TokenStore {
             path,
-            entry: RefCell::new(entry),
+            entry: Mutex::new(entry),
         })
     }
 
@@ -66,13 +66,13 @@ impl TokenStore {
     }
 
     pub fn save(&self) -> Result<()> {
-        let json = serde_json::to_string_pretty(&self.entry)?;
+        let json = serde_json::to_string_pretty(&*self.entry.lock().expect("lock entry"))?;
         std::fs::write(&self.path, json)?;
         Ok(())
     }
 
     pub fn valid_access_token(&self) -> Option<String> {
-        if let Some(token) = &self.entry.borrow().access_token {
+        if let Some(token) = &self.entry.lock().expect("lock entry").access_token {
             if token.expire_date.unwrap_or(std::u128::MAX) > Self::current_time().as_millis() {
                 return Some(token.secret.clone());
             }
@@ -82,7 +82,8 @@ impl TokenStore {
 
     pub fn refresh_token(&self) -> Option<String> {
         self.entry
-            .borrow()
+            .lock()
+            .expect("lock entry")
             .refresh_token
             .as_ref()
             .map(|t| t.secret.clone())
@@ -105,16 +106,19 @@ impl TokenStore {
         let now = Self::current_time().as_millis();
         let expire_date = expires_in.map(|t| now + t);
 
-        self.entry.borrow_mut().access_token = access_token.map(|t| Token {
-            secret: t,
-            created_date: now,
-            expire_date,
-        });
-        self.entry.borrow_mut().refresh_token = refresh_token.map(|t| Token {
-            secret: t,
-            created_date: Self::current_time().as_millis(),
-            expire_date: None,
-        });
+        {
+            let mut entry = self.entry.lock().expect("lock entry");
+            entry.access_token = access_token.map(|t| Token {
+                secret: t,
+                created_date: now,
+                expire_date,
+            });
+            entry.refresh_token = refresh_token.map(|t| Token {
+                secret: t,
+                created_date: Self::current_time().as_millis(),
+                expire_date: None,
+            });
+        }
 
         debug!(
             "Local tokens {:?} updated to expire at {:?}",
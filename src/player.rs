@@ -1,7 +1,103 @@
-use failure::Error;
+use chrono::{DateTime, Local};
+use failure::{format_err, Error};
+use serde::Serialize;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
+/// Position of the caption on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl FromStr for CaptionPosition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "top-left" => Ok(CaptionPosition::TopLeft),
+            "top" => Ok(CaptionPosition::Top),
+            "top-right" => Ok(CaptionPosition::TopRight),
+            "bottom-left" => Ok(CaptionPosition::BottomLeft),
+            "bottom" => Ok(CaptionPosition::Bottom),
+            "bottom-right" => Ok(CaptionPosition::BottomRight),
+            _ => Err(format_err!("unknown caption position: {}", s)),
+        }
+    }
+}
+
+/// Overlay showing metadata of the item being played
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptionConfig {
+    pub position: CaptionPosition,
+    /// Text in which `{date}`, `{description}` and `{filename}` are replaced by the item's metadata
+    pub format: String,
+    /// strftime format of `{date}`
+    pub date_format: String,
+}
+
+impl Default for CaptionConfig {
+    fn default() -> Self {
+        CaptionConfig {
+            position: CaptionPosition::BottomLeft,
+            format: "{date} {description}".to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+        }
+    }
+}
+
+impl CaptionConfig {
+    /// Return the caption text of the item
+    pub fn render(&self, meta: &ItemMeta) -> String {
+        let date = meta
+            .created_time
+            .map(|t| {
+                DateTime::<Local>::from(t)
+                    .format(&self.date_format)
+                    .to_string()
+            })
+            .unwrap_or_default();
+        self.format
+            .replace("{date}", &date)
+            .replace("{description}", meta.description.as_deref().unwrap_or(""))
+            .replace("{filename}", meta.filename.as_deref().unwrap_or(""))
+            .trim()
+            .to_string()
+    }
+}
+
+/// Metadata of an item which can be shown in the caption
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemMeta {
+    pub created_time: Option<SystemTime>,
+    pub description: Option<String>,
+    pub filename: Option<String>,
+}
+
+/// Item of the playlist passed to the player
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistItem {
+    /// Local path to the content
+    pub path: PathBuf,
+    pub meta: ItemMeta,
+}
+
+impl PlaylistItem {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        PlaylistItem {
+            path: path.into(),
+            meta: ItemMeta::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct SlideshowConfig {
     /// Duration to keep showing single photo
     pub show_duration: Duration,
@@ -9,6 +105,8 @@ pub struct SlideshowConfig {
     pub fullscreen: bool,
     /// Audio volume in percent when playing videos
     pub audio_volume: f32,
+    /// Caption overlaid on items, None to show nothing
+    pub caption: Option<CaptionConfig>,
 }
 
 impl Default for SlideshowConfig {
@@ -17,18 +115,34 @@ impl Default for SlideshowConfig {
             show_duration: Duration::from_secs(10),
             fullscreen: true,
             audio_volume: 0.5,
+            caption: None,
         }
     }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Snapshot of player's state
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayerStatus {
+    pub paused: bool,
+    pub sleeping: bool,
+    pub muted: bool,
+    /// Path or filename of the item currently being played, if known
+    pub current: Option<PathBuf>,
+}
+
 pub trait Player {
     /// Launch player
     ///
     /// At the time this method returns, player must be ready to start slideshow
     /// immediately by upcoming `update_playlist` call.
     fn start(&mut self, config: SlideshowConfig) -> Result<()>;
+    /// Terminate player
+    ///
+    /// All states such as pausing or muting are reset, and the player can be
+    /// launched again by `start()`.
+    fn stop(&mut self);
     /// Move to the next item in the playlist
     fn play_next(&mut self) -> Result<()>;
     /// Move to the previous item in the playlist
@@ -58,9 +172,45 @@ pub trait Player {
     /// Unmute volume
     fn unmute(&mut self) -> Result<()>;
     /// Update by replacing the current playlist with newly given playlist
-    fn update_playlist(&mut self, playlist: Vec<PathBuf>) -> Result<()>;
+    fn update_playlist(&mut self, playlist: Vec<PlaylistItem>) -> Result<()>;
+    /// Add items to the tail of the current playlist without interrupting the current play
+    fn append_playlist(&mut self, items: Vec<PlaylistItem>) -> Result<()>;
     /// Return whether the player is pausing or sleeping
     fn locked(&self) -> bool;
     /// Healthcheck. If player is considered as not functioning at the moment, return false.
     fn is_ok(&self) -> bool;
+    /// Return the current state of the player
+    fn status(&self) -> PlayerStatus;
+    /// Apply new config to the running player.
+    ///
+    /// Returns names of config fields which changed but can't be applied
+    /// without restarting the player.
+    fn reconfigure(&mut self, config: &SlideshowConfig) -> Result<Vec<&'static str>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_render_caption() {
+        let created_time = Local.ymd(2020, 5, 3).and_hms(10, 0, 0);
+        let meta = ItemMeta {
+            created_time: Some(created_time.into()),
+            description: Some("Picnic".to_string()),
+            filename: Some("IMG_0001.jpg".to_string()),
+        };
+        assert_eq!("2020-05-03 Picnic", CaptionConfig::default().render(&meta));
+
+        let config = CaptionConfig {
+            format: "{filename} ({date})".to_string(),
+            date_format: "%b %Y".to_string(),
+            ..CaptionConfig::default()
+        };
+        assert_eq!("IMG_0001.jpg (May 2020)", config.render(&meta));
+
+        // * Missing metadata is replaced with empty
+        assert_eq!("", CaptionConfig::default().render(&ItemMeta::default()));
+    }
 }
@@ -0,0 +1,849 @@
+use crate::player::{
+    CaptionConfig, CaptionPosition, Player, PlayerStatus, PlaylistItem, Result, SlideshowConfig,
+};
+use failure::{format_err, Fail};
+use libc;
+use log::{debug, info, warn};
+use serde_json::{self, json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::process::Command;
+use std::time::Duration;
+use std::time::Instant;
+
+const MPV_VOLUME_MAX: f32 = 100.0;
+const MPV_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const MPV_STARTUP_CHECK_BACKOFF: Duration = Duration::from_millis(500);
+
+const MPV_DEFAULT_BIN: &str = "mpv";
+const MPV_DEFAULT_IPC_SOCKET: &str = "/tmp/phoseum-mpv.sock";
+const MPV_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Commands are sent one by one over dedicated connection, so fixed ID is enough
+const MPV_REQUEST_ID: u64 = 1;
+
+#[derive(Debug, Fail)]
+pub enum MpvError {
+    #[fail(display = "Player not started")]
+    NotStarted,
+    #[fail(display = "Timed out in waiting player to start")]
+    StartTimeout,
+    #[fail(display = "Failed to send command to player: {}", _0)]
+    BadResponse(#[fail(cause)] failure::Error),
+}
+
+impl From<io::Error> for MpvError {
+    fn from(e: io::Error) -> Self {
+        MpvError::BadResponse(e.into())
+    }
+}
+
+impl From<serde_json::Error> for MpvError {
+    fn from(e: serde_json::Error) -> Self {
+        MpvError::BadResponse(e.into())
+    }
+}
+
+#[derive(Default)]
+pub struct MpvConfig {
+    pub mpv_bin: Option<String>,
+    pub ipc_socket: Option<PathBuf>,
+}
+
+pub trait IpcClient {
+    fn send_command(
+        &self,
+        socket: &Path,
+        command: &[Value],
+    ) -> std::result::Result<Value, MpvError>;
+}
+
+/// Client talking mpv's JSON IPC protocol over an Unix domain socket.
+pub struct UnixSocketClient;
+
+impl IpcClient for UnixSocketClient {
+    fn send_command(
+        &self,
+        socket: &Path,
+        command: &[Value],
+    ) -> std::result::Result<Value, MpvError> {
+        let request = json!({ "command": command, "request_id": MPV_REQUEST_ID });
+        debug!("Sending command to mpv: {}", request);
+
+        let mut stream = UnixStream::connect(socket)?;
+        stream.set_read_timeout(Some(MPV_REQUEST_TIMEOUT))?;
+        stream.write_all(format!("{}\n", request).as_bytes())?;
+
+        for line in BufReader::new(stream).lines() {
+            let resp: Value = serde_json::from_str(&line?)?;
+            // Asynchronous events are delivered through the same connection
+            if resp.get("request_id") != Some(&json!(MPV_REQUEST_ID)) {
+                continue;
+            }
+            return match resp.get("error").and_then(Value::as_str) {
+                Some("success") => Ok(resp.get("data").cloned().unwrap_or(Value::Null)),
+                Some(error) => Err(MpvError::BadResponse(format_err!(
+                    "Command {:?} failed: {}",
+                    command,
+                    error
+                ))),
+                None => Err(MpvError::BadResponse(format_err!(
+                    "Malformed response: {}",
+                    resp
+                ))),
+            };
+        }
+
+        Err(MpvError::BadResponse(format_err!(
+            "Connection closed without response"
+        )))
+    }
+}
+
+pub struct MpvPlayer<C: IpcClient = UnixSocketClient> {
+    mpv_config: MpvConfig,
+
+    config: Option<SlideshowConfig>,
+    process: Option<Child>,
+    client: C,
+
+    pausing: bool,
+    sleeping: bool,
+    muting: bool,
+}
+
+impl MpvPlayer {
+    pub fn new(config: MpvConfig) -> Self {
+        Self::new_with_client(config, UnixSocketClient)
+    }
+}
+
+impl<C: IpcClient> MpvPlayer<C> {
+    fn new_with_client(config: MpvConfig, client: C) -> Self {
+        Self {
+            mpv_config: config,
+            config: None,
+            process: None,
+            client,
+            pausing: false,
+            sleeping: false,
+            muting: false,
+        }
+    }
+
+    fn config(&self) -> std::result::Result<&SlideshowConfig, MpvError> {
+        self.config.as_ref().ok_or(MpvError::NotStarted)
+    }
+
+    /// Convert `audio_volume` set in config into the value
+    /// range used in mpv player
+    fn audio_volume(&self) -> std::result::Result<u32, MpvError> {
+        Ok((MPV_VOLUME_MAX * self.config()?.audio_volume).round() as u32)
+    }
+
+    fn ipc_socket(&self) -> &Path {
+        self.mpv_config
+            .ipc_socket
+            .as_ref()
+            .map(|p| p.as_ref())
+            .unwrap_or_else(|| Path::new(MPV_DEFAULT_IPC_SOCKET))
+    }
+
+    /// Path to the file to pass playlist to mpv
+    fn playlist_file(&self) -> PathBuf {
+        self.ipc_socket().with_extension("m3u")
+    }
+
+    /// Write items into the playlist file, with its caption as the title of each item
+    fn write_playlist(&self, path: &Path, items: &[PlaylistItem]) -> io::Result<()> {
+        let caption = self.config.as_ref().and_then(|c| c.caption.as_ref());
+        let mut content = String::new();
+        for item in items {
+            if let Some(caption) = caption {
+                let title = caption.render(&item.meta).replace('\n', " ");
+                content.push_str(&format!("#EXTINF:-1,{}\n", title));
+            }
+            content.push_str(item.path.to_str().expect("playlist path"));
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+
+    /// Return the values of `osd-align-x` and `osd-align-y` for the caption position
+    fn osd_align(caption: &CaptionConfig) -> (&'static str, &'static str) {
+        match caption.position {
+            CaptionPosition::TopLeft => ("left", "top"),
+            CaptionPosition::Top => ("center", "top"),
+            CaptionPosition::TopRight => ("right", "top"),
+            CaptionPosition::BottomLeft => ("left", "bottom"),
+            CaptionPosition::Bottom => ("center", "bottom"),
+            CaptionPosition::BottomRight => ("right", "bottom"),
+        }
+    }
+
+    /// Show or hide the caption, which is the title of each item shown as OSD message
+    fn set_caption(&self, caption: Option<&CaptionConfig>) -> std::result::Result<(), MpvError> {
+        match caption {
+            Some(caption) => {
+                let (align_x, align_y) = Self::osd_align(caption);
+                self.set_property("osd-align-x", json!(align_x))?;
+                self.set_property("osd-align-y", json!(align_y))?;
+                self.set_property("osd-msg1", json!("${media-title}"))?;
+                self.set_property("osd-level", json!(1))
+            }
+            None => self.set_property("osd-level", json!(0)),
+        }
+    }
+
+    fn send_command(&self, command: &[Value]) -> std::result::Result<Value, MpvError> {
+        self.client.send_command(self.ipc_socket(), command)
+    }
+
+    fn set_property(&self, name: &str, value: Value) -> std::result::Result<(), MpvError> {
+        self.send_command(&[json!("set_property"), json!(name), value])?;
+        Ok(())
+    }
+
+    fn wait_on_ipc_socket(&self) -> std::result::Result<(), MpvError> {
+        let start_time = Instant::now();
+
+        while Instant::now() - start_time < MPV_STARTUP_TIMEOUT {
+            if self.is_ok() {
+                return Ok(());
+            }
+            std::thread::sleep(MPV_STARTUP_CHECK_BACKOFF);
+        }
+        Err(MpvError::StartTimeout)
+    }
+
+    fn set_volume(&self, volume: u32) -> std::result::Result<(), MpvError> {
+        info!("Setting audio volume to {}", volume);
+        self.set_property("volume", json!(volume))
+    }
+
+    fn maybe_pause(&self) -> std::result::Result<(), MpvError> {
+        if !self.pausing && !self.sleeping {
+            self.set_property("pause", json!(true))?;
+        }
+        Ok(())
+    }
+
+    fn maybe_resume(&mut self, resume: bool) -> std::result::Result<(), MpvError> {
+        if (self.pausing && resume) || (self.sleeping && !self.pausing) {
+            self.set_property("pause", json!(false))?;
+            self.pausing = false;
+            self.sleeping = false;
+        }
+        Ok(())
+    }
+}
+
+impl<C: IpcClient> Player for MpvPlayer<C> {
+    fn start(&mut self, config: SlideshowConfig) -> Result<()> {
+        let mpv_bin = self
+            .mpv_config
+            .mpv_bin
+            .as_ref()
+            .map(|s| s.as_ref())
+            .unwrap_or(MPV_DEFAULT_BIN);
+
+        let mut cmd = Command::new(mpv_bin);
+        // https://mpv.io/manual/stable/#options
+        cmd.arg("--idle=yes")
+            .arg("--force-window=yes")
+            .arg("--loop-playlist=inf")
+            .arg("--no-terminal")
+            .arg(format!(
+                "--image-display-duration={}",
+                config.show_duration.as_secs()
+            ))
+            .arg(format!(
+                "--input-ipc-server={}",
+                self.ipc_socket().display()
+            ));
+
+        if config.fullscreen {
+            cmd.arg("--fullscreen");
+        }
+        match &config.caption {
+            Some(caption) => {
+                let (align_x, align_y) = Self::osd_align(caption);
+                cmd.arg("--osd-level=1")
+                    .arg("--osd-msg1=${media-title}")
+                    .arg(format!("--osd-align-x={}", align_x))
+                    .arg(format!("--osd-align-y={}", align_y));
+            }
+            None => {
+                cmd.arg("--osd-level=0");
+            }
+        }
+
+        self.process = Some(cmd.spawn()?);
+        self.wait_on_ipc_socket()?;
+
+        self.config = Some(config);
+        self.set_volume(self.audio_volume()?)?;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut proc) = self.process.take() {
+            // Rust's Command doesn't support other than SIGKILL in portable interface
+            unsafe {
+                libc::kill(proc.id() as i32, libc::SIGTERM);
+            }
+            match proc.wait() {
+                Ok(status) => debug!("mpv process exit with {}", status.code().unwrap_or(-1)),
+                Err(e) => warn!("Failed to stop mpv process gracefully: {}", e),
+            }
+        }
+        self.config = None;
+        self.pausing = false;
+        self.sleeping = false;
+        self.muting = false;
+    }
+
+    fn play_next(&mut self) -> Result<()> {
+        // Pausing state is kept over items in mpv, no need to restore it
+        self.send_command(&[json!("playlist-next")])?;
+        Ok(())
+    }
+
+    fn play_back(&mut self) -> Result<()> {
+        self.send_command(&[json!("playlist-prev")])?;
+        Ok(())
+    }
+
+    fn sleep(&mut self) -> Result<()> {
+        self.maybe_pause()?;
+        self.sleeping = true;
+        Ok(())
+    }
+
+    fn wakeup(&mut self) -> Result<()> {
+        self.maybe_resume(false)?;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.maybe_pause()?;
+        self.pausing = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.maybe_resume(true)?;
+        Ok(())
+    }
+
+    fn mute(&mut self) -> Result<()> {
+        if !self.muting {
+            self.set_property("mute", json!(true))?;
+        }
+        self.muting = true;
+        Ok(())
+    }
+
+    fn unmute(&mut self) -> Result<()> {
+        if self.muting {
+            self.set_property("mute", json!(false))?;
+        }
+        self.muting = false;
+        Ok(())
+    }
+
+    fn update_playlist(&mut self, playlist: Vec<PlaylistItem>) -> Result<()> {
+        debug!("Start updating playlist");
+        let playlist_file = self.playlist_file();
+        self.write_playlist(&playlist_file, &playlist)?;
+
+        // Unlike VLC, mpv can swap the whole playlist at once
+        self.send_command(&[
+            json!("loadlist"),
+            json!(playlist_file.to_str().expect("playlist file path")),
+            json!("replace"),
+        ])?;
+
+        debug!("Update playlist complete");
+        Ok(())
+    }
+
+    fn append_playlist(&mut self, items: Vec<PlaylistItem>) -> Result<()> {
+        if self.config()?.caption.is_some() {
+            // Titles can be given only through playlist file
+            let append_file = self.playlist_file().with_extension("append.m3u");
+            self.write_playlist(&append_file, &items)?;
+            debug!("Appending {} items to playlist", items.len());
+            self.send_command(&[
+                json!("loadlist"),
+                json!(append_file.to_str().expect("playlist file path")),
+                json!("append"),
+            ])?;
+            return Ok(());
+        }
+        for item in items {
+            debug!("Appending item to playlist: {}", item.path.display());
+            self.send_command(&[
+                json!("loadfile"),
+                json!(item.path.to_str().expect("playlist path")),
+                json!("append"),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn locked(&self) -> bool {
+        self.pausing || self.sleeping
+    }
+
+    fn is_ok(&self) -> bool {
+        match self.send_command(&[json!("get_property"), json!("mpv-version")]) {
+            Ok(_) => true,
+            Err(e) => {
+                debug!("Got error response while checking health of mpv: {}", e);
+                false
+            }
+        }
+    }
+
+    fn reconfigure(&mut self, config: &SlideshowConfig) -> Result<Vec<&'static str>> {
+        let current = self.config()?.clone();
+        if config.show_duration != current.show_duration {
+            info!(
+                "Setting image display duration to {}",
+                config.show_duration.as_secs()
+            );
+            self.set_property(
+                "image-display-duration",
+                json!(config.show_duration.as_secs()),
+            )?;
+        }
+        if config.fullscreen != current.fullscreen {
+            self.set_property("fullscreen", json!(config.fullscreen))?;
+        }
+        if config.caption != current.caption {
+            // Titles of items already in the playlist are kept until it's updated
+            self.set_caption(config.caption.as_ref())?;
+        }
+        self.config = Some(config.clone());
+        if config.audio_volume != current.audio_volume {
+            self.set_volume(self.audio_volume()?)?;
+        }
+        // Every config can be changed through properties
+        Ok(Vec::new())
+    }
+
+    fn status(&self) -> PlayerStatus {
+        let current = match self.send_command(&[json!("get_property"), json!("path")]) {
+            Ok(path) => path.as_str().map(PathBuf::from),
+            Err(e) => {
+                debug!("Failed to obtain current item from mpv: {}", e);
+                None
+            }
+        };
+        PlayerStatus {
+            paused: self.pausing,
+            sleeping: self.sleeping,
+            muted: self.muting,
+            current,
+        }
+    }
+}
+
+impl<C: IpcClient> Drop for MpvPlayer<C> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+    use tempfile;
+
+    impl<F: Fn(&[Value]) -> std::result::Result<Value, MpvError>> IpcClient for F {
+        fn send_command(
+            &self,
+            _socket: &Path,
+            command: &[Value],
+        ) -> std::result::Result<Value, MpvError> {
+            self(command)
+        }
+    }
+
+    fn dummy_bin_player<C: Fn(&[Value]) -> std::result::Result<Value, MpvError>>(
+        client: C,
+    ) -> (tempfile::TempDir, MpvPlayer<C>) {
+        let dir = tempfile::tempdir().unwrap();
+        let dummy_bin = dir.path().join("mpv");
+        fs::write(&dummy_bin, "#!/bin/sh\nsleep 60\n").unwrap();
+        let mut perm = fs::metadata(&dummy_bin).unwrap().permissions();
+        perm.set_mode(0o775);
+        fs::set_permissions(&dummy_bin, perm).unwrap();
+
+        let player = MpvPlayer::new_with_client(
+            MpvConfig {
+                mpv_bin: Some(dummy_bin.to_str().unwrap().to_string()),
+                ipc_socket: Some(dir.path().join("mpv.sock")),
+            },
+            client,
+        );
+        (dir, player)
+    }
+
+    /// Return the command name and its first argument
+    fn command_name(command: &[Value]) -> String {
+        command
+            .iter()
+            .take(2)
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn test_is_ok() {
+        let shutdown = Cell::new(false);
+        let (_dir, mut player) = dummy_bin_player(|_| {
+            if shutdown.get() {
+                Err(MpvError::BadResponse(format_err!("")))
+            } else {
+                Ok(Value::Null)
+            }
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+
+        // Player health's good while it's running
+        assert!(player.is_ok());
+
+        // Now process exits and health should not be okay
+        shutdown.set(true);
+        assert!(!player.is_ok());
+    }
+
+    #[test]
+    fn test_pause() {
+        let req = RefCell::new(None);
+        let (_dir, mut player) = dummy_bin_player(|c| {
+            req.borrow_mut().replace(c.to_vec());
+            Ok(Value::Null)
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+
+        player.pause().unwrap();
+        assert_eq!(
+            Some(vec![json!("set_property"), json!("pause"), json!(true)]),
+            req.borrow_mut().take()
+        );
+        // Calling pause twice should be no-op
+        player.pause().unwrap();
+        assert_eq!(None, req.borrow_mut().take());
+
+        player.resume().unwrap();
+        assert_eq!(
+            Some(vec![json!("set_property"), json!("pause"), json!(false)]),
+            req.borrow_mut().take()
+        );
+        player.resume().unwrap();
+        assert_eq!(None, req.borrow_mut().take());
+
+        // Do not send pause again if its alredy sleeping
+        player.sleep().unwrap();
+        req.borrow_mut().take();
+        player.pause().unwrap();
+        assert_eq!(None, req.borrow_mut().take());
+
+        // Resume can ignore sleep
+        player.resume().unwrap();
+        assert_eq!(
+            Some(vec![json!("set_property"), json!("pause"), json!(false)]),
+            req.borrow_mut().take()
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let (_dir, mut player) = dummy_bin_player(|_| Ok(Value::Null));
+        player.start(SlideshowConfig::default()).unwrap();
+        player.pause().unwrap();
+        player.mute().unwrap();
+
+        // * Process should be terminated and states should be reset
+        player.stop();
+        assert!(player.process.is_none());
+        let status = player.status();
+        assert!(!status.paused && !status.sleeping && !status.muted);
+        assert!(player.reconfigure(&SlideshowConfig::default()).is_err());
+
+        // * Player should be able to start again
+        player.start(SlideshowConfig::default()).unwrap();
+        assert!(player.process.is_some());
+    }
+
+    #[test]
+    fn test_sleep() {
+        let req = RefCell::new(None);
+        let (_dir, mut player) = dummy_bin_player(|c| {
+            req.borrow_mut().replace(c.to_vec());
+            Ok(Value::Null)
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+
+        player.sleep().unwrap();
+        assert_eq!(
+            Some(vec![json!("set_property"), json!("pause"), json!(true)]),
+            req.borrow_mut().take()
+        );
+        // Calling sleep twice should be no-op
+        player.sleep().unwrap();
+        assert_eq!(None, req.borrow_mut().take());
+
+        player.wakeup().unwrap();
+        assert_eq!(
+            Some(vec![json!("set_property"), json!("pause"), json!(false)]),
+            req.borrow_mut().take()
+        );
+        // Calling wakeup twice should be no-op
+        player.wakeup().unwrap();
+        assert_eq!(None, req.borrow_mut().take());
+
+        // Wakeup should not resume if it's pausing
+        player.pause().unwrap();
+        player.sleep().unwrap();
+        req.borrow_mut().take();
+        player.wakeup().unwrap();
+        assert_eq!(None, req.borrow_mut().take());
+    }
+
+    #[test]
+    fn test_mute_and_navigation() {
+        let reqs = RefCell::new(Vec::new());
+        let (_dir, mut player) = dummy_bin_player(|c| {
+            reqs.borrow_mut().push(command_name(c));
+            Ok(Value::Null)
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+        reqs.borrow_mut().clear();
+
+        player.mute().unwrap();
+        player.mute().unwrap();
+        player.unmute().unwrap();
+        player.play_next().unwrap();
+        player.play_back().unwrap();
+        assert_eq!(
+            vec![
+                "set_property mute",
+                "set_property mute",
+                "playlist-next",
+                "playlist-prev"
+            ],
+            *reqs.borrow()
+        );
+    }
+
+    #[test]
+    fn test_update_playlist() {
+        let req = RefCell::new(None);
+        let (dir, mut player) = dummy_bin_player(|c| {
+            req.borrow_mut().replace(c.to_vec());
+            Ok(Value::Null)
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+
+        player
+            .update_playlist(vec![
+                PlaylistItem::new(PathBuf::from("/a.jpg")),
+                PlaylistItem::new(PathBuf::from("/b.mp4")),
+            ])
+            .unwrap();
+        let playlist_file = dir.path().join("mpv.m3u");
+        assert_eq!(
+            Some(vec![
+                json!("loadlist"),
+                json!(playlist_file.to_str().unwrap()),
+                json!("replace"),
+            ]),
+            req.borrow_mut().take()
+        );
+        assert_eq!(
+            "/a.jpg\n/b.mp4\n",
+            fs::read_to_string(&playlist_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_playlist_with_caption() {
+        let reqs = RefCell::new(Vec::new());
+        let (dir, mut player) = dummy_bin_player(|c| {
+            reqs.borrow_mut().push(c.to_vec());
+            Ok(Value::Null)
+        });
+
+        player
+            .start(SlideshowConfig {
+                caption: Some(CaptionConfig::default()),
+                ..SlideshowConfig::default()
+            })
+            .unwrap();
+        let mut item = PlaylistItem::new(PathBuf::from("/a.jpg"));
+        item.meta.description = Some("At the\nbeach".to_string());
+        player.update_playlist(vec![item.clone()]).unwrap();
+        assert_eq!(
+            "#EXTINF:-1,At the beach\n/a.jpg\n",
+            fs::read_to_string(dir.path().join("mpv.m3u")).unwrap()
+        );
+
+        // * Appended items are passed with titles through another playlist file
+        reqs.borrow_mut().clear();
+        player.append_playlist(vec![item]).unwrap();
+        let append_file = dir.path().join("mpv.append.m3u");
+        assert_eq!(
+            vec![vec![
+                json!("loadlist"),
+                json!(append_file.to_str().unwrap()),
+                json!("append"),
+            ]],
+            *reqs.borrow()
+        );
+        assert_eq!(
+            "#EXTINF:-1,At the beach\n/a.jpg\n",
+            fs::read_to_string(&append_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_append_playlist() {
+        let reqs = RefCell::new(Vec::new());
+        let (_dir, mut player) = dummy_bin_player(|c| {
+            reqs.borrow_mut().push(c.to_vec());
+            Ok(Value::Null)
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+        reqs.borrow_mut().clear();
+
+        player
+            .append_playlist(vec![PlaylistItem::new(PathBuf::from("/c.jpg"))])
+            .unwrap();
+        assert_eq!(
+            vec![vec![json!("loadfile"), json!("/c.jpg"), json!("append")]],
+            *reqs.borrow()
+        );
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let reqs = RefCell::new(Vec::new());
+        let (_dir, mut player) = dummy_bin_player(|c| {
+            reqs.borrow_mut().push(c.to_vec());
+            Ok(Value::Null)
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+        reqs.borrow_mut().clear();
+
+        // Nothing should be sent if config isn't changed
+        assert!(player
+            .reconfigure(&SlideshowConfig::default())
+            .unwrap()
+            .is_empty());
+        assert!(reqs.borrow().is_empty());
+
+        let config = SlideshowConfig {
+            show_duration: Duration::from_secs(30),
+            audio_volume: 0.2,
+            ..SlideshowConfig::default()
+        };
+        assert!(player.reconfigure(&config).unwrap().is_empty());
+        assert_eq!(
+            vec![
+                vec![
+                    json!("set_property"),
+                    json!("image-display-duration"),
+                    json!(30)
+                ],
+                vec![json!("set_property"), json!("volume"), json!(20)],
+            ],
+            *reqs.borrow()
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let (_dir, mut player) = dummy_bin_player(|c| match command_name(c).as_ref() {
+            "get_property path" => Ok(json!("/media/a.jpg")),
+            _ => Ok(Value::Null),
+        });
+
+        player.start(SlideshowConfig::default()).unwrap();
+        player.mute().unwrap();
+
+        let status = player.status();
+        assert_eq!(Some(PathBuf::from("/media/a.jpg")), status.current);
+        assert!(status.muted && !status.paused && !status.sleeping);
+    }
+
+    #[test]
+    fn test_unix_socket_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("mpv.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        // Fake mpv answering a command after emitting an unrelated event
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut writer = stream;
+            writeln!(writer, r#"{{"event":"idle"}}"#).unwrap();
+            writeln!(
+                writer,
+                r#"{{"data":"mpv 0.32.0","error":"success","request_id":1}}"#
+            )
+            .unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        });
+
+        let resp = UnixSocketClient
+            .send_command(&socket, &[json!("get_property"), json!("mpv-version")])
+            .unwrap();
+        assert_eq!(json!("mpv 0.32.0"), resp);
+        assert_eq!(
+            json!({"command": ["get_property", "mpv-version"], "request_id": 1}),
+            server.join().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unix_socket_client_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("mpv.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            reader.read_line(&mut String::new()).unwrap();
+            let mut writer = stream;
+            writeln!(writer, r#"{{"error":"property not found","request_id":1}}"#).unwrap();
+        });
+
+        assert!(UnixSocketClient
+            .send_command(&socket, &[json!("get_property"), json!("foo")])
+            .is_err());
+        server.join().unwrap();
+    }
+}
@@ -1,4 +1,6 @@
-use crate::player::{Player, Result, SlideshowConfig};
+use crate::player::{
+    CaptionConfig, CaptionPosition, Player, PlayerStatus, PlaylistItem, Result, SlideshowConfig,
+};
 use elementtree::Element;
 use failure::{format_err, Fail};
 use libc;
@@ -226,6 +228,55 @@ impl<C: HttpClient> VlcPlayer<C> {
         )))
     }
 
+    /// Return the filename of the item currently playing from status XML
+    fn current_filename(element: &Element) -> Option<PathBuf> {
+        element
+            .find("information")?
+            .find_all("category")
+            .filter(|c| c.get_attr("name") == Some("meta"))
+            .flat_map(|c| c.find_all("info"))
+            .find(|info| info.get_attr("name") == Some("filename"))
+            .map(|info| PathBuf::from(info.text()))
+    }
+
+    /// Add the item to the playlist, with its caption as the title shown by marquee
+    fn enqueue(&self, item: &PlaylistItem) -> std::result::Result<(), VlcError> {
+        let input = item.path.to_str().expect("playlist path");
+        match self.config.as_ref().and_then(|c| c.caption.as_ref()) {
+            Some(caption) => {
+                let option = format!(":meta-title={}", caption.render(&item.meta));
+                self.send_status_cmd("in_enqueue", &[("input", input), ("option", &option)])?
+            }
+            None => self.send_status_cmd("in_enqueue", &[("input", input)])?,
+        };
+        Ok(())
+    }
+
+    /// Return the value of `--marq-position` for the caption position
+    fn marq_position(caption: &CaptionConfig) -> u32 {
+        // 1: left, 2: right, 4: top, 8: bottom, and center for 0 on each axis
+        match caption.position {
+            CaptionPosition::TopLeft => 5,
+            CaptionPosition::Top => 4,
+            CaptionPosition::TopRight => 6,
+            CaptionPosition::BottomLeft => 9,
+            CaptionPosition::Bottom => 8,
+            CaptionPosition::BottomRight => 10,
+        }
+    }
+
+    /// Return the arguments to show the title of each item, which is set to its caption, by marquee
+    fn marq_args(caption: &CaptionConfig) -> Vec<String> {
+        vec![
+            "--sub-source".to_string(),
+            "marq".to_string(),
+            "--marq-marquee".to_string(),
+            "$t".to_string(),
+            "--marq-position".to_string(),
+            Self::marq_position(caption).to_string(),
+        ]
+    }
+
     fn maybe_restore_pause(&self) -> std::result::Result<(), VlcError> {
         // Moving resets the pausing state
         if self.locked() {
@@ -281,6 +332,9 @@ impl<C: HttpClient> Player for VlcPlayer<C> {
         if config.fullscreen {
             cmd.arg("--fullscreen");
         }
+        if let Some(caption) = &config.caption {
+            cmd.args(Self::marq_args(caption));
+        }
 
         self.process = Some(cmd.spawn()?);
         self.wait_on_http_interface()?;
@@ -291,6 +345,23 @@ impl<C: HttpClient> Player for VlcPlayer<C> {
         Ok(())
     }
 
+    fn stop(&mut self) {
+        if let Some(mut proc) = self.process.take() {
+            // Rust's Command doesn't support other than SIGKILL in portable interface
+            unsafe {
+                libc::kill(proc.id() as i32, libc::SIGTERM);
+            }
+            match proc.wait() {
+                Ok(status) => debug!("VLC process exit with {}", status.code().unwrap_or(-1)),
+                Err(e) => warn!("Failed to stop VLC process gracefully: {}", e),
+            }
+        }
+        self.config = None;
+        self.pausing = false;
+        self.sleeping = false;
+        self.muting = false;
+    }
+
     fn play_next(&mut self) -> Result<()> {
         self.send_status_cmd("pl_next", &[])?;
         self.maybe_restore_pause()?;
@@ -341,15 +412,15 @@ impl<C: HttpClient> Player for VlcPlayer<C> {
         Ok(())
     }
 
-    fn update_playlist(&mut self, playlist: Vec<PathBuf>) -> Result<()> {
+    fn update_playlist(&mut self, playlist: Vec<PlaylistItem>) -> Result<()> {
         debug!("Start updating playlist");
         // 1. get current playlist
         let old_ids = Self::playlist_ids(self.get_playlist()?)?;
 
         // 2. enqueue all new items
-        for path in playlist {
-            debug!("Adding new item to playlist: {}", path.display());
-            self.send_status_cmd("in_enqueue", &[("input", path.to_str().unwrap())])?;
+        for item in playlist {
+            debug!("Adding new item to playlist: {}", item.path.display());
+            self.enqueue(&item)?;
         }
 
         // 3. move to the head of new items
@@ -370,6 +441,14 @@ impl<C: HttpClient> Player for VlcPlayer<C> {
         Ok(())
     }
 
+    fn append_playlist(&mut self, items: Vec<PlaylistItem>) -> Result<()> {
+        for item in items {
+            debug!("Appending item to playlist: {}", item.path.display());
+            self.enqueue(&item)?;
+        }
+        Ok(())
+    }
+
     fn locked(&self) -> bool {
         self.pausing || self.sleeping
     }
@@ -383,20 +462,51 @@ impl<C: HttpClient> Player for VlcPlayer<C> {
             }
         }
     }
+
+    fn reconfigure(&mut self, config: &SlideshowConfig) -> Result<Vec<&'static str>> {
+        let mut current = self.config()?.clone();
+        let mut restart_required = Vec::new();
+        // These are given only as command line arguments of VLC
+        if config.show_duration != current.show_duration {
+            restart_required.push("show_duration");
+        }
+        if config.fullscreen != current.fullscreen {
+            restart_required.push("fullscreen");
+        }
+        if config.caption != current.caption {
+            restart_required.push("caption");
+        }
+        if config.audio_volume != current.audio_volume {
+            current.audio_volume = config.audio_volume;
+            self.config = Some(current);
+            self.set_volume(self.audio_volume()?)?;
+        }
+        Ok(restart_required)
+    }
+
+    fn status(&self) -> PlayerStatus {
+        let current = match self
+            .send_status_cmd("", &[])
+            .and_then(|xml| Ok(Element::from_reader(xml.into_bytes().as_slice())?))
+        {
+            Ok(element) => Self::current_filename(&element),
+            Err(e) => {
+                debug!("Failed to obtain current item from VLC: {}", e);
+                None
+            }
+        };
+        PlayerStatus {
+            paused: self.pausing,
+            sleeping: self.sleeping,
+            muted: self.muting,
+            current,
+        }
+    }
 }
 
 impl<C: HttpClient> Drop for VlcPlayer<C> {
     fn drop(&mut self) {
-        if let Some(mut proc) = self.process.take() {
-            // Rust's Command doesn't support other than SIGKILL in portable interface
-            unsafe {
-                libc::kill(proc.id() as i32, libc::SIGTERM);
-            }
-            match proc.wait() {
-                Ok(status) => debug!("VLC process exit with {}", status.code().unwrap_or(-1)),
-                Err(e) => warn!("Failed to stop VLC process gracefully: {}", e),
-            }
-        }
+        self.stop();
     }
 }
 
@@ -445,6 +555,111 @@ mod tests {
         (dummy_bin, player)
     }
 
+    #[test]
+    fn test_status() {
+        let player = VlcPlayer::new_with_client(
+            VlcConfig::default(),
+            |path: &str, _: &HashMap<&str, &str>| {
+                assert_eq!("requests/status.xml", path);
+                Ok(r#"<?xml version="1.0" encoding="utf-8" standalone="yes" ?>
+<root>
+  <state>playing</state>
+  <information>
+    <category name="meta">
+      <info name='title'>Title</info>
+      <info name='filename'>abc.jpg</info>
+    </category>
+  </information>
+</root>"#
+                    .to_string())
+            },
+        );
+
+        let status = player.status();
+        assert_eq!(Some(PathBuf::from("abc.jpg")), status.current);
+        assert!(!status.paused && !status.sleeping && !status.muted);
+    }
+
+    #[test]
+    fn test_append_playlist() {
+        let reqs = RefCell::new(Vec::new());
+        let mut player = VlcPlayer::new_with_client(
+            VlcConfig::default(),
+            |_: &str, params: &HashMap<&str, &str>| {
+                reqs.borrow_mut()
+                    .push((params["command"].to_string(), params["input"].to_string()));
+                Ok(String::new())
+            },
+        );
+
+        player
+            .append_playlist(vec![
+                PlaylistItem::new(PathBuf::from("/a.jpg")),
+                PlaylistItem::new(PathBuf::from("/b.mp4")),
+            ])
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("in_enqueue".to_string(), "/a.jpg".to_string()),
+                ("in_enqueue".to_string(), "/b.mp4".to_string()),
+            ],
+            *reqs.borrow()
+        );
+    }
+
+    #[test]
+    fn test_append_playlist_caption() {
+        let reqs = RefCell::new(Vec::new());
+        let mut player = VlcPlayer::new_with_client(
+            VlcConfig::default(),
+            |_: &str, params: &HashMap<&str, &str>| {
+                reqs.borrow_mut().push((
+                    params["input"].to_string(),
+                    params.get("option").map(|o| o.to_string()),
+                ));
+                Ok(String::new())
+            },
+        );
+        player.config = Some(SlideshowConfig {
+            caption: Some(CaptionConfig {
+                format: "{filename}: {description}".to_string(),
+                ..CaptionConfig::default()
+            }),
+            ..SlideshowConfig::default()
+        });
+
+        let mut item = PlaylistItem::new(PathBuf::from("/a.jpg"));
+        item.meta.filename = Some("a.jpg".to_string());
+        item.meta.description = Some("Beach".to_string());
+        player.append_playlist(vec![item]).unwrap();
+        assert_eq!(
+            vec![(
+                "/a.jpg".to_string(),
+                Some(":meta-title=a.jpg: Beach".to_string())
+            )],
+            *reqs.borrow()
+        );
+    }
+
+    #[test]
+    fn test_marq_args() {
+        let caption = CaptionConfig {
+            position: CaptionPosition::TopRight,
+            ..CaptionConfig::default()
+        };
+        assert_eq!(
+            vec![
+                "--sub-source",
+                "marq",
+                "--marq-marquee",
+                "$t",
+                "--marq-position",
+                "6"
+            ],
+            <VlcPlayer>::marq_args(&caption)
+        );
+    }
+
     #[test]
     fn test_is_ok() {
         let shutdown = Cell::new(false);
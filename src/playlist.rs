@@ -1,10 +1,116 @@
 use crate::album::Album;
 use crate::album::AlbumItem;
 use crate::album::Error;
-use log::warn;
-use selector::Selector;
+use crate::album::MediaType;
+use crate::metrics::METRICS;
+use chrono::{Datelike, Local, NaiveDate};
+use failure::format_err;
+use log::{info, warn};
+use selector::{BuildContext, Selector};
+use std::str::FromStr;
 use std::time::Duration;
 
+/// Function creating the selector of a pipeline stage for each build
+type SelectorFactory<I> = Box<dyn Fn(&BuildContext) -> Box<dyn Selector<I>>>;
+
+/// Seed of the randomness choosing and ordering items of a playlist
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Seed {
+    /// Seed chosen randomly on each build, logged to reproduce the playlist
+    Random,
+    /// Same seed on every build, reproducing the playlist from the same album
+    Fixed(u64),
+    /// Seed changing daily, keeping the playlist of the day even across restarts
+    Daily,
+}
+
+impl Seed {
+    fn value(self, today: NaiveDate) -> u64 {
+        match self {
+            Seed::Random => rand::random(),
+            Seed::Fixed(seed) => seed,
+            Seed::Daily => today.num_days_from_ce() as u64,
+        }
+    }
+}
+
+impl FromStr for Seed {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Seed::Random),
+            "daily" => Ok(Seed::Daily),
+            _ => s
+                .parse()
+                .map(Seed::Fixed)
+                .map_err(|_| format_err!("seed must be either random, daily or an integer: {}", s)),
+        }
+    }
+}
+
+/// Duration assumed for videos whose albums don't tell it, e.g. Google Photos
+const DEFAULT_VIDEO_DURATION: Duration = Duration::from_secs(60);
+
+/// Limits on videos in a playlist, to keep long videos from monopolizing the frame
+/// and the download bandwidth
+#[derive(Debug, Clone)]
+struct VideoLimits {
+    max_count: Option<usize>,
+    /// Maximum fraction of videos in the playlist, counted against `min_size` at least
+    max_fraction: Option<f64>,
+    /// Maximum total duration of videos
+    max_duration: Option<Duration>,
+    /// Duration counted against `max_duration` for videos of unknown duration
+    default_duration: Duration,
+}
+
+impl Default for VideoLimits {
+    fn default() -> Self {
+        VideoLimits {
+            max_count: None,
+            max_fraction: None,
+            max_duration: None,
+            default_duration: DEFAULT_VIDEO_DURATION,
+        }
+    }
+}
+
+/// Videos selected into a playlist so far, to check against `VideoLimits`
+#[derive(Debug, Default)]
+struct VideoUsage {
+    count: usize,
+    duration: Duration,
+}
+
+impl VideoLimits {
+    /// Return whether the item can be added to the playlist, which will be of
+    /// at least `size` items, updating the usage if it's a video.
+    fn admit<T: AlbumItem>(&self, item: &T, usage: &mut VideoUsage, size: usize) -> bool {
+        if item.media_type() != MediaType::VIDEO {
+            return true;
+        }
+        let count = usage.count + 1;
+        let duration = usage.duration + item.duration().unwrap_or(self.default_duration);
+        if matches!(self.max_count, Some(max) if count > max)
+            || matches!(self.max_fraction, Some(max) if count as f64 > max * size as f64)
+            || matches!(self.max_duration, Some(max) if duration > max)
+        {
+            return false;
+        }
+        usage.count = count;
+        usage.duration = duration;
+        true
+    }
+}
+
+/// Stage of the selector pipeline
+struct Stage<I> {
+    factory: SelectorFactory<I>,
+    /// Maximum number of items this stage contributes to the playlist
+    quota: Option<usize>,
+}
+
 /// Build a playlist given stream of available contents.
 ///
 /// First decide the `min_size` to be present on the list.
@@ -16,16 +122,33 @@ use std::time::Duration;
 /// selected and filled in for the remaining slots.
 /// If "fresh items" were found more than `min_size`, the playlist size
 /// will be extended up to `max_size`.
-pub struct PlaylistBuilder {
+///
+/// Optionally, old items created around today's date in previous years are
+/// picked up before the random old items, see `on_this_day`.
+///
+/// The pipeline of selectors above can be replaced by `selector`, which
+/// gives each item to the stages in order until one of them takes it.
+///
+/// Randomness is seeded by `seed` to reproduce playlists, randomly by default.
+///
+/// Videos exceeding the limits set by `max_videos`, `max_video_fraction` or
+/// `video_duration_budget` are left out of the playlist, whichever selector chose them.
+pub struct PlaylistBuilder<I: AlbumItem> {
     /// Expected minimum items to be present in the list
     min_size: usize,
     /// Maximum items to be present in the list
     max_size: usize,
     /// Time threshold to decide if an item is "fresh" or not
     fresh_retention: Duration,
+    /// Pair of the window in days and the max number of "on this day" items, None to disable
+    on_this_day: Option<(u32, usize)>,
+    /// Selector pipeline to build with, the default pipeline is used if empty
+    stages: Vec<Stage<I>>,
+    video_limits: VideoLimits,
+    seed: Seed,
 }
 
-impl PlaylistBuilder {
+impl<I: AlbumItem + 'static> PlaylistBuilder<I> {
     pub fn new() -> Self {
         Default::default()
     }
@@ -45,17 +168,100 @@ impl PlaylistBuilder {
         self
     }
 
-    pub fn updated<'a, T: Album>(
+    /// Prioritize old items created within `window_days` of today's date in
+    /// previous years, picking up to `max_items` of them weighted by closeness to the date.
+    ///
+    /// With a custom pipeline, only the window is applied to the `on_this_day` stage.
+    pub fn on_this_day(mut self, window_days: u32, max_items: usize) -> Self {
+        self.on_this_day = Some((window_days, max_items));
+        self
+    }
+
+    /// Limit the number of videos in the playlist
+    pub fn max_videos(mut self, max_videos: usize) -> Self {
+        self.video_limits.max_count = Some(max_videos);
+        self
+    }
+
+    /// Limit the fraction of videos in the playlist, between 0.0 and 1.0
+    pub fn max_video_fraction(mut self, fraction: f64) -> Self {
+        self.video_limits.max_fraction = Some(fraction);
+        self
+    }
+
+    /// Limit the total duration of videos in the playlist
+    pub fn video_duration_budget(mut self, budget: Duration) -> Self {
+        self.video_limits.max_duration = Some(budget);
+        self
+    }
+
+    /// Set the duration counted against `video_duration_budget` for videos whose
+    /// duration is unknown, which is 60 seconds by default
+    pub fn default_video_duration(mut self, duration: Duration) -> Self {
+        self.video_limits.default_duration = duration;
+        self
+    }
+
+    /// Set the seed of randomness, to reproduce playlists or keep them for a day
+    pub fn seed(mut self, seed: Seed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Append a stage to the selector pipeline, replacing the default one.
+    ///
+    /// The factory is called on each build to create a selector, which takes items
+    /// passed over by the preceding stages. The stage contributes at most `quota`
+    /// items to the playlist if given, which is passed to the factory as
+    /// `BuildContext::quota`. Selectors should pass items beyond it over to the
+    /// following stages, as items they took and didn't contribute are left out.
+    /// Each stage gets its own seed of randomness in `BuildContext::seed`.
+    pub fn selector<F>(mut self, quota: Option<usize>, factory: F) -> Self
+    where
+        F: Fn(&BuildContext) -> Box<dyn Selector<I>> + 'static,
+    {
+        self.stages.push(Stage {
+            factory: Box::new(factory),
+            quota,
+        });
+        self
+    }
+
+    /// Append a stage of the built-in selector of the name, returning None if it's unknown.
+    /// See `selector::BUILTIN_NAMES` for available names.
+    pub fn builtin_selector(self, name: &str, quota: Option<usize>) -> Option<Self> {
+        let factory = selector::builtin::<I>(name)?;
+        Some(self.selector(quota, factory))
+    }
+
+    fn context(&self, today: NaiveDate, seed: u64, quota: Option<usize>) -> BuildContext {
+        BuildContext {
+            min_size: self.min_size,
+            max_size: self.max_size,
+            fresh_retention: self.fresh_retention,
+            on_this_day_window: self
+                .on_this_day
+                .map_or(selector::DEFAULT_ON_THIS_DAY_WINDOW, |(window, _)| window),
+            today,
+            quota,
+            seed,
+        }
+    }
+
+    pub fn updated<'a, T: Album<Item = I>>(
         &self,
         album: &T,
         playlist: &'a [T::Item],
     ) -> Result<Option<Vec<T::Item>>, T::E> {
         let updated = self.do_build(
-            Selectors::new(vec![Box::new(selector::PreviousItemSelector::new(
-                self.fresh_retention,
-                self.max_size,
-                playlist.iter(),
-            ))]),
+            Selectors::new(vec![(
+                Box::new(selector::PreviousItemSelector::new(
+                    self.fresh_retention,
+                    self.max_size,
+                    playlist.iter(),
+                )),
+                None,
+            )]),
             album,
         )?;
         Ok(if updated == playlist {
@@ -65,19 +271,49 @@ impl PlaylistBuilder {
         })
     }
 
-    pub fn build<T: Album>(&self, album: &T) -> Result<Vec<T::Item>, T::E> {
-        self.do_build(
-            Selectors::new(vec![
-                Box::new(selector::FreshItemSelector::new(self.fresh_retention)),
-                Box::new(selector::OldItemSelector::new(self.min_size)),
-            ]),
-            album,
-        )
+    pub fn build<T: Album<Item = I>>(&self, album: &T) -> Result<Vec<T::Item>, T::E> {
+        let today = Local::today().naive_local();
+        let seed = self.seed.value(today);
+        info!("Building playlist with seed {}", seed);
+
+        let default_stages;
+        let stages = if self.stages.is_empty() {
+            default_stages = self.default_stages();
+            &default_stages
+        } else {
+            &self.stages
+        };
+        let selectors = stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| {
+                // Each stage gets its own sequence of randomness
+                let ctx = self.context(today, seed.wrapping_add(i as u64), stage.quota);
+                ((stage.factory)(&ctx), stage.quota)
+            })
+            .collect();
+        self.do_build(Selectors::new(selectors), album)
+    }
+
+    /// Return the default pipeline of fresh, on this day if enabled, then old items
+    fn default_stages(&self) -> Vec<Stage<I>> {
+        let mut names = vec![("fresh", None)];
+        if let Some((_, max_items)) = self.on_this_day {
+            names.push(("on_this_day", Some(max_items)));
+        }
+        names.push(("old", None));
+        names
+            .into_iter()
+            .map(|(name, quota)| Stage {
+                factory: Box::new(selector::builtin(name).expect("built-in selector")),
+                quota,
+            })
+            .collect()
     }
 
-    fn do_build<T: Album>(
+    fn do_build<'a, T: Album<Item = I>>(
         &self,
-        mut selectors: Selectors<T::Item>,
+        mut selectors: Selectors<'a, T::Item>,
         album: &T,
     ) -> Result<Vec<T::Item>, T::E> {
         let mut first_error = None;
@@ -104,7 +340,7 @@ impl PlaylistBuilder {
             selectors.consume(item);
         }
 
-        let pl = selectors.select(self.min_size, self.max_size);
+        let pl = selectors.select(self.min_size, self.max_size, &self.video_limits);
         // If the result is empty and there was an error from album API, we should not
         // likely ignore the error we seen.
         if pl.is_empty() {
@@ -116,27 +352,33 @@ impl PlaylistBuilder {
     }
 }
 
-impl Default for PlaylistBuilder {
+impl<I: AlbumItem> Default for PlaylistBuilder<I> {
     fn default() -> Self {
         PlaylistBuilder {
             min_size: 30,
             max_size: 100,
             fresh_retention: Duration::from_secs(3600 * 24 * 14), // 2 weeks
+            on_this_day: None,
+            stages: Vec::new(),
+            video_limits: VideoLimits::default(),
+            seed: Seed::Random,
         }
     }
 }
 
+/// Selectors of the pipeline paired with their quota
 struct Selectors<'a, T: AlbumItem> {
-    impls: Vec<Box<dyn Selector<T> + 'a>>,
+    impls: Vec<(Box<dyn Selector<T> + 'a>, Option<usize>)>,
 }
 
 impl<'a, T: AlbumItem> Selectors<'a, T> {
-    fn new(impls: Vec<Box<dyn Selector<T> + 'a>>) -> Self {
+    fn new(impls: Vec<(Box<dyn Selector<T> + 'a>, Option<usize>)>) -> Self {
         Self { impls }
     }
 
     fn consume(&mut self, mut item: T) {
-        for selector in &mut self.impls {
+        for (selector, _) in &mut self.impls {
+            // Selectors keep taking items beyond their quota to sample randomly among them
             if let Some(it) = selector.take(item) {
                 item = it;
             } else {
@@ -146,14 +388,19 @@ impl<'a, T: AlbumItem> Selectors<'a, T> {
     }
 
     fn locked_count(&self) -> usize {
-        self.impls.iter().map(|s| s.locked_count()).sum()
+        self.impls
+            .iter()
+            .map(|(s, quota)| quota.map_or(s.locked_count(), |q| s.locked_count().min(q)))
+            .sum()
     }
 
-    fn select(self, min_count: usize, max_count: usize) -> Vec<T> {
+    fn select(self, min_count: usize, max_count: usize, limits: &VideoLimits) -> Vec<T> {
         let mut items = Vec::new();
-        'outer: for selector in self.impls {
+        let mut videos = VideoUsage::default();
+        let mut counts: Vec<_> = self.impls.iter().map(|(s, _)| (s.name(), 0)).collect();
+        'outer: for (i, (selector, quota)) in self.impls.into_iter().enumerate() {
             let mut locked = selector.locked_count();
-            for item in selector.drain() {
+            for item in selector.drain().take(quota.unwrap_or(usize::MAX)) {
                 if items.len() >= max_count {
                     break 'outer;
                 }
@@ -163,48 +410,144 @@ impl<'a, T: AlbumItem> Selectors<'a, T> {
                 if locked > 0 {
                     locked -= 1;
                 }
+                if !limits.admit(&item, &mut videos, min_count.max(items.len() + 1)) {
+                    continue;
+                }
                 items.push(item);
+                counts[i].1 += 1;
             }
         }
+        METRICS.playlist_items.replace(counts);
         items
     }
 }
 
-mod selector {
+/// Selectors choosing items of a playlist, which can be composed into a pipeline
+/// by `PlaylistBuilder::selector`.
+pub mod selector {
     use crate::album::AlbumItem;
+    use chrono::{DateTime, Datelike, Local, NaiveDate};
     use log::debug;
-    use rand::Rng;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
     use std::cmp::Reverse;
     use std::collections::HashMap;
     use std::fmt::Debug;
     use std::time::Duration;
     use std::time::SystemTime;
 
-    pub(super) trait Selector<I: AlbumItem> {
+    /// Window in days of the `on_this_day` stage unless configured
+    pub const DEFAULT_ON_THIS_DAY_WINDOW: u32 = 3;
+
+    /// Names of the built-in selectors available by `PlaylistBuilder::builtin_selector`
+    pub const BUILTIN_NAMES: &[&str] = &["fresh", "on_this_day", "old"];
+
+    /// Parameters given to create the selector of a pipeline stage
+    #[derive(Debug, Clone)]
+    pub struct BuildContext {
+        pub min_size: usize,
+        pub max_size: usize,
+        pub fresh_retention: Duration,
+        pub on_this_day_window: u32,
+        /// Date of the build in local time
+        pub today: NaiveDate,
+        /// Quota of the stage being created, if any. Selectors should pass items
+        /// over to the following stages rather than taking more than this.
+        pub quota: Option<usize>,
+        /// Seed of randomness for the stage being created
+        pub seed: u64,
+    }
+
+    impl BuildContext {
+        /// Return a random number generator seeded for the stage.
+        /// The generator is portable, so the same seed reproduces the same playlist
+        /// across platforms and versions of rand.
+        pub fn rng(&self) -> ChaCha8Rng {
+            ChaCha8Rng::seed_from_u64(self.seed)
+        }
+    }
+
+    /// Stage of the pipeline choosing items of a playlist.
+    ///
+    /// Each item in the album is given to stages in order until one of them takes it.
+    pub trait Selector<I: AlbumItem> {
+        /// Name to distinguish items chosen by this selector in metrics
+        fn name(&self) -> &'static str;
+
+        /// Take the item if it's of interest, otherwise return it to pass it over
+        /// to the following stages. Selectors can also return another item taken
+        /// earlier instead, e.g. to keep a random sample.
         fn take(&mut self, item: I) -> Option<I>;
 
+        /// Return the number of items which must be in the playlist regardless of `min_size`
         fn locked_count(&self) -> usize;
 
+        /// Return items taken in the order to be played
         fn drain(self: Box<Self>) -> Box<dyn Iterator<Item = I>>;
     }
 
-    pub(super) struct FreshItemSelector<I> {
+    /// Function creating a built-in selector
+    pub type BuiltinFactory<I> = fn(&BuildContext) -> Box<dyn Selector<I>>;
+
+    /// Return the factory of the built-in selector of the name
+    pub fn builtin<I: AlbumItem + 'static>(name: &str) -> Option<BuiltinFactory<I>> {
+        match name {
+            "fresh" => Some(|ctx| {
+                let mut selector = FreshItemSelector::new(ctx.fresh_retention);
+                if let Some(quota) = ctx.quota {
+                    selector = selector.max_items(quota);
+                }
+                Box::new(selector)
+            }),
+            "on_this_day" => Some(|ctx| {
+                Box::new(OnThisDaySelector::new(
+                    ctx.today,
+                    ctx.on_this_day_window,
+                    ctx.quota.unwrap_or(ctx.max_size),
+                    ctx.rng(),
+                ))
+            }),
+            // Sample beyond min_size, so videos left out by limits are made up for by the rest
+            "old" => Some(|ctx| {
+                Box::new(OldItemSelector::new(
+                    ctx.quota.map_or(ctx.max_size, |q| q.min(ctx.max_size)),
+                    ctx.rng(),
+                ))
+            }),
+            _ => None,
+        }
+    }
+
+    /// Selector of items created within the fresh retention, newer first
+    pub struct FreshItemSelector<I> {
         min_fresh_time: SystemTime,
+        max_items: usize,
         items: Vec<I>,
     }
 
     impl<T> FreshItemSelector<T> {
-        pub(super) fn new(fresh_retention: Duration) -> Self {
+        pub fn new(fresh_retention: Duration) -> Self {
             Self {
                 min_fresh_time: SystemTime::now() - fresh_retention,
+                max_items: usize::MAX,
                 items: Vec::new(),
             }
         }
+
+        /// Pass fresh items over to the following stages once taken this many
+        pub fn max_items(mut self, max_items: usize) -> Self {
+            self.max_items = max_items;
+            self
+        }
     }
 
     impl<I: AlbumItem + 'static> Selector<I> for FreshItemSelector<I> {
+        fn name(&self) -> &'static str {
+            "fresh"
+        }
+
         fn take(&mut self, item: I) -> Option<I> {
-            if item.created_time() >= self.min_fresh_time {
+            if item.created_time() >= self.min_fresh_time && self.items.len() < self.max_items {
                 debug!(
                     "Adding item as FRESH; id={}, time={}",
                     item.id(),
@@ -230,21 +573,26 @@ mod selector {
         }
     }
 
-    pub(super) struct OldItemSelector<I: Debug> {
+    /// Selector of random items, filling the playlist up to `min_size`
+    pub struct OldItemSelector<I: Debug> {
         max_items: usize,
         rand_slots: RandomSlots<I>,
     }
 
     impl<I: AlbumItem> OldItemSelector<I> {
-        pub(super) fn new(max_items: usize) -> Self {
+        pub fn new(max_items: usize, rng: ChaCha8Rng) -> Self {
             Self {
                 max_items,
-                rand_slots: RandomSlots::new(max_items),
+                rand_slots: RandomSlots::new(max_items, rng),
             }
         }
     }
 
     impl<I: AlbumItem + 'static> Selector<I> for OldItemSelector<I> {
+        fn name(&self) -> &'static str {
+            "old"
+        }
+
         fn take(&mut self, item: I) -> Option<I> {
             debug!(
                 "Adding item as OLD; id={}, time={}",
@@ -266,6 +614,100 @@ mod selector {
         }
     }
 
+    /// Selector of items created around today's date in previous years
+    pub struct OnThisDaySelector<I> {
+        today: NaiveDate,
+        window_days: u32,
+        max_items: usize,
+        /// Pairs of the sampling key and the item, keeping ones with the largest keys
+        items: Vec<(f64, I)>,
+        rng: ChaCha8Rng,
+    }
+
+    impl<I: AlbumItem> OnThisDaySelector<I> {
+        pub fn new(today: NaiveDate, window_days: u32, max_items: usize, rng: ChaCha8Rng) -> Self {
+            Self {
+                today,
+                window_days,
+                max_items,
+                items: Vec::with_capacity(max_items),
+                rng,
+            }
+        }
+
+        /// Return the number of days between today and the anniversary of the date closest to
+        /// today, or None if the date isn't of previous years.
+        pub(super) fn days_apart(&self, date: NaiveDate) -> Option<u32> {
+            if date.year() >= self.today.year() {
+                return None;
+            }
+            (self.today.year() - 1..=self.today.year() + 1)
+                .filter_map(|year| {
+                    // Anniversary of Feb 29 is Feb 28 in non-leap years
+                    date.with_year(year)
+                        .or_else(|| NaiveDate::from_ymd_opt(year, 2, 28))
+                })
+                .map(|anniversary| (anniversary - self.today).num_days().unsigned_abs() as u32)
+                .min()
+        }
+    }
+
+    impl<I: AlbumItem + 'static> Selector<I> for OnThisDaySelector<I> {
+        fn name(&self) -> &'static str {
+            "on_this_day"
+        }
+
+        fn take(&mut self, item: I) -> Option<I> {
+            let date = DateTime::<Local>::from(item.created_time())
+                .naive_local()
+                .date();
+            let days = match self.days_apart(date) {
+                Some(days) if days <= self.window_days => days,
+                _ => return Some(item),
+            };
+            if self.max_items == 0 {
+                return Some(item);
+            }
+            debug!(
+                "Adding item as ON THIS DAY; id={}, date={}",
+                item.id(),
+                date
+            );
+
+            // Weighted random sampling by Efraimidis and Spirakis, closer items weigh more
+            let weight = f64::from(self.window_days - days + 1);
+            let key = self.rng.gen::<f64>().powf(1.0 / weight);
+            if self.items.len() < self.max_items {
+                self.items.push((key, item));
+                return None;
+            }
+            let (min_idx, min_key) = self
+                .items
+                .iter()
+                .enumerate()
+                .map(|(i, (k, _))| (i, *k))
+                .fold(
+                    (0, f64::MAX),
+                    |min, cur| if cur.1 < min.1 { cur } else { min },
+                );
+            if key <= min_key {
+                return Some(item);
+            }
+            let (_, evicted) = std::mem::replace(&mut self.items[min_idx], (key, item));
+            Some(evicted)
+        }
+
+        fn locked_count(&self) -> usize {
+            self.items.len()
+        }
+
+        fn drain(mut self: Box<Self>) -> Box<dyn Iterator<Item = I>> {
+            self.items
+                .sort_unstable_by_key(|(_, item)| Reverse(item.created_time()));
+            Box::new(self.items.into_iter().map(|(_, item)| item))
+        }
+    }
+
     pub(super) struct PreviousItemSelector<'a, I> {
         max_items: usize,
         prev_items: Vec<Option<I>>,
@@ -308,6 +750,10 @@ mod selector {
     }
 
     impl<'a, I: AlbumItem + 'static> Selector<I> for PreviousItemSelector<'a, I> {
+        fn name(&self) -> &'static str {
+            "previous"
+        }
+
         fn take(&mut self, item: I) -> Option<I> {
             if let Some(&slot) = self.order_map.get(item.id()) {
                 debug!(
@@ -356,16 +802,16 @@ mod selector {
     struct RandomSlots<T: std::fmt::Debug> {
         capacity: usize,
         slots: Vec<Option<T>>,
-        rng: rand::rngs::ThreadRng,
+        rng: ChaCha8Rng,
         count: usize,
     }
 
     impl<T: std::fmt::Debug> RandomSlots<T> {
-        fn new(capacity: usize) -> Self {
+        fn new(capacity: usize, rng: ChaCha8Rng) -> Self {
             RandomSlots {
                 capacity,
                 slots: Vec::with_capacity(capacity),
-                rng: rand::thread_rng(),
+                rng,
                 count: 0,
             }
         }
@@ -408,6 +854,7 @@ mod selector {
 mod tests {
     use super::*;
     use crate::album::{self, Album, AlbumItem, MediaType};
+    use chrono::NaiveDate;
     use failure::{self, Fail};
     use std::path::Path;
     use std::time::SystemTime;
@@ -457,7 +904,19 @@ mod tests {
         }
 
         fn media_type(&self) -> MediaType {
-            panic!("not implemented");
+            if self.0.starts_with("vid") {
+                MediaType::VIDEO
+            } else {
+                MediaType::PHOTO
+            }
+        }
+
+        fn duration(&self) -> Option<Duration> {
+            // Videos named "vidu-*" are of unknown duration
+            match self.media_type() {
+                MediaType::VIDEO if !self.0.starts_with("vidu") => Some(Duration::from_secs(60)),
+                _ => None,
+            }
         }
 
         fn created_time(&self) -> SystemTime {
@@ -521,7 +980,7 @@ mod tests {
             .collect()
     }
 
-    fn setup() -> (Times, PlaylistBuilder) {
+    fn setup() -> (Times, PlaylistBuilder<MockAlbumItem>) {
         let times = Times::new();
         let builder = PlaylistBuilder::new()
             .min_size(3)
@@ -587,16 +1046,239 @@ mod tests {
             times.old("old-b"),
             times.old("old-c"),
         ]);
+        let builder = builder.seed(Seed::Fixed(0));
         let pivot = builder.build(&album).unwrap();
-        let mut all_same = true;
+        // * Same seed reproduces the same playlist
         for _ in 0..10 {
-            let pl = builder.build(&album).unwrap();
-            if pivot != pl {
-                all_same = false;
-                break;
+            assert!(pivot == builder.build(&album).unwrap());
+        }
+        // * Another seed orders differently
+        let pl = setup().1.seed(Seed::Fixed(1)).build(&album).unwrap();
+        assert_eq!(vec!["old-b", "old-a", "old-c"], names(pivot));
+        assert_eq!(vec!["old-c", "old-b", "old-a"], names(pl));
+    }
+
+    #[test]
+    fn test_seed() {
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        let today = date(2020, 3, 1);
+        assert_eq!(Seed::Random, "random".parse().unwrap());
+        assert_eq!(Seed::Daily, "daily".parse().unwrap());
+        assert_eq!(Seed::Fixed(42), "42".parse().unwrap());
+        assert!("-1".parse::<Seed>().is_err());
+        assert!("weekly".parse::<Seed>().is_err());
+
+        assert_eq!(42, Seed::Fixed(42).value(today));
+        assert_eq!(Seed::Daily.value(today), Seed::Daily.value(today));
+        assert_ne!(
+            Seed::Daily.value(today),
+            Seed::Daily.value(date(2020, 3, 2))
+        );
+    }
+
+    #[test]
+    fn test_build_on_this_day() {
+        let (mut times, builder) = setup();
+        let builder = builder.on_this_day(3, 2);
+        let years_ago = |name, years: u64| {
+            (
+                name,
+                SystemTime::now() - Duration::from_secs(3600 * 24 * 365 * years),
+            )
+        };
+
+        let pl = builder
+            .build(&album(vec![
+                times.old("old-a"),
+                years_ago("otd-a", 1),
+                times.old("old-b"),
+                years_ago("otd-b", 2),
+                (
+                    "old-c",
+                    SystemTime::now() - Duration::from_secs(3600 * 24 * 180),
+                ),
+                times.fresh("new-a"),
+            ]))
+            .unwrap();
+        // * Items around today's date in previous years precede old items, newer first
+        // * They are chosen regardless of min_size as fresh items are
+        let got = names(pl);
+        assert_eq!(vec!["new-a", "otd-a", "otd-b"], got);
+
+        let pl = builder
+            .on_this_day(3, 1)
+            .build(&album(vec![
+                years_ago("otd-a", 1),
+                years_ago("otd-b", 2),
+                years_ago("otd-c", 3),
+            ]))
+            .unwrap();
+        // * Items exceeding max items are left for old items
+        assert_eq!(3, pl.len());
+    }
+
+    #[test]
+    fn test_build_video_limits() {
+        let (mut times, builder) = setup();
+        let items = vec![
+            times.fresh("vid-a"),
+            times.fresh("vid-b"),
+            times.fresh("new-a"),
+            times.fresh("vid-c"),
+            times.old("old-a"),
+            times.old("old-b"),
+            times.old("vid-d"),
+        ];
+
+        let pl = builder.max_videos(1).build(&album(items.clone())).unwrap();
+        // * Videos beyond the limit are left out, filled up by other items instead
+        let got = names(pl);
+        assert_eq!(3, got.len());
+        assert_eq!(vec!["vid-a", "new-a"], &got[0..=1]);
+        assert!(got[2] == "old-a" || got[2] == "old-b");
+
+        let (_, builder) = setup();
+        let pl = builder
+            .video_duration_budget(Duration::from_secs(150))
+            .build(&album(items.clone()))
+            .unwrap();
+        // * Total duration of videos is kept within the budget
+        assert_eq!(vec!["vid-a", "vid-b", "new-a"], names(pl));
+
+        let (_, builder) = setup();
+        let pl = builder
+            .max_video_fraction(0.4)
+            .build(&album(items))
+            .unwrap();
+        // * Fraction is counted against min_size at least
+        let got = names(pl);
+        assert_eq!(vec!["vid-a", "new-a"], &got[0..=1]);
+        assert!(got.iter().filter(|id| id.starts_with("vid")).count() <= 1);
+    }
+
+    #[test]
+    fn test_build_video_unknown_duration() {
+        let (mut times, builder) = setup();
+        let items = vec![
+            times.fresh("vidu-a"),
+            times.fresh("vidu-b"),
+            times.fresh("vid-c"),
+            times.fresh("new-a"),
+        ];
+
+        let pl = builder
+            .video_duration_budget(Duration::from_secs(150))
+            .build(&album(items.clone()))
+            .unwrap();
+        // * Videos of unknown duration are counted by the default duration
+        assert_eq!(vec!["vidu-a", "vidu-b", "new-a"], names(pl));
+
+        let (_, builder) = setup();
+        let pl = builder
+            .video_duration_budget(Duration::from_secs(150))
+            .default_video_duration(Duration::from_secs(100))
+            .build(&album(items))
+            .unwrap();
+        assert_eq!(vec!["vidu-a", "new-a"], &names(pl)[0..=1]);
+    }
+
+    /// Selector taking all items whose ID starts with "fav"
+    struct FavoriteSelector(Vec<MockAlbumItem>, usize);
+
+    impl Selector<MockAlbumItem> for FavoriteSelector {
+        fn name(&self) -> &'static str {
+            "favorite"
+        }
+
+        fn take(&mut self, item: MockAlbumItem) -> Option<MockAlbumItem> {
+            if item.0.starts_with("fav") && self.0.len() < self.1 {
+                self.0.push(item);
+                return None;
             }
+            Some(item)
         }
-        assert!(!all_same);
+
+        fn locked_count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn drain(self: Box<Self>) -> Box<dyn Iterator<Item = MockAlbumItem>> {
+            Box::new(self.0.into_iter())
+        }
+    }
+
+    #[test]
+    fn test_build_custom_pipeline() {
+        let (mut times, builder) = setup();
+        let builder = builder
+            .selector(Some(1), |ctx| {
+                Box::new(FavoriteSelector(Vec::new(), ctx.quota.unwrap()))
+            })
+            .builtin_selector("fresh", None)
+            .unwrap()
+            .builtin_selector("old", None)
+            .unwrap();
+
+        let pl = builder
+            .build(&album(vec![
+                times.old("fav-a"),
+                times.old("old-a"),
+                times.old("fav-b"),
+                times.fresh("new-a"),
+            ]))
+            .unwrap();
+        // * Stages are applied in order
+        // * Items exceeding the quota of a stage are passed over to the following stages
+        let got = names(pl);
+        assert_eq!(3, got.len());
+        assert_eq!(vec!["fav-a", "new-a"], &got[0..=1]);
+        assert!(got[2] == "old-a" || got[2] == "fav-b");
+
+        let (mut times, builder) = setup();
+        let pl = builder
+            .builtin_selector("fresh", Some(1))
+            .unwrap()
+            .builtin_selector("old", None)
+            .unwrap()
+            .build(&album(vec![
+                times.fresh("new-a"),
+                times.fresh("new-b"),
+                times.fresh("new-c"),
+                times.fresh("new-d"),
+            ]))
+            .unwrap();
+        // * Built-in selectors are limited by quota as well
+        let got = names(pl);
+        assert_eq!(3, got.len());
+        assert_eq!("new-a", got[0]);
+
+        assert!(PlaylistBuilder::<MockAlbumItem>::new()
+            .builtin_selector("unknown", None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_on_this_day_days_apart() {
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        let rng = || rand::SeedableRng::seed_from_u64(0);
+        let selector =
+            selector::OnThisDaySelector::<MockAlbumItem>::new(date(2020, 3, 1), 3, 10, rng());
+        assert_eq!(Some(2), selector.days_apart(date(2019, 2, 28)));
+        assert_eq!(Some(0), selector.days_apart(date(2010, 3, 1)));
+        // * This year's items aren't of the day
+        assert_eq!(None, selector.days_apart(date(2020, 3, 1)));
+        assert_eq!(None, selector.days_apart(date(2020, 2, 29)));
+
+        let selector =
+            selector::OnThisDaySelector::<MockAlbumItem>::new(date(2021, 3, 1), 3, 10, rng());
+        // * Feb 29 is on Feb 28 in non-leap years
+        assert_eq!(Some(1), selector.days_apart(date(2016, 2, 29)));
+
+        let selector =
+            selector::OnThisDaySelector::<MockAlbumItem>::new(date(2021, 1, 1), 3, 10, rng());
+        // * Window spans over the turn of the year
+        assert_eq!(Some(1), selector.days_apart(date(2019, 12, 31)));
+        assert_eq!(Some(182), selector.days_apart(date(2019, 7, 3)));
     }
 
     #[test]
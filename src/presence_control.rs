@@ -0,0 +1,249 @@
+use crate::calendar::Calendar;
+use crate::control::{Commander, PlayerCmd};
+use crate::gpio_control::LineSource;
+use crate::metrics::METRICS;
+use crate::schedule_control::last_scheduled;
+use chrono::Local;
+use log::{debug, error, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Maximum interval to check the terminate flag and the schedule
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Duration to wait before retrying after failing to read the sensor
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct PresenceConfig {
+    /// Duration without motion to put the slideshow into sleep
+    pub idle_timeout: Duration,
+    /// Whether motion wakes up the slideshow during scheduled sleep
+    pub wake_during_sleep: bool,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            idle_timeout: Duration::from_secs(600),
+            wake_during_sleep: false,
+        }
+    }
+}
+
+/// State of presence tracked from motion sensor's level changes
+#[derive(Debug)]
+struct PresenceState {
+    /// True while the sensor is detecting motion
+    motion: bool,
+    /// Time motion was detected last
+    last_motion: Instant,
+    /// True while the slideshow is supposed to be sleeping
+    asleep: bool,
+    /// True while in the period of scheduled sleep
+    quiet: bool,
+}
+
+impl PresenceState {
+    fn new(motion: bool, quiet: bool, now: Instant) -> Self {
+        PresenceState {
+            motion,
+            last_motion: now,
+            // Scheduler catches up with the last sleep on startup
+            asleep: quiet,
+            quiet,
+        }
+    }
+
+    fn set_motion(&mut self, motion: bool, at: Instant) {
+        self.motion = motion;
+        self.last_motion = at;
+    }
+
+    /// Return the command to send for the current motion and schedule
+    fn update(&mut self, quiet: bool, now: Instant, config: &PresenceConfig) -> Option<PlayerCmd> {
+        if quiet != self.quiet {
+            // Scheduler sends the command, presence is tracked from there
+            debug!("Scheduled sleep period changed: quiet={}", quiet);
+            self.quiet = quiet;
+            self.asleep = quiet;
+            self.last_motion = now;
+            return None;
+        }
+        if self.motion {
+            self.last_motion = now;
+        }
+
+        if self.asleep {
+            if self.motion && (!quiet || config.wake_during_sleep) {
+                self.asleep = false;
+                return Some(PlayerCmd::Wakeup);
+            }
+        } else if !self.motion
+            && now.saturating_duration_since(self.last_motion) >= config.idle_timeout
+        {
+            self.asleep = true;
+            return Some(PlayerCmd::Sleep);
+        }
+        None
+    }
+
+    /// Return the time at which `update` may send sleep without motion changes
+    fn deadline(&self, config: &PresenceConfig) -> Option<Instant> {
+        if self.asleep || self.motion {
+            None
+        } else {
+            Some(self.last_motion + config.idle_timeout)
+        }
+    }
+}
+
+/// Commander putting the slideshow into sleep when nobody is around, by a motion sensor
+/// such as PIR connected to GPIO.
+///
+/// Sleep and wakeup schedules are given to let the slideshow keep sleeping at night
+/// even if motion is detected, unless configured otherwise.
+pub struct PresenceCommander {
+    lines: Box<dyn LineSource>,
+    /// Level of the sensor's pin while detecting motion
+    active_high: bool,
+    schedules: Vec<(Calendar, PlayerCmd)>,
+    config: PresenceConfig,
+}
+
+impl PresenceCommander {
+    pub fn new(lines: Box<dyn LineSource>, active_high: bool, config: PresenceConfig) -> Self {
+        PresenceCommander {
+            lines,
+            active_high,
+            schedules: Vec::new(),
+            config,
+        }
+    }
+
+    /// Set sleep and wakeup schedules to tell the period of scheduled sleep
+    pub fn schedules(mut self, schedules: Vec<(Calendar, PlayerCmd)>) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    fn is_quiet(&self) -> bool {
+        matches!(
+            last_scheduled(&self.schedules, &Local::now()),
+            Some(PlayerCmd::Sleep)
+        )
+    }
+}
+
+impl Commander<PlayerCmd> for PresenceCommander {
+    fn run(&mut self, sender: mpsc::Sender<PlayerCmd>, terminate: Arc<AtomicBool>) {
+        let motion = match self.lines.values() {
+            Ok(values) => values.first() == Some(&self.active_high),
+            Err(e) => {
+                error!("Failed reading motion sensor: {}", e);
+                false
+            }
+        };
+        let mut state = PresenceState::new(motion, self.is_quiet(), Instant::now());
+        while !terminate.load(Ordering::Relaxed) {
+            if let Some(cmd) = state.update(self.is_quiet(), Instant::now(), &self.config) {
+                info!("Sending {:?} by presence", cmd);
+                METRICS.commands.inc("presence");
+                if let Err(e) = sender.send(cmd) {
+                    debug!("Breaking out loop facing error: {:?}", e);
+                    return;
+                }
+            }
+
+            let timeout = state
+                .deadline(&self.config)
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+                .map_or(POLL_INTERVAL, |timeout| timeout.min(POLL_INTERVAL));
+            match self.lines.wait(timeout) {
+                Ok(events) => {
+                    for event in events {
+                        state.set_motion(event.level == self.active_high, event.at);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed reading motion sensor: {}", e);
+                    std::thread::sleep(ERROR_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PresenceConfig {
+        PresenceConfig {
+            idle_timeout: Duration::from_secs(60),
+            ..PresenceConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_idle_sleep_and_wakeup() {
+        let config = config();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut state = PresenceState::new(false, false, start);
+
+        assert!(state.update(false, at(59), &config).is_none());
+        assert_eq!(Some(at(60)), state.deadline(&config));
+        assert!(matches!(
+            state.update(false, at(60), &config),
+            Some(PlayerCmd::Sleep)
+        ));
+        assert!(state.update(false, at(61), &config).is_none());
+
+        state.set_motion(true, at(100));
+        assert!(matches!(
+            state.update(false, at(100), &config),
+            Some(PlayerCmd::Wakeup)
+        ));
+        // * Idle timeout counts from the end of motion
+        assert!(state.update(false, at(200), &config).is_none());
+        state.set_motion(false, at(210));
+        assert!(state.update(false, at(269), &config).is_none());
+        assert!(matches!(
+            state.update(false, at(270), &config),
+            Some(PlayerCmd::Sleep)
+        ));
+    }
+
+    #[test]
+    fn test_scheduled_sleep() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // * Motion doesn't wake up during scheduled sleep unless allowed
+        let config = config();
+        let mut state = PresenceState::new(true, true, start);
+        assert!(state.update(true, at(1), &config).is_none());
+        // * Idle timer restarts at scheduled wakeup
+        state.set_motion(false, at(2));
+        assert!(state.update(false, at(10), &config).is_none());
+        assert!(state.update(false, at(69), &config).is_none());
+        assert!(matches!(
+            state.update(false, at(70), &config),
+            Some(PlayerCmd::Sleep)
+        ));
+
+        let config = PresenceConfig {
+            wake_during_sleep: true,
+            ..config
+        };
+        let mut state = PresenceState::new(false, false, start);
+        assert!(state.update(true, at(1), &config).is_none());
+        state.set_motion(true, at(2));
+        assert!(matches!(
+            state.update(true, at(2), &config),
+            Some(PlayerCmd::Wakeup)
+        ));
+    }
+}
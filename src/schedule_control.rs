@@ -0,0 +1,283 @@
+use crate::calendar::Calendar;
+use crate::control::Commander;
+use crate::metrics::METRICS;
+use chrono::{DateTime, Local};
+use log::{debug, info, warn};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Maximum interval to check the terminate flag and the wall clock
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far to look back for the last event on catch-up
+const CATCH_UP_DAYS: i64 = 7;
+
+/// Return the command of the most recent event before now, if it's within days to catch up
+pub fn last_scheduled<C: Copy>(schedules: &[(Calendar, C)], now: &DateTime<Local>) -> Option<C> {
+    let since = *now - chrono::Duration::days(CATCH_UP_DAYS);
+    schedules
+        .iter()
+        .filter_map(|(cal, cmd)| cal.last_before(now).map(|t| (t, *cmd)))
+        .filter(|(t, _)| *t > since)
+        .max_by_key(|(t, _)| *t)
+        .map(|(_, cmd)| cmd)
+}
+
+/// Source of the current time, replaceable for testing
+pub trait Clock {
+    fn now(&self) -> DateTime<Local>;
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Commander sending commands at the time of calendar events.
+///
+/// With catch-up enabled, the command of the most recent event is sent on
+/// startup, so e.g. the slideshow starts sleeping if launched in the middle of
+/// the night between sleep and wakeup events. Likewise only the latest one is
+/// sent when the clock jumps forward past several events.
+pub struct ScheduleCommander<C, K: Clock = SystemClock> {
+    schedules: Vec<(Calendar, C)>,
+    catch_up: bool,
+    clock: K,
+}
+
+impl<C> ScheduleCommander<C> {
+    pub fn new(schedules: Vec<(Calendar, C)>) -> Self {
+        Self::with_clock(schedules, SystemClock)
+    }
+}
+
+impl<C, K: Clock> ScheduleCommander<C, K> {
+    pub fn with_clock(schedules: Vec<(Calendar, C)>, clock: K) -> Self {
+        ScheduleCommander {
+            schedules,
+            catch_up: false,
+            clock,
+        }
+    }
+
+    pub fn catch_up(mut self, catch_up: bool) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+}
+
+impl<C: Copy + Debug, K: Clock> ScheduleCommander<C, K> {
+    /// Return the command of the most recent event before now
+    fn last_command(&self, now: &DateTime<Local>) -> Option<C> {
+        last_scheduled(&self.schedules, now)
+    }
+
+    fn next_events(&self, now: &DateTime<Local>) -> Vec<Option<DateTime<Local>>> {
+        let next: Vec<_> = self
+            .schedules
+            .iter()
+            .map(|(cal, _)| cal.next_after(now))
+            .collect();
+        for ((cal, cmd), next) in self.schedules.iter().zip(&next) {
+            match next {
+                Some(t) => debug!("Next {:?} by '{}' at {}", cmd, cal, t),
+                None => warn!("Calendar '{}' for {:?} never happens", cal, cmd),
+            }
+        }
+        next
+    }
+}
+
+impl<C, K> Commander<C> for ScheduleCommander<C, K>
+where
+    C: Copy + Debug,
+    K: Clock,
+{
+    fn run(&mut self, sender: mpsc::Sender<C>, terminate: Arc<AtomicBool>) {
+        let mut last_check = self.clock.now();
+        if self.catch_up {
+            if let Some(cmd) = self.last_command(&last_check) {
+                info!("Catching up with the last scheduled command {:?}", cmd);
+                METRICS.commands.inc("schedule");
+                if let Err(e) = sender.send(cmd) {
+                    debug!("Breaking out loop facing error: {:?}", e);
+                    return;
+                }
+            }
+        }
+
+        let mut next = self.next_events(&last_check);
+        while !terminate.load(Ordering::Relaxed) {
+            let now = self.clock.now();
+            if now < last_check {
+                info!("Clock went backward, rescheduling");
+                next = self.next_events(&now);
+            }
+            last_check = now;
+
+            let passed: Vec<_> = (0..self.schedules.len())
+                .filter(|&i| matches!(next[i], Some(t) if t <= now))
+                .collect();
+            // Clock jumping forward, e.g. by NTP sync after boot, can pass several events
+            // at once, of which only the latest one is sent in the same way as catch-up
+            let latest = passed
+                .iter()
+                .filter_map(|&i| {
+                    let (cal, cmd) = &self.schedules[i];
+                    cal.last_before(&now).map(|t| (t, *cmd))
+                })
+                .max_by_key(|(t, _)| *t);
+            if let Some((_, cmd)) = latest {
+                info!("Sending scheduled command {:?}", cmd);
+                METRICS.commands.inc("schedule");
+                if let Err(e) = sender.send(cmd) {
+                    debug!("Breaking out loop facing error: {:?}", e);
+                    return;
+                }
+            }
+            for i in passed {
+                next[i] = self.schedules[i].0.next_after(&now);
+            }
+
+            let wait = next
+                .iter()
+                .filter_map(|t| *t)
+                .min()
+                .and_then(|t| (t - now).to_std().ok())
+                .map(|d| d.min(POLL_INTERVAL))
+                .unwrap_or(POLL_INTERVAL);
+            self.clock.sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::PlayerCmd;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    /// Clock advancing its time only by sleep, setting terminate flag at the given time
+    struct MockClock {
+        now: Mutex<DateTime<Local>>,
+        until: DateTime<Local>,
+        terminate: Arc<AtomicBool>,
+        /// Time to jump to on the first sleep
+        jump: Mutex<Option<DateTime<Local>>>,
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> DateTime<Local> {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            let mut now = self.now.lock().unwrap();
+            *now = match self.jump.lock().unwrap().take() {
+                Some(jump) => jump,
+                None => *now + chrono::Duration::from_std(duration).unwrap(),
+            };
+            if *now >= self.until {
+                self.terminate.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn run_schedule(
+        schedules: &[(&str, PlayerCmd)],
+        start: DateTime<Local>,
+        jump: Option<DateTime<Local>>,
+        until: DateTime<Local>,
+        catch_up: bool,
+    ) -> Vec<PlayerCmd> {
+        let terminate = Arc::new(AtomicBool::new(false));
+        let clock = MockClock {
+            now: Mutex::new(start),
+            until,
+            terminate: Arc::clone(&terminate),
+            jump: Mutex::new(jump),
+        };
+        let schedules = schedules
+            .iter()
+            .map(|(cal, cmd)| (cal.parse().unwrap(), *cmd))
+            .collect();
+        let mut commander = ScheduleCommander::with_clock(schedules, clock).catch_up(catch_up);
+        let (sender, receiver) = mpsc::channel();
+        commander.run(sender, terminate);
+        receiver.try_iter().collect()
+    }
+
+    fn names(cmds: Vec<PlayerCmd>) -> Vec<String> {
+        cmds.iter().map(|c| format!("{:?}", c)).collect()
+    }
+
+    #[test]
+    fn test_schedule() {
+        let schedules = [("00:00", PlayerCmd::Sleep), ("10:00", PlayerCmd::Wakeup)];
+        let start = Local.ymd(2020, 1, 1).and_hms(9, 0, 0);
+        let until = Local.ymd(2020, 1, 2).and_hms(12, 0, 0);
+        // * Commands should be sent at each event
+        // * Catch-up should send the command of the last event on startup
+        assert_eq!(
+            vec!["Sleep", "Wakeup", "Sleep", "Wakeup"],
+            names(run_schedule(&schedules, start, None, until, true))
+        );
+        assert_eq!(
+            vec!["Wakeup", "Sleep", "Wakeup"],
+            names(run_schedule(&schedules, start, None, until, false))
+        );
+    }
+
+    #[test]
+    fn test_schedule_clock_jump() {
+        let start = Local.ymd(2020, 1, 1).and_hms(9, 0, 0);
+        let until = Local.ymd(2020, 1, 3).and_hms(12, 0, 0);
+        let jump = Some(Local.ymd(2020, 1, 2).and_hms(1, 0, 0));
+        // * Only the latest of events passed by the jump should be sent, regardless of the order
+        for schedules in &[
+            [("10:00", PlayerCmd::Wakeup), ("00:00", PlayerCmd::Sleep)],
+            [("00:00", PlayerCmd::Sleep), ("10:00", PlayerCmd::Wakeup)],
+        ] {
+            assert_eq!(
+                vec!["Sleep", "Wakeup", "Sleep", "Wakeup"],
+                names(run_schedule(schedules, start, jump, until, false))
+            );
+        }
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let commander = ScheduleCommander::new(vec![
+            ("00:00".parse().unwrap(), PlayerCmd::Sleep),
+            ("10:00".parse().unwrap(), PlayerCmd::Wakeup),
+        ]);
+        let at = |h| Local.ymd(2020, 1, 1).and_hms(h, 0, 0);
+        assert!(matches!(
+            commander.last_command(&at(2)),
+            Some(PlayerCmd::Sleep)
+        ));
+        assert!(matches!(
+            commander.last_command(&at(12)),
+            Some(PlayerCmd::Wakeup)
+        ));
+
+        // * Events too old are not caught up
+        let commander =
+            ScheduleCommander::new(vec![("2019-01-01".parse().unwrap(), PlayerCmd::Sleep)]);
+        assert!(commander.last_command(&at(2)).is_none());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::control::{Commander, PlaylistCmd};
+use crate::metrics::METRICS;
+use log::{debug, info};
+use signal_hook;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Commander producing `PlaylistCmd::Reload` on receiving SIGHUP
+pub struct SignalCommander {
+    hangup: Arc<AtomicBool>,
+}
+
+impl SignalCommander {
+    pub fn new() -> io::Result<Self> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::SIGHUP, Arc::clone(&hangup))?;
+        Ok(SignalCommander { hangup })
+    }
+}
+
+impl Commander<PlaylistCmd> for SignalCommander {
+    fn run(&mut self, sender: mpsc::Sender<PlaylistCmd>, terminate: Arc<AtomicBool>) {
+        while !terminate.load(Ordering::Relaxed) {
+            if self.hangup.swap(false, Ordering::Relaxed) {
+                info!("Received SIGHUP, reloading");
+                METRICS.commands.inc("signal");
+                if let Err(e) = sender.send(PlaylistCmd::Reload) {
+                    debug!("Breaking out loop facing error: {:?}", e);
+                    break;
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc;
+    use std::thread;
+
+    #[test]
+    fn test_reload_on_sighup() {
+        let mut commander = SignalCommander::new().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let terminate = Arc::new(AtomicBool::new(false));
+        let term_copy = Arc::clone(&terminate);
+        let th = thread::spawn(move || commander.run(sender, term_copy));
+
+        unsafe {
+            libc::raise(libc::SIGHUP);
+        }
+        let cmd = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(cmd, PlaylistCmd::Reload));
+
+        terminate.store(true, Ordering::Relaxed);
+        th.join().unwrap();
+    }
+}
@@ -1,126 +1,981 @@
-use crate::album::{Album, AlbumItem};
-use crate::player::Player;
+use crate::album::{Album, AlbumItem, Error as _, MediaType};
+use crate::metrics::METRICS;
 use crate::player::SlideshowConfig;
+use crate::player::{ItemMeta, Player, PlayerStatus, PlaylistItem};
 use crate::playlist::PlaylistBuilder;
-use crate::storage::Storage;
+use crate::storage::{Storage, TMPFILE_PREFIX};
+use chrono::{DateTime, Local};
+use failure::format_err;
 pub use failure::Error;
 use log::{debug, error, info, warn};
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-const TMPFILE_NAME: &str = ".downloading.tmp";
+/// File in the storage to persist the current playlist over restarts
+const PLAYLIST_FILENAME: &str = ".playlist.json";
+const PLAYLIST_TMP_FILENAME: &str = ".playlist.json.tmp";
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Configuration of background preparation of playlist items
+#[derive(Debug, Clone)]
+pub struct PrefetchConfig {
+    /// Number of items prepared concurrently
+    pub workers: usize,
+    /// Number of ready items to start playing a new playlist with.
+    /// The rest of items are appended to the playlist as they become ready.
+    pub initial_items: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        PrefetchConfig {
+            workers: 4,
+            initial_items: 10,
+        }
+    }
+}
+
+/// Settings re-applied to a running slideshow by `Slideshow::reload()`
+pub struct ReloadConfig<I: AlbumItem> {
+    pub pl_builder: PlaylistBuilder<I>,
+    pub slideshow: SlideshowConfig,
+    pub prefetch: PrefetchConfig,
+    /// Names of changed settings which can't be applied without restart
+    pub restart_required: Vec<String>,
+}
+
+/// Function loading the latest settings to reload
+pub type Reloader<I> = Box<dyn Fn() -> Result<ReloadConfig<I>>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemStatus {
+    pub id: String,
+    /// Filename of the item in the storage
+    pub path: PathBuf,
+    pub media_type: MediaType,
+    /// Metadata to restore the playlist with
+    #[serde(skip)]
+    pub meta: ItemMeta,
+}
+
+/// Persisted form of `ItemStatus`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SavedItem {
+    id: String,
+    path: PathBuf,
+    media_type: MediaType,
+    /// Milliseconds since the UNIX epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_time: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+}
+
+impl From<&ItemStatus> for SavedItem {
+    fn from(item: &ItemStatus) -> Self {
+        SavedItem {
+            id: item.id.clone(),
+            path: item.path.clone(),
+            media_type: item.media_type,
+            created_time: item.meta.created_time.map(|t| {
+                t.duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0)
+            }),
+            description: item.meta.description.clone(),
+            filename: item.meta.filename.clone(),
+        }
+    }
+}
+
+impl From<SavedItem> for ItemStatus {
+    fn from(item: SavedItem) -> Self {
+        ItemStatus {
+            id: item.id,
+            path: item.path,
+            media_type: item.media_type,
+            meta: ItemMeta {
+                created_time: item
+                    .created_time
+                    .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+                description: item.description,
+                filename: item.filename,
+            },
+        }
+    }
+}
+
+/// Playlist persisted to resume playing it after restart
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SavedPlaylist {
+    items: Vec<SavedItem>,
+    /// Index of the item being played when saved
+    #[serde(default)]
+    position: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageStatus {
+    pub using: u64,
+    pub capacity: u64,
+}
+
+/// Snapshot of the current slideshow state
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    /// Item currently being played, None if it's unknown
+    pub current: Option<ItemStatus>,
+    pub playlist_size: usize,
+    pub player: PlayerStatus,
+    pub storage: StorageStatus,
+    /// Time of the last successful update/refresh in RFC3339
+    pub last_update: Option<String>,
+    pub last_refresh: Option<String>,
+    /// Settings changed by the last reload which take effect only after restart
+    pub restart_required: Vec<String>,
+}
+
+/// State kept updated by `Slideshow` to answer status requests from other threads
+#[derive(Debug, Default)]
+struct State {
+    playlist: Vec<ItemStatus>,
+    storage: StorageStatus,
+    last_update: Option<DateTime<Local>>,
+    last_refresh: Option<DateTime<Local>>,
+    restart_required: Vec<String>,
+    /// Directory of the storage containing files of playlist items
+    media_dir: PathBuf,
+}
+
+/// Source of `Status` which can be passed to other threads
+pub trait StatusSource: Send + Sync {
+    fn status(&self) -> Status;
+
+    /// Return items of the current playlist in order
+    fn playlist(&self) -> Vec<ItemStatus> {
+        Vec::new()
+    }
+
+    /// Return the path to the file of the item at the index of the current playlist,
+    /// or None if the item there isn't the one of the ID
+    fn media_file(&self, _index: usize, _id: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// `StatusSource` of a fixed status and a single item playlist, for testing its users
+#[cfg(test)]
+pub struct MockStatusSource;
+
+#[cfg(test)]
+impl StatusSource for MockStatusSource {
+    fn status(&self) -> Status {
+        Status {
+            current: None,
+            playlist_size: 3,
+            player: PlayerStatus {
+                paused: true,
+                muted: true,
+                ..PlayerStatus::default()
+            },
+            storage: StorageStatus {
+                using: 10,
+                capacity: 100,
+            },
+            last_update: None,
+            last_refresh: Some("2020-01-01T00:00:00+09:00".to_string()),
+            restart_required: vec![],
+        }
+    }
+
+    fn playlist(&self) -> Vec<ItemStatus> {
+        vec![ItemStatus {
+            id: "a".to_string(),
+            path: PathBuf::from("a.jpg"),
+            media_type: MediaType::PHOTO,
+            meta: ItemMeta {
+                created_time: Some(std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1)),
+                description: Some("beach".to_string()),
+                filename: None,
+            },
+        }]
+    }
+
+    /// Serve this source file as the item at index 0, and a missing file at index 1
+    fn media_file(&self, index: usize, id: &str) -> Option<PathBuf> {
+        match (index, id) {
+            (0, "a") => Some(PathBuf::from(file!())),
+            (1, "b") => Some(PathBuf::from("/nonexistent/b.jpg")),
+            _ => None,
+        }
+    }
+}
+
+/// Handle to obtain the status of a slideshow without owning it
+pub struct StatusHandle<P: Player> {
+    player: Arc<Mutex<P>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl<P: Player> StatusHandle<P> {
+    pub fn status(&self) -> Status {
+        let player = self.player.lock().expect("lock player").status();
+        let state = self.state.lock().expect("lock state");
+
+        let current = player.current.as_ref().and_then(|current| {
+            state
+                .playlist
+                .iter()
+                .find(|item| current.file_name() == Some(item.path.as_os_str()))
+                .cloned()
+        });
+        Status {
+            current,
+            playlist_size: state.playlist.len(),
+            player,
+            storage: state.storage.clone(),
+            last_update: state.last_update.map(|t| t.to_rfc3339()),
+            last_refresh: state.last_refresh.map(|t| t.to_rfc3339()),
+            restart_required: state.restart_required.clone(),
+        }
+    }
+}
+
+impl<P: Player> Clone for StatusHandle<P> {
+    fn clone(&self) -> Self {
+        StatusHandle {
+            player: Arc::clone(&self.player),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<P: Player + Send> StatusSource for StatusHandle<P> {
+    fn status(&self) -> Status {
+        StatusHandle::status(self)
+    }
+
+    fn playlist(&self) -> Vec<ItemStatus> {
+        self.state.lock().expect("lock state").playlist.clone()
+    }
+
+    fn media_file(&self, index: usize, id: &str) -> Option<PathBuf> {
+        let state = self.state.lock().expect("lock state");
+        state
+            .playlist
+            .get(index)
+            .filter(|item| item.id == id)
+            .map(|item| state.media_dir.join(&item.path))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PrefetchKind {
+    Refresh,
+    Update,
+}
+
+/// Result of preparing an item by a worker.
+/// Path to the temporary file containing the content, or None if it already exists in storage.
+type Prepared = Result<Option<PathBuf>>;
+
+/// New playlist whose items are being prepared by background workers
+struct PrefetchJob<I> {
+    kind: PrefetchKind,
+    items: Arc<Vec<I>>,
+    results: mpsc::Receiver<(usize, Prepared)>,
+    cancel: Arc<AtomicBool>,
+    /// Number of items not processed yet
+    pending: usize,
+    /// Filenames acquired in storage for this playlist
+    acquired: Vec<PathBuf>,
+    /// Indexes of items ready to play but not passed to the player yet
+    ready: Vec<usize>,
+    /// Whether this playlist has replaced the one on the player
+    pushed: bool,
+}
+
+impl<I> Drop for PrefetchJob<I> {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
 pub struct Slideshow<P: Player, A: Album> {
-    album: A,
+    album: Arc<A>,
     player: Arc<Mutex<P>>,
-    pl_builder: PlaylistBuilder,
+    pl_builder: PlaylistBuilder<A::Item>,
     storage: Storage,
-    config: Option<SlideshowConfig>,
-    playlist: Option<Vec<A::Item>>,
+    config: SlideshowConfig,
+    started: bool,
+    /// State of the player to restore once it's restarted successfully
+    restore: Option<PlayerStatus>,
+    prefetch: PrefetchConfig,
+    playlist: Option<Arc<Vec<A::Item>>>,
+    /// Filenames acquired in storage for the current playlist
+    acquired: Vec<PathBuf>,
+    job: Option<PrefetchJob<A::Item>>,
+    job_seq: u64,
+    state: Arc<Mutex<State>>,
+    reloader: Option<Reloader<A::Item>>,
+    /// Album items of the playlist restored from disk, being looked up in background
+    sync: Option<mpsc::Receiver<Result<Vec<A::Item>>>>,
 }
 
-impl<P: Player, A: Album> Slideshow<P, A> {
+impl<P: Player, A: Album + 'static> Slideshow<P, A> {
     pub fn new(
         album: A,
         player: P,
-        pl_builder: PlaylistBuilder,
+        pl_builder: PlaylistBuilder<A::Item>,
         storage: Storage,
         slideshow_config: SlideshowConfig,
     ) -> Self {
+        let state = State {
+            media_dir: storage.dir().to_path_buf(),
+            ..State::default()
+        };
         Slideshow {
-            album,
+            album: Arc::new(album),
             player: Arc::new(Mutex::new(player)),
             pl_builder,
             storage,
-            config: Some(slideshow_config),
+            config: slideshow_config,
+            started: false,
+            restore: None,
+            prefetch: PrefetchConfig::default(),
             playlist: None,
+            acquired: Vec::new(),
+            job: None,
+            job_seq: 0,
+            state: Arc::new(Mutex::new(state)),
+            reloader: None,
+            sync: None,
         }
     }
 
+    pub fn prefetch_config(mut self, config: PrefetchConfig) -> Self {
+        self.prefetch = config;
+        self
+    }
+
+    /// Set the function providing settings to apply on `reload()`
+    pub fn reloader<F>(mut self, reloader: F) -> Self
+    where
+        F: Fn() -> Result<ReloadConfig<A::Item>> + 'static,
+    {
+        self.reloader = Some(Box::new(reloader));
+        self
+    }
+
+    /// Load settings through the reloader and apply them to the running slideshow.
+    ///
+    /// New playlist parameters take effect from the next update or refresh.
+    /// Names of changed settings which require restart are logged and exposed
+    /// through the status.
+    pub fn reload(&mut self) -> Result<()> {
+        let reloader = match &self.reloader {
+            Some(reloader) => reloader,
+            None => {
+                info!("No reloader configured, ignoring reload");
+                return Ok(());
+            }
+        };
+        info!("Reloading configuration");
+        let config = reloader()?;
+        self.pl_builder = config.pl_builder;
+        self.prefetch = config.prefetch;
+
+        let mut restart_required = config.restart_required;
+        // Player which hasn't started yet will use the new config as is
+        if self.started {
+            let fields = self
+                .player
+                .lock()
+                .expect("lock player")
+                .reconfigure(&config.slideshow)?;
+            restart_required.extend(fields.iter().map(|f| format!("slideshow.{}", f)));
+        }
+        self.config = config.slideshow;
+
+        if restart_required.is_empty() {
+            info!("Configuration reloaded");
+        } else {
+            warn!(
+                "Configuration reloaded, but changes in {:?} take effect after restart",
+                restart_required
+            );
+        }
+        self.state.lock().expect("lock state").restart_required = restart_required;
+        Ok(())
+    }
+
+    /// Start the player with the playlist saved by the last run if any, otherwise
+    /// with a new playlist built by refresh.
     pub fn start(&mut self) -> Result<()> {
-        if let Some(config) = self.config.take() {
-            self.player.lock().expect("lock player").start(config)?;
-            self.refresh_playlist()?;
+        if !self.started {
+            self.player
+                .lock()
+                .expect("lock player")
+                .start(self.config.clone())?;
+            self.started = true;
+            if !self.restore_playlist()? {
+                self.refresh_playlist()?;
+            }
         }
         Ok(())
     }
 
-    fn prepare_items(&mut self, playlist: &[A::Item]) -> Result<Vec<PathBuf>> {
+    /// Write the current playlist and the position in it to the storage,
+    /// so that the next run can resume playing it.
+    pub fn save_playlist(&self) -> Result<()> {
+        let current = self.player.lock().expect("lock player").status().current;
+        let state = self.state.lock().expect("lock state");
+        let position = current
+            .and_then(|current| {
+                state
+                    .playlist
+                    .iter()
+                    .position(|item| current.file_name() == Some(item.path.as_os_str()))
+            })
+            .unwrap_or(0);
+        let saved = SavedPlaylist {
+            items: state.playlist.iter().map(SavedItem::from).collect(),
+            position,
+        };
+        drop(state);
+
+        let dir = self.storage.dir();
+        let tmp_path = dir.join(PLAYLIST_TMP_FILENAME);
+        fs::write(&tmp_path, serde_json::to_vec(&saved)?)?;
+        fs::rename(&tmp_path, dir.join(PLAYLIST_FILENAME))?;
+        debug!(
+            "Saved playlist with {} items at position {}",
+            saved.items.len(),
+            position
+        );
+        Ok(())
+    }
+
+    fn load_playlist(&self) -> Option<SavedPlaylist> {
+        let path = self.storage.dir().join(PLAYLIST_FILENAME);
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to read saved playlist {}: {}", path.display(), e);
+                }
+                return None;
+            }
+        };
+        match serde_json::from_slice(&content) {
+            Ok(saved) => Some(saved),
+            Err(e) => {
+                warn!("Ignoring broken saved playlist {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Pass the playlist saved by the last run to the player, starting from the item
+    /// being played when saved. Items whose files have gone from storage are skipped.
+    ///
+    /// Album items of the playlist are looked up in background, as listing the album
+    /// may take long. Returns false if there's no playlist to restore.
+    fn restore_playlist(&mut self) -> Result<bool> {
+        let saved = match self.load_playlist() {
+            Some(saved) => saved,
+            None => return Ok(false),
+        };
+        let mut items = saved.items;
+        if saved.position < items.len() {
+            items.rotate_left(saved.position);
+        }
+
+        // Files already in storage are acquired without evicting anything
+        let reserved = HashSet::new();
+        let mut statuses = Vec::new();
+        let mut acquired = Vec::new();
+        for item in items {
+            let path = self.storage.filepath(&item.path)?;
+            let size = match fs::metadata(&path) {
+                Ok(meta) => meta.len(),
+                Err(_) => {
+                    debug!("Media of saved item has gone: {}", item.path.display());
+                    continue;
+                }
+            };
+            if !self
+                .storage
+                .acquire_item(&item.id, &item.path, size, &reserved)?
+            {
+                continue;
+            }
+            acquired.push(item.path.clone());
+            statuses.push(ItemStatus::from(item));
+        }
+        if statuses.is_empty() {
+            info!("No items of the saved playlist remain in storage");
+            return Ok(false);
+        }
+
+        let pl_items = statuses
+            .iter()
+            .map(|item| {
+                Ok(PlaylistItem {
+                    path: self.storage.filepath(&item.path)?,
+                    meta: item.meta.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        info!(
+            "Restoring saved playlist with {} items from position {}",
+            pl_items.len(),
+            saved.position
+        );
+        self.player
+            .lock()
+            .expect("lock player")
+            .update_playlist(pl_items)?;
+        let ids: Vec<_> = statuses.iter().map(|item| item.id.clone()).collect();
+        self.state.lock().expect("lock state").playlist = statuses;
+        self.acquired = acquired;
+        self.storage.flush()?;
+        self.update_storage_status();
+
+        let (sender, receiver) = mpsc::channel();
+        let album = Arc::clone(&self.album);
+        thread::spawn(move || {
+            let _ = sender.send(Self::sync_items(&*album, &ids));
+        });
+        self.sync = Some(receiver);
+        Ok(true)
+    }
+
+    /// Look up album items of the IDs, returning them in the order of IDs.
+    /// Items no longer in the album are left out.
+    fn sync_items(album: &A, ids: &[String]) -> Result<Vec<A::Item>> {
+        let wanted: HashSet<_> = ids.iter().map(String::as_str).collect();
+        let mut found = HashMap::new();
+        for item in album.items() {
+            let item = match item {
+                Ok(item) => item,
+                Err(e) if e.is_fatal() => return Err(e.into()),
+                Err(e) => {
+                    warn!("Skipping item on syncing by error: {}", e);
+                    continue;
+                }
+            };
+            if wanted.contains(item.id()) {
+                found.insert(item.id().to_string(), item);
+            }
+        }
+        Ok(ids.iter().filter_map(|id| found.remove(id)).collect())
+    }
+
+    /// Take album items of the restored playlist once they are looked up,
+    /// so that following updates can be applied to the playlist
+    fn poll_sync(&mut self) -> Result<()> {
+        let result = match &self.sync {
+            Some(receiver) => match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    Err(format_err!("Album sync terminated unexpectedly"))
+                }
+            },
+            None => return Ok(()),
+        };
+        self.sync = None;
+        let items = result?;
+        let restored = self.state.lock().expect("lock state").playlist.len();
+        info!(
+            "Synced restored playlist with album, {} of {} items remain",
+            items.len(),
+            restored
+        );
+        self.playlist = Some(Arc::new(items));
+        Ok(())
+    }
+
+    /// Restart the player, then restore its playlist and state such as pausing.
+    ///
+    /// The state is kept across failed attempts, so calling this again restores
+    /// the state before the first attempt.
+    pub fn restart_player(&mut self) -> Result<()> {
+        let mut player = self.player.lock().expect("lock player");
+        let restore = self.restore.get_or_insert_with(|| player.status()).clone();
+        info!(
+            "Restarting player (paused={}, sleeping={}, muted={})",
+            restore.paused, restore.sleeping, restore.muted
+        );
+        player.stop();
+        player.start(self.config.clone())?;
+
+        let items = self
+            .state
+            .lock()
+            .expect("lock state")
+            .playlist
+            .iter()
+            .map(|item| {
+                Ok(PlaylistItem {
+                    path: self.storage.filepath(&item.path)?,
+                    meta: item.meta.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !items.is_empty() {
+            info!("Restoring playlist with {} items", items.len());
+            player.update_playlist(items)?;
+        }
+        if restore.paused {
+            player.pause()?;
+        }
+        if restore.sleeping {
+            player.sleep()?;
+        }
+        if restore.muted {
+            player.mute()?;
+        }
+        self.restore = None;
+        info!("Player restarted");
+        Ok(())
+    }
+
+    /// Start preparing items of the new playlist in background.
+    ///
+    /// Playlist on the player is replaced once enough items become ready
+    /// through subsequent `poll_prefetch()` calls.
+    fn replace_playlist(&mut self, playlist: Vec<A::Item>, kind: PrefetchKind) -> Result<()> {
+        if let Some(job) = self.job.take() {
+            info!("Cancelling preparation of the previous playlist");
+            self.abort_prefetch(job);
+        }
         info!("Preparing {} items locally", playlist.len());
 
-        let reserved_paths: HashSet<_> = playlist.iter().map(|item| item.path()).collect();
-        let tmpfile = self.storage.filepath(TMPFILE_NAME).expect("filepath");
-        let mut paths = Vec::with_capacity(playlist.len());
-        for item in playlist {
-            let path = self.storage.filepath(item.path())?;
-            // Error handling rule:
-            // * album.prepare_item => return error because it could make all items in list to fail prepare
-            // * fs::* : io::Error => return error because they are not supposed to happen in normal situation
-            // * storage.acquire : io::Error => same as the above
-            // * storage.acquire failure => skip because other smaller size media might succeeds to acquire
-            let size = if path.exists() {
+        self.job_seq += 1;
+        let tmpfile_prefix = self
+            .storage
+            .filepath(format!("{}-{}", TMPFILE_PREFIX, self.job_seq))?;
+        let items = Arc::new(playlist);
+        let (sender, results) = mpsc::channel();
+        let mut queue = VecDeque::new();
+        for (i, item) in items.iter().enumerate() {
+            if self.storage.filepath(item.path())?.exists() {
                 debug!(
                     "Media already exists, skipping download: {}",
                     item.path().display()
                 );
-                fs::metadata(&path)?.len()
+                sender.send((i, Ok(None))).expect("send to own receiver");
             } else {
-                info!("Downloading {}", item.path().display());
-                self.album.prepare_item(&item, &tmpfile)?;
-                fs::metadata(&tmpfile)?.len()
+                queue.push_back(i);
+            }
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let workers = self.prefetch.workers.max(1).min(queue.len());
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..workers {
+            let album = Arc::clone(&self.album);
+            let items = Arc::clone(&items);
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            let cancel = Arc::clone(&cancel);
+            let tmpfile_prefix = tmpfile_prefix.clone();
+            thread::spawn(move || {
+                Self::prefetch_worker(&*album, &items, &queue, &sender, &cancel, &tmpfile_prefix)
+            });
+        }
+
+        self.job = Some(PrefetchJob {
+            kind,
+            pending: items.len(),
+            items,
+            results,
+            cancel,
+            acquired: Vec::new(),
+            ready: Vec::new(),
+            pushed: false,
+        });
+        self.poll_prefetch()
+    }
+
+    fn prefetch_worker(
+        album: &A,
+        items: &[A::Item],
+        queue: &Mutex<VecDeque<usize>>,
+        sender: &mpsc::Sender<(usize, Prepared)>,
+        cancel: &AtomicBool,
+        tmpfile_prefix: &Path,
+    ) {
+        while !cancel.load(Ordering::Relaxed) {
+            let i = match queue.lock().expect("lock queue").pop_front() {
+                Some(i) => i,
+                None => break,
             };
+            let item = &items[i];
+            let mut tmpfile = tmpfile_prefix.as_os_str().to_owned();
+            tmpfile.push(format!("-{}.tmp", i));
+            let tmpfile = PathBuf::from(tmpfile);
 
-            if !self.storage.acquire(item.path(), size, &reserved_paths)? {
+            info!("Downloading {}", item.path().display());
+            let start = Instant::now();
+            let prepared = album
+                .prepare_item(item, &tmpfile)
+                .map(|_| Some(tmpfile.clone()))
+                .map_err(Error::from);
+            match &prepared {
+                Ok(_) => {
+                    METRICS.downloads.inc();
+                    METRICS.download_duration.observe(start.elapsed());
+                    if let Ok(meta) = fs::metadata(&tmpfile) {
+                        METRICS.download_bytes.add(meta.len());
+                    }
+                }
+                Err(_) => METRICS.download_failures.inc(),
+            }
+            if prepared.is_err() {
+                // Partially written content is useless
+                let _ = fs::remove_file(&tmpfile);
+            }
+            if sender.send((i, prepared)).is_err() {
+                // The playlist has been abandoned
+                let _ = fs::remove_file(&tmpfile);
+            }
+        }
+    }
+
+    /// Process items prepared by background workers and pass them to the player.
+    /// Album items of the restored playlist are taken as well once looked up.
+    ///
+    /// This needs to be called periodically while `is_prefetching()` returns true.
+    pub fn poll_prefetch(&mut self) -> Result<()> {
+        if let Err(e) = self.poll_sync() {
+            warn!("Failed to sync restored playlist with album: {}", e);
+        }
+        let mut job = match self.job.take() {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        match self.process_prefetch(&mut job) {
+            Ok(true) => self.job = Some(job),
+            Ok(false) => self.finish_prefetch(job)?,
+            Err(e) => {
+                self.abort_prefetch(job);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return whether items of a new playlist are still being prepared
+    pub fn is_prefetching(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Process prepared items and update player's playlist.
+    /// Returns true if the job still has items to process.
+    fn process_prefetch(&mut self, job: &mut PrefetchJob<A::Item>) -> Result<bool> {
+        let items = Arc::clone(&job.items);
+        let reserved_paths: HashSet<_> = items.iter().map(|item| item.path()).collect();
+        while job.pending > 0 {
+            let (i, prepared) = match job.results.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    return Err(format_err!("Prefetch workers terminated unexpectedly"));
+                }
+            };
+            job.pending -= 1;
+
+            // Error handling rule:
+            // * album.prepare_item => return error because it could make all items in list to fail prepare
+            // * fs::* : io::Error => return error because they are not supposed to happen in normal situation
+            // * storage.acquire : io::Error => same as the above
+            // * storage.acquire failure => skip because other smaller size media might succeeds to acquire
+            let item = &items[i];
+            let tmpfile = prepared?;
+            let path = self.storage.filepath(item.path())?;
+            let size = fs::metadata(tmpfile.as_ref().unwrap_or(&path))?.len();
+            if !self
+                .storage
+                .acquire_item(item.id(), item.path(), size, &reserved_paths)?
+            {
                 warn!(
                     "Failed to acquire storage for media: {}",
                     item.path().display()
                 );
+                if let Some(tmpfile) = tmpfile {
+                    fs::remove_file(tmpfile)?;
+                }
                 continue;
             }
-
-            if !path.exists() {
+            if let Some(tmpfile) = tmpfile {
                 fs::rename(&tmpfile, &path)?;
             }
-            paths.push(path);
+            job.acquired.push(item.path().to_path_buf());
+            job.ready.push(i);
         }
 
-        Ok(paths)
-    }
+        if job.ready.is_empty() {
+            if !job.pushed && job.pending == 0 {
+                info!("Not updating playlist because it has no items");
+            }
+            return Ok(job.pending > 0);
+        }
+        if !job.pushed && job.ready.len() < self.prefetch.initial_items && job.pending > 0 {
+            return Ok(true);
+        }
 
-    fn replace_playlist(&mut self, playlist: Vec<A::Item>) -> Result<()> {
-        let paths = self.prepare_items(&playlist)?;
+        let pl_items = job
+            .ready
+            .iter()
+            .map(|&i| {
+                Ok(PlaylistItem {
+                    path: self.storage.filepath(items[i].path())?,
+                    meta: Self::item_meta(&items[i]),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let statuses = job.ready.iter().map(|&i| Self::item_status(&items[i]));
+        let mut player = self.player.lock().expect("lock player");
+        if job.pushed {
+            info!("Appending {} items to playlist", pl_items.len());
+            player.append_playlist(pl_items)?;
+            self.state
+                .lock()
+                .expect("lock state")
+                .playlist
+                .extend(statuses);
+        } else {
+            if player.locked() {
+                info!("Player is locked, not replacing playlist");
+                return Ok(false);
+            }
+            info!(
+                "Updating playlist on player with {} items...",
+                pl_items.len()
+            );
+            player.update_playlist(pl_items)?;
+            job.pushed = true;
+            self.state.lock().expect("lock state").playlist = statuses.collect();
+            // Album items of the restored playlist aren't needed anymore
+            self.sync = None;
 
-        if paths.is_empty() {
-            info!("Not updating playlist because it has no items");
-            return Ok(());
+            for path in self.acquired.drain(..) {
+                if let Err(e) = self.storage.release(&path) {
+                    error!("Failed to release {} from storage: {:?}", path.display(), e);
+                }
+            }
+            self.playlist = Some(Arc::clone(&items));
+            drop(player);
+            self.save_playlist_logged();
         }
+        job.ready.clear();
+        Ok(job.pending > 0)
+    }
 
-        let mut player = self.player.lock().unwrap();
-        if player.locked() {
-            info!("Player is locked, not replacing playlist");
-            return Ok(());
+    fn finish_prefetch(&mut self, mut job: PrefetchJob<A::Item>) -> Result<()> {
+        if job.pushed {
+            self.save_playlist_logged();
+            self.acquired.append(&mut job.acquired);
+            let mut state = self.state.lock().expect("lock state");
+            match job.kind {
+                PrefetchKind::Refresh => state.last_refresh = Some(Local::now()),
+                PrefetchKind::Update => state.last_update = Some(Local::now()),
+            }
+            info!("Finish updating playlist");
+        } else {
+            // Player was locked, items in the middle of preparation are abandoned
+            Self::discard_results(&mut job);
+            self.release_all(&mut job.acquired);
         }
+        self.storage.flush()?;
+        self.update_storage_status();
+        Ok(())
+    }
 
-        info!("Updating playlist on player...");
-        player.update_playlist(paths)?;
+    /// Stop preparing items and cleanup files and storage acquired for them
+    fn abort_prefetch(&mut self, mut job: PrefetchJob<A::Item>) {
+        Self::discard_results(&mut job);
+        if job.pushed {
+            // Items are already in the playlist on the player
+            self.save_playlist_logged();
+            self.acquired.append(&mut job.acquired);
+        } else {
+            self.release_all(&mut job.acquired);
+        }
+        if let Err(e) = self.storage.flush() {
+            warn!("Failed to flush storage: {}", e);
+        }
+        self.update_storage_status();
+    }
 
-        if let Some(old_playlist) = self.playlist.replace(playlist) {
-            for item in old_playlist {
-                if let Err(e) = self.storage.release(item.path()) {
-                    error!(
-                        "Failed to release {} from storage: {:?}",
-                        item.path().display(),
-                        e
-                    );
+    /// Stop workers of the job and remove temporary files of items they prepared.
+    ///
+    /// Results are drained in background until all workers stop, as they may be
+    /// in the middle of preparing items and send them later.
+    fn discard_results(job: &mut PrefetchJob<A::Item>) {
+        job.cancel.store(true, Ordering::Relaxed);
+        let (_, closed) = mpsc::channel();
+        let results = std::mem::replace(&mut job.results, closed);
+        thread::spawn(move || {
+            for (_, prepared) in results {
+                if let Ok(Some(tmpfile)) = prepared {
+                    if let Err(e) = fs::remove_file(&tmpfile) {
+                        warn!("Failed to remove {}: {}", tmpfile.display(), e);
+                    }
                 }
             }
+        });
+    }
+
+    fn save_playlist_logged(&self) {
+        if let Err(e) = self.save_playlist() {
+            warn!("Failed to save playlist: {}", e);
+        }
+    }
+
+    fn release_all(&mut self, paths: &mut Vec<PathBuf>) {
+        for path in paths.drain(..) {
+            if let Err(e) = self.storage.release(&path) {
+                error!("Failed to release {} from storage: {:?}", path.display(), e);
+            }
         }
-        info!("Finish updating playlist");
-        Ok(())
+    }
+
+    fn item_status(item: &A::Item) -> ItemStatus {
+        ItemStatus {
+            id: item.id().to_string(),
+            path: item.path().to_path_buf(),
+            media_type: item.media_type(),
+            meta: Self::item_meta(item),
+        }
+    }
+
+    fn item_meta(item: &A::Item) -> ItemMeta {
+        ItemMeta {
+            created_time: Some(item.created_time()),
+            description: item.description().map(String::from),
+            filename: item.filename().map(String::from),
+        }
+    }
+
+    fn update_storage_status(&self) {
+        self.state.lock().expect("lock state").storage = StorageStatus {
+            using: self.storage.using(),
+            capacity: self.storage.capacity(),
+        };
     }
 
     pub fn refresh_playlist(&mut self) -> Result<()> {
@@ -129,8 +984,8 @@ impl<P: Player, A: Album> Slideshow<P, A> {
             info!("Player is locked, not refreshing playlist");
             return Ok(());
         }
-        let playlist = self.pl_builder.build(&self.album)?;
-        self.replace_playlist(playlist)?;
+        let playlist = self.pl_builder.build(&*self.album)?;
+        self.replace_playlist(playlist, PrefetchKind::Refresh)?;
         Ok(())
     }
 
@@ -143,12 +998,17 @@ impl<P: Player, A: Album> Slideshow<P, A> {
             info!("Player is locked, not updating playlist");
             return Ok(());
         }
+        if self.sync.is_some() {
+            info!("Restored playlist is being synced with album, not updating playlist");
+            return Ok(());
+        }
         if let Some(cur_pl) = &self.playlist {
-            if let Some(new_pl) = self.pl_builder.updated(&self.album, cur_pl)? {
+            if let Some(new_pl) = self.pl_builder.updated(&*self.album, cur_pl)? {
                 info!("Playlist updated, new list contains {} items", new_pl.len());
-                self.replace_playlist(new_pl)?;
+                self.replace_playlist(new_pl, PrefetchKind::Update)?;
             } else {
                 info!("No new updates for playlist");
+                self.state.lock().expect("lock state").last_update = Some(Local::now());
             }
         } else {
             info!("Current playlist has no items, delegate from update to refresh");
@@ -161,7 +1021,521 @@ impl<P: Player, A: Album> Slideshow<P, A> {
         Arc::clone(&self.player)
     }
 
+    /// Return a handle which can be used to obtain the status from other threads
+    pub fn status_handle(&self) -> StatusHandle<P> {
+        StatusHandle {
+            player: Arc::clone(&self.player),
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status_handle().status()
+    }
+
     pub fn is_player_ok(&self) -> bool {
-        self.player.lock().is_ok()
+        self.player.lock().map(|p| p.is_ok()).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album;
+    use crate::player;
+    use failure::Fail;
+    use std::time::{Duration, Instant, SystemTime};
+    use tempfile;
+
+    struct MockAlbum {
+        /// Pairs of item name and its size. Negative size makes preparation fail,
+        /// and zero size makes listing fail.
+        items: Vec<(&'static str, i64)>,
+    }
+
+    impl Album for MockAlbum {
+        type E = MockError;
+        type Item = MockAlbumItem;
+        type Items = Box<dyn Iterator<Item = std::result::Result<MockAlbumItem, MockError>>>;
+
+        fn items(&self) -> Self::Items {
+            Box::new(
+                self.items
+                    .clone()
+                    .into_iter()
+                    .map(|(name, size)| match size {
+                        0 => Err(MockError),
+                        _ => Ok(MockAlbumItem(name, PathBuf::from(name))),
+                    }),
+            )
+        }
+
+        fn prepare_item<T: AsRef<Path>>(
+            &self,
+            item: &Self::Item,
+            path: T,
+        ) -> std::result::Result<(), Self::E> {
+            let size = self
+                .items
+                .iter()
+                .find(|(name, _)| *name == item.0)
+                .unwrap()
+                .1;
+            if size < 0 {
+                return Err(MockError);
+            }
+            fs::write(path, vec![0; size as usize]).unwrap();
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct MockAlbumItem(&'static str, PathBuf);
+
+    impl AlbumItem for MockAlbumItem {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn path(&self) -> &Path {
+            &self.1
+        }
+
+        fn media_type(&self) -> MediaType {
+            MediaType::PHOTO
+        }
+
+        fn created_time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+    }
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "error")]
+    struct MockError;
+
+    impl album::Error for MockError {
+        fn is_fatal(&self) -> bool {
+            false
+        }
+    }
+
+    /// Player recording playlist operations
+    #[derive(Default)]
+    struct MockPlayer {
+        updates: Vec<Vec<PathBuf>>,
+        appends: Vec<Vec<PathBuf>>,
+        config: Option<SlideshowConfig>,
+        status: PlayerStatus,
+        starts: usize,
+        fail_start: bool,
+        locked: bool,
+    }
+
+    impl Player for MockPlayer {
+        fn start(&mut self, config: SlideshowConfig) -> player::Result<()> {
+            if self.fail_start {
+                return Err(format_err!("failed to start"));
+            }
+            self.config = Some(config);
+            self.starts += 1;
+            Ok(())
+        }
+        fn stop(&mut self) {
+            self.config = None;
+            self.status = PlayerStatus::default();
+        }
+        fn play_next(&mut self) -> player::Result<()> {
+            Ok(())
+        }
+        fn play_back(&mut self) -> player::Result<()> {
+            Ok(())
+        }
+        fn sleep(&mut self) -> player::Result<()> {
+            self.status.sleeping = true;
+            Ok(())
+        }
+        fn wakeup(&mut self) -> player::Result<()> {
+            self.status.sleeping = false;
+            Ok(())
+        }
+        fn pause(&mut self) -> player::Result<()> {
+            self.status.paused = true;
+            Ok(())
+        }
+        fn resume(&mut self) -> player::Result<()> {
+            self.status.paused = false;
+            Ok(())
+        }
+        fn mute(&mut self) -> player::Result<()> {
+            self.status.muted = true;
+            Ok(())
+        }
+        fn unmute(&mut self) -> player::Result<()> {
+            self.status.muted = false;
+            Ok(())
+        }
+        fn update_playlist(&mut self, playlist: Vec<PlaylistItem>) -> player::Result<()> {
+            self.updates
+                .push(playlist.into_iter().map(|item| item.path).collect());
+            Ok(())
+        }
+        fn append_playlist(&mut self, items: Vec<PlaylistItem>) -> player::Result<()> {
+            self.appends
+                .push(items.into_iter().map(|item| item.path).collect());
+            Ok(())
+        }
+        fn locked(&self) -> bool {
+            self.locked
+        }
+        fn is_ok(&self) -> bool {
+            true
+        }
+        fn status(&self) -> PlayerStatus {
+            self.status.clone()
+        }
+        fn reconfigure(&mut self, config: &SlideshowConfig) -> player::Result<Vec<&'static str>> {
+            let mut restart = Vec::new();
+            if self.config.as_ref().map(|c| c.fullscreen) != Some(config.fullscreen) {
+                restart.push("fullscreen");
+            }
+            self.config = Some(config.clone());
+            Ok(restart)
+        }
+    }
+
+    fn new_slideshow(
+        items: Vec<(&'static str, i64)>,
+        capacity: u64,
+        prefetch: PrefetchConfig,
+    ) -> (Slideshow<MockPlayer, MockAlbum>, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let slideshow = Slideshow::new(
+            MockAlbum { items },
+            MockPlayer::default(),
+            PlaylistBuilder::new(),
+            Storage::open(dir.path(), capacity).unwrap(),
+            SlideshowConfig::default(),
+        )
+        .prefetch_config(prefetch);
+        (slideshow, dir)
+    }
+
+    fn wait_prefetch(slideshow: &mut Slideshow<MockPlayer, MockAlbum>) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while slideshow.is_prefetching() {
+            assert!(Instant::now() < deadline, "prefetch didn't complete");
+            slideshow.poll_prefetch()?;
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    fn tmpfiles(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.to_str().unwrap().contains(TMPFILE_PREFIX))
+            .collect()
+    }
+
+    fn playlist_items(album: &MockAlbum) -> Vec<MockAlbumItem> {
+        album.items().map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let items = vec![("a", 1), ("b", 2), ("c", 3), ("d", 4), ("e", 5)];
+        let (mut slideshow, dir) = new_slideshow(
+            items,
+            100,
+            PrefetchConfig {
+                workers: 2,
+                initial_items: 2,
+            },
+        );
+        // Items already in storage should be used without download
+        fs::write(dir.path().join("c"), vec![0; 3]).unwrap();
+
+        let playlist = playlist_items(&slideshow.album);
+        slideshow
+            .replace_playlist(playlist, PrefetchKind::Refresh)
+            .unwrap();
+        wait_prefetch(&mut slideshow).unwrap();
+
+        let player = slideshow.player.lock().unwrap();
+        // * Player should start with enough ready items, then rest are appended
+        assert_eq!(1, player.updates.len());
+        assert!(player.updates[0].len() >= 2);
+        let mut played: Vec<_> = player
+            .updates
+            .iter()
+            .chain(player.appends.iter())
+            .flatten()
+            .cloned()
+            .collect();
+        played.sort();
+        let expected: Vec<_> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        assert_eq!(expected, played);
+        assert_eq!(15, slideshow.storage.using());
+        assert!(tmpfiles(dir.path()).is_empty());
+        drop(player);
+
+        let status = slideshow.status();
+        assert_eq!(5, status.playlist_size);
+        assert!(status.last_refresh.is_some());
+
+        // * Files of playlist items can be looked up from other threads
+        let handle = slideshow.status_handle();
+        let playlist = handle.playlist();
+        assert_eq!(5, playlist.len());
+        assert_eq!(
+            Some(dir.path().join(&playlist[4].path)),
+            handle.media_file(4, &playlist[4].id)
+        );
+        assert!(handle.media_file(4, &playlist[3].id).is_none());
+        assert!(handle.media_file(5, &playlist[4].id).is_none());
+    }
+
+    #[test]
+    fn test_prefetch_storage_shortage() {
+        let items = vec![("a", 6), ("b", 6), ("c", 3)];
+        let (mut slideshow, dir) = new_slideshow(
+            items,
+            10,
+            PrefetchConfig {
+                workers: 1,
+                initial_items: 10,
+            },
+        );
+
+        let playlist = playlist_items(&slideshow.album);
+        slideshow
+            .replace_playlist(playlist, PrefetchKind::Refresh)
+            .unwrap();
+        wait_prefetch(&mut slideshow).unwrap();
+
+        // * Items failed to acquire storage should be skipped
+        // * Playlist should be pushed once all items are processed even though it's smaller than initial items
+        let player = slideshow.player.lock().unwrap();
+        assert_eq!(
+            vec![vec![dir.path().join("a"), dir.path().join("c")]],
+            player.updates
+        );
+        assert!(player.appends.is_empty());
+        assert!(!dir.path().join("b").exists());
+        assert!(tmpfiles(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_prefetch_error() {
+        let items = vec![("a", 1), ("b", -1), ("c", 1)];
+        let (mut slideshow, dir) = new_slideshow(
+            items,
+            10,
+            PrefetchConfig {
+                workers: 1,
+                initial_items: 10,
+            },
+        );
+
+        let playlist = playlist_items(&slideshow.album);
+        // * Failure in preparing an item should abort the whole playlist with the album's error
+        let err = slideshow
+            .replace_playlist(playlist, PrefetchKind::Refresh)
+            .and_then(|_| wait_prefetch(&mut slideshow))
+            .unwrap_err();
+        assert!(err.downcast_ref::<MockError>().is_some(), "{:?}", err);
+        assert!(!slideshow.is_prefetching());
+        assert!(slideshow.player.lock().unwrap().updates.is_empty());
+        assert!(slideshow.status().last_refresh.is_none());
+        thread::sleep(Duration::from_millis(100));
+        assert!(tmpfiles(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_prefetch_locked_player() {
+        let items = vec![("a", 1), ("b", 1), ("c", 1)];
+        let (mut slideshow, dir) = new_slideshow(
+            items,
+            10,
+            PrefetchConfig {
+                workers: 3,
+                initial_items: 1,
+            },
+        );
+        slideshow.player.lock().unwrap().locked = true;
+
+        let playlist = playlist_items(&slideshow.album);
+        slideshow
+            .replace_playlist(playlist, PrefetchKind::Refresh)
+            .unwrap();
+        wait_prefetch(&mut slideshow).unwrap();
+
+        // * Playlist shouldn't replace the one of locked player
+        // * Items prepared for it should be cleaned up
+        assert!(slideshow.player.lock().unwrap().updates.is_empty());
+        assert!(slideshow.status().last_refresh.is_none());
+        thread::sleep(Duration::from_millis(100));
+        assert!(tmpfiles(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_reload() {
+        let (slideshow, _dir) = new_slideshow(vec![], 10, PrefetchConfig::default());
+        let mut slideshow = slideshow.reloader(|| {
+            Ok(ReloadConfig {
+                pl_builder: PlaylistBuilder::new(),
+                slideshow: SlideshowConfig {
+                    fullscreen: false,
+                    audio_volume: 0.1,
+                    ..SlideshowConfig::default()
+                },
+                prefetch: PrefetchConfig {
+                    workers: 1,
+                    initial_items: 3,
+                },
+                restart_required: vec!["album".to_string()],
+            })
+        });
+
+        // * Config should be replaced as is until the player starts
+        slideshow.reload().unwrap();
+        assert_eq!(0.1, slideshow.config.audio_volume);
+        assert_eq!(1, slideshow.prefetch.workers);
+        slideshow.start().unwrap();
+        assert_eq!(
+            Some(false),
+            slideshow
+                .player
+                .lock()
+                .unwrap()
+                .config
+                .as_ref()
+                .map(|c| c.fullscreen)
+        );
+
+        // * Once started, config should be applied to the player
+        // * Settings requiring restart should be reported through the status
+        slideshow.player.lock().unwrap().config = Some(SlideshowConfig::default());
+        slideshow.reload().unwrap();
+        assert_eq!(
+            Some(0.1),
+            slideshow
+                .player
+                .lock()
+                .unwrap()
+                .config
+                .as_ref()
+                .map(|c| c.audio_volume)
+        );
+        assert_eq!(
+            vec!["album".to_string(), "slideshow.fullscreen".to_string()],
+            slideshow.status().restart_required
+        );
+    }
+
+    #[test]
+    fn test_restart_player() {
+        let items = vec![("a", 1), ("b", 1)];
+        let (mut slideshow, dir) = new_slideshow(items, 10, PrefetchConfig::default());
+        slideshow.start().unwrap();
+        wait_prefetch(&mut slideshow).unwrap();
+        {
+            let mut player = slideshow.player.lock().unwrap();
+            player.pause().unwrap();
+            player.mute().unwrap();
+            player.updates.clear();
+            player.fail_start = true;
+        }
+
+        // * State to restore should be kept over failed attempts
+        assert!(slideshow.restart_player().is_err());
+        slideshow.player.lock().unwrap().fail_start = false;
+        slideshow.restart_player().unwrap();
+
+        // * Playlist and state should be restored
+        let mut player = slideshow.player.lock().unwrap();
+        assert_eq!(2, player.starts);
+        assert_eq!(1, player.updates.len());
+        player.updates[0].sort();
+        assert_eq!(
+            vec![dir.path().join("a"), dir.path().join("b")],
+            player.updates[0]
+        );
+        assert!(player.status.paused && player.status.muted && !player.status.sleeping);
+    }
+
+    #[test]
+    fn test_restore_playlist() {
+        let items = vec![("a", 1), ("b", 1), ("c", 1)];
+        let (mut slideshow, dir) = new_slideshow(items, 10, PrefetchConfig::default());
+        slideshow.start().unwrap();
+        wait_prefetch(&mut slideshow).unwrap();
+        let names: Vec<_> = slideshow
+            .status_handle()
+            .playlist()
+            .into_iter()
+            .map(|item| item.id)
+            .collect();
+        slideshow.player.lock().unwrap().status.current = Some(dir.path().join(&names[1]));
+        slideshow.save_playlist().unwrap();
+        drop(slideshow);
+
+        // Media of the last item has gone, and the first one is removed from the album
+        fs::remove_file(dir.path().join(&names[2])).unwrap();
+        let album_items = vec![("a", 1), ("b", 1), ("c", 1)]
+            .into_iter()
+            .filter(|(name, _)| *name != names[0])
+            .collect();
+        let mut slideshow = Slideshow::new(
+            MockAlbum { items: album_items },
+            MockPlayer::default(),
+            PlaylistBuilder::new(),
+            Storage::open(dir.path(), 10).unwrap(),
+            SlideshowConfig::default(),
+        );
+        slideshow.start().unwrap();
+
+        // * Saved playlist should be played from the saved position without preparing items
+        // * Items whose media has gone should be skipped
+        assert!(!slideshow.is_prefetching());
+        assert_eq!(
+            vec![vec![dir.path().join(&names[1]), dir.path().join(&names[0])]],
+            slideshow.player.lock().unwrap().updates
+        );
+        assert_eq!(2, slideshow.status().playlist_size);
+        assert_eq!(2, slideshow.storage.using());
+
+        // * Album items should be synced in background, dropping ones not in the album
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while slideshow.sync.is_some() {
+            assert!(Instant::now() < deadline, "sync didn't complete");
+            slideshow.poll_prefetch().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let synced: Vec<_> = slideshow
+            .playlist
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|item| item.0)
+            .collect();
+        assert_eq!(vec![names[1].as_str()], synced);
+    }
+
+    #[test]
+    fn test_sync_items_skips_errors() {
+        let album = MockAlbum {
+            items: vec![("a", 1), ("err", 0), ("b", 1), ("c", 1)],
+        };
+        let ids: Vec<_> = ["c", "x", "a"].iter().map(|id| id.to_string()).collect();
+        // * Items failed to list are skipped rather than failing the whole sync
+        let items = Slideshow::<MockPlayer, MockAlbum>::sync_items(&album, &ids).unwrap();
+        let names: Vec<_> = items.iter().map(|item| item.0).collect();
+        assert_eq!(vec!["c", "a"], names);
     }
 }
@@ -1,15 +1,28 @@
+use crate::metrics::METRICS;
 use failure::Fail;
 use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs, io};
 
+/// File to persist usage statistics of residents over restarts
+const INDEX_FILENAME: &str = ".index.json";
+const INDEX_TMP_FILENAME: &str = ".index.json.tmp";
+/// Prefix of temporary files being downloaded into the storage.
+/// Ones found on open are leftovers of an interrupted run and removed.
+pub const TMPFILE_PREFIX: &str = ".downloading";
+
 #[derive(Fail, Debug)]
 pub enum Error {
     #[fail(display = "Error in I/O with disks: {}", _0)]
     IO(#[fail(cause)] io::Error),
     #[fail(display = "Invalid path: {:?}: {}", path, reason)]
     InvalidPath { path: PathBuf, reason: &'static str },
+    #[fail(display = "Failed to serialize index: {}", _0)]
+    Index(#[fail(cause)] serde_json::Error),
 }
 
 impl From<io::Error> for Error {
@@ -18,17 +31,83 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Index(e)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Policy to choose files to evict when storage needs to free up space
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum EvictionPolicy {
+    /// Evict smaller files first to avoid wasting bandwidth to re-download large files
+    #[default]
+    SmallestFirst,
+    /// Evict least recently used files first
+    Lru,
+    /// Evict least frequently used files first
+    Lfu,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "smallest-first" => Ok(EvictionPolicy::SmallestFirst),
+            "lru" => Ok(EvictionPolicy::Lru),
+            "lfu" => Ok(EvictionPolicy::Lfu),
+            _ => Err(format!("unknown eviction policy: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 struct Entry {
     size: u64,
     users: usize,
+    last_used: SystemTime,
+    hits: u64,
+    item_id: Option<String>,
 }
 
 impl Entry {
     fn new(size: u64) -> Self {
-        Self { size, users: 0 }
+        Self {
+            size,
+            users: 0,
+            last_used: UNIX_EPOCH,
+            hits: 0,
+            item_id: None,
+        }
+    }
+}
+
+/// Persisted form of `Entry`
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexEntry {
+    size: u64,
+    /// Milliseconds since the UNIX epoch
+    last_used: u64,
+    hits: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    item_id: Option<String>,
+}
+
+impl From<&Entry> for IndexEntry {
+    fn from(entry: &Entry) -> Self {
+        IndexEntry {
+            size: entry.size,
+            last_used: entry
+                .last_used
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            hits: entry.hits,
+            item_id: entry.item_id.clone(),
+        }
     }
 }
 
@@ -37,17 +116,22 @@ impl Entry {
 /// This storage offers management of media files with:
 /// * Filesystem size usage limit
 /// * Transparent eviction of files which are not under use
+/// * Usage statistics of files persisted over restarts, used to choose files to evict
 pub struct Storage {
     dir: PathBuf,
     capacity: u64,
     using: u64,
     residents: HashMap<PathBuf, Entry>,
+    eviction_policy: EvictionPolicy,
+    /// Whether residents has changes not yet written to the index
+    dirty: bool,
 }
 
 impl Storage {
     pub fn open<P: Into<PathBuf>>(path: P, capacity: u64) -> Result<Self> {
         let path = path.into();
-        let residents = Self::scan_residents(&path)?;
+        let mut residents = Self::scan_residents(&path)?;
+        Self::reconcile_index(&mut residents, Self::load_index(&path));
         let using = residents.values().map(|e| e.size).sum();
         info!(
             "Finish loading storage {}, {} entries using {} bytes",
@@ -55,20 +139,41 @@ impl Storage {
             residents.len(),
             using
         );
+        METRICS.storage_used_bytes.set(using);
+        METRICS.storage_capacity_bytes.set(capacity);
         Ok(Storage {
             dir: path,
             capacity,
             using,
             residents,
+            eviction_policy: EvictionPolicy::default(),
+            dirty: false,
         })
     }
 
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
     fn scan_residents(path: &Path) -> io::Result<HashMap<PathBuf, Entry>> {
         let mut residents = HashMap::new();
         for dentry in fs::read_dir(path)? {
             let dentry = dentry?;
             let meta = dentry.metadata()?;
             let filename: PathBuf = dentry.path().file_name().expect("filename").into();
+            let name = filename.to_string_lossy();
+            if name.starts_with(TMPFILE_PREFIX) {
+                info!("Removing stale temporary file {}", filename.display());
+                if let Err(e) = fs::remove_file(dentry.path()) {
+                    warn!("Failed to remove {}: {}", filename.display(), e);
+                }
+                continue;
+            }
+            // Files of the index and such are not residents
+            if name.starts_with('.') {
+                continue;
+            }
             if !meta.file_type().is_file() {
                 warn!(
                     "Skipping an entry in storage which is not a file: {}",
@@ -84,6 +189,82 @@ impl Storage {
         Ok(residents)
     }
 
+    fn load_index(path: &Path) -> HashMap<PathBuf, IndexEntry> {
+        let index_path = path.join(INDEX_FILENAME);
+        let content = match fs::read(&index_path) {
+            Ok(content) => content,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!(
+                        "Failed to read storage index {}: {}",
+                        index_path.display(),
+                        e
+                    );
+                }
+                return HashMap::new();
+            }
+        };
+        match serde_json::from_slice(&content) {
+            Ok(index) => index,
+            Err(e) => {
+                warn!(
+                    "Ignoring broken storage index {}: {}",
+                    index_path.display(),
+                    e
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Restore statistics of residents from the index.
+    ///
+    /// Files existing on disk are the source of truth. Index entries for missing files
+    /// are dropped and ones with mismatching size are considered as a different file.
+    fn reconcile_index(
+        residents: &mut HashMap<PathBuf, Entry>,
+        mut index: HashMap<PathBuf, IndexEntry>,
+    ) {
+        for (filename, entry) in residents.iter_mut() {
+            match index.remove(filename) {
+                Some(indexed) if indexed.size == entry.size => {
+                    entry.last_used = UNIX_EPOCH + Duration::from_millis(indexed.last_used);
+                    entry.hits = indexed.hits;
+                    entry.item_id = indexed.item_id;
+                }
+                Some(_) => debug!(
+                    "Size of {} differs from the index, resetting its stats",
+                    filename.display()
+                ),
+                None => debug!("Found resident {} not in the index", filename.display()),
+            }
+        }
+        for filename in index.keys() {
+            debug!(
+                "Dropping index entry of missing file {}",
+                filename.display()
+            );
+        }
+    }
+
+    /// Write statistics of residents into the index if there's any change.
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let index: HashMap<_, _> = self
+            .residents
+            .iter()
+            .map(|(filename, entry)| (filename, IndexEntry::from(entry)))
+            .collect();
+        let tmp_path = self.dir.join(INDEX_TMP_FILENAME);
+        fs::write(&tmp_path, serde_json::to_vec(&index)?)?;
+        fs::rename(&tmp_path, self.dir.join(INDEX_FILENAME))?;
+        self.dirty = false;
+        debug!("Wrote storage index with {} entries", index.len());
+        Ok(())
+    }
+
     /// Acquire the size specified in local storage.
     ///
     /// Acquisition may fails when free capacity cannot contain
@@ -91,11 +272,34 @@ impl Storage {
     /// Returns the list containing path to acquired file for those succeeds,
     /// None on failure.
     pub fn acquire(&mut self, path: &Path, size: u64, reserved: &HashSet<&Path>) -> Result<bool> {
+        self.acquire_entry(None, path, size, reserved)
+    }
+
+    /// Same as `acquire`, but also records the ID of the album item the file came from.
+    pub fn acquire_item(
+        &mut self,
+        item_id: &str,
+        path: &Path,
+        size: u64,
+        reserved: &HashSet<&Path>,
+    ) -> Result<bool> {
+        self.acquire_entry(Some(item_id), path, size, reserved)
+    }
+
+    fn acquire_entry(
+        &mut self,
+        item_id: Option<&str>,
+        path: &Path,
+        size: u64,
+        reserved: &HashSet<&Path>,
+    ) -> Result<bool> {
         let filename = Self::valid_filename(path)?;
 
         if let Some(entry) = self.residents.get_mut(&filename) {
             debug!("Adding users of {}/{}", filename.display(), entry.users);
             entry.users += 1;
+            Self::touch(entry, item_id);
+            self.dirty = true;
             return Ok(true);
         }
 
@@ -118,11 +322,22 @@ impl Storage {
             .or_insert_with(|| Entry::new(size));
         entry.size = size;
         entry.users += 1;
+        Self::touch(entry, item_id);
+        self.dirty = true;
 
         self.using += size;
+        METRICS.storage_used_bytes.set(self.using);
         Ok(true)
     }
 
+    fn touch(entry: &mut Entry, item_id: Option<&str>) {
+        entry.last_used = SystemTime::now();
+        entry.hits += 1;
+        if let Some(item_id) = item_id {
+            entry.item_id = Some(item_id.to_string());
+        }
+    }
+
     /// Release the size specified from local storage.
     ///
     /// This release locked size in capacity so that new acquisition can
@@ -145,14 +360,18 @@ impl Storage {
     }
 
     fn try_evict(&mut self, acquire_size: u64, reserved: &HashSet<&Path>) -> io::Result<bool> {
-        let mut sizes: Vec<_> = self.residents.iter().collect();
-        // It may end up wasting network bandwidth to evict 300MB file
-        // to acquire 1MB file. Prefer to evict smaller files as possible.
-        sizes.sort_unstable_by_key(|(_, entry)| entry.size);
+        let mut candidates: Vec<_> = self.residents.iter().collect();
+        match self.eviction_policy {
+            // It may end up wasting network bandwidth to evict 300MB file
+            // to acquire 1MB file. Prefer to evict smaller files as possible.
+            EvictionPolicy::SmallestFirst => candidates.sort_unstable_by_key(|(_, e)| e.size),
+            EvictionPolicy::Lru => candidates.sort_unstable_by_key(|(_, e)| e.last_used),
+            EvictionPolicy::Lfu => candidates.sort_unstable_by_key(|(_, e)| (e.hits, e.last_used)),
+        }
 
         let mut evicted = Vec::new();
         let mut freed = 0;
-        for (path, entry) in sizes {
+        for (path, entry) in candidates {
             if entry.users > 0 || reserved.contains(&path.as_ref()) {
                 continue;
             }
@@ -173,6 +392,7 @@ impl Storage {
             fs::remove_file(self.filepath(&path).expect("filepath"))?;
             let entry = self.residents.remove(&path).unwrap();
             self.using -= entry.size;
+            METRICS.storage_evictions.inc();
             debug!(
                 "Evict file {} to free {} bytes, using = {}",
                 path.display(),
@@ -180,10 +400,26 @@ impl Storage {
                 self.using
             )
         }
+        self.dirty = true;
 
         Ok(true)
     }
 
+    /// Return the directory keeping media files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Return the total size of files kept in the storage
+    pub fn using(&self) -> u64 {
+        self.using
+    }
+
+    /// Return the size limit of the storage
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
     pub fn filepath<P: AsRef<Path>>(&self, filename: P) -> Result<PathBuf> {
         let filename = Self::valid_filename(filename.as_ref())?;
         Ok(self.dir.join(filename))
@@ -214,6 +450,14 @@ impl Storage {
     }
 }
 
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("Failed to write storage index: {}", e);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -269,6 +513,101 @@ mod tests {
         assert!(file_exists(dir.path(), "b"));
     }
 
+    fn set_last_used(storage: &mut Storage, name: &str, secs: u64) {
+        storage
+            .residents
+            .get_mut(Path::new(name))
+            .unwrap()
+            .last_used = UNIX_EPOCH + Duration::from_secs(secs);
+    }
+
+    #[test]
+    fn test_acquire_with_lru_eviction() {
+        let (storage, dir) = new_storage(20);
+        let mut storage = storage.eviction_policy(EvictionPolicy::Lru);
+
+        let reserved = HashSet::new();
+        assert!(storage.acquire(&PathBuf::from("a"), 5, &reserved).unwrap());
+        create_file(dir.path(), "a", 5);
+        assert!(storage.acquire(&PathBuf::from("b"), 10, &reserved).unwrap());
+        create_file(dir.path(), "b", 10);
+        storage.release(&PathBuf::from("a")).unwrap();
+        storage.release(&PathBuf::from("b")).unwrap();
+        set_last_used(&mut storage, "a", 200);
+        set_last_used(&mut storage, "b", 100);
+
+        // b should be evicted over smaller a because it's used less recently
+        assert!(storage.acquire(&PathBuf::from("c"), 6, &reserved).unwrap());
+        assert!(file_exists(dir.path(), "a"));
+        assert!(!file_exists(dir.path(), "b"));
+    }
+
+    #[test]
+    fn test_acquire_with_lfu_eviction() {
+        let (storage, dir) = new_storage(20);
+        let mut storage = storage.eviction_policy(EvictionPolicy::Lfu);
+
+        let reserved = HashSet::new();
+        assert!(storage.acquire(&PathBuf::from("a"), 10, &reserved).unwrap());
+        create_file(dir.path(), "a", 10);
+        assert!(storage.acquire(&PathBuf::from("b"), 10, &reserved).unwrap());
+        create_file(dir.path(), "b", 10);
+        // Acquire a once more
+        assert!(storage.acquire(&PathBuf::from("a"), 10, &reserved).unwrap());
+        storage.release(&PathBuf::from("a")).unwrap();
+        storage.release(&PathBuf::from("a")).unwrap();
+        storage.release(&PathBuf::from("b")).unwrap();
+        set_last_used(&mut storage, "a", 100);
+        set_last_used(&mut storage, "b", 200);
+
+        // b should be evicted because it's used less frequently, even though more recently
+        assert!(storage.acquire(&PathBuf::from("c"), 10, &reserved).unwrap());
+        assert!(file_exists(dir.path(), "a"));
+        assert!(!file_exists(dir.path(), "b"));
+    }
+
+    #[test]
+    fn test_index_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let reserved = HashSet::new();
+        let mut storage = Storage::open(dir.path(), 100).unwrap();
+        for (name, size) in &[("a", 10), ("b", 20), ("c", 30)] {
+            assert!(storage
+                .acquire_item(&format!("id-{}", name), Path::new(name), *size, &reserved)
+                .unwrap());
+            create_file(dir.path(), name, *size);
+        }
+        assert!(storage.acquire(&PathBuf::from("a"), 10, &reserved).unwrap());
+        set_last_used(&mut storage, "a", 100);
+        drop(storage);
+
+        // Removed file should be dropped and resized file should lose its stats
+        fs::remove_file(dir.path().join("b")).unwrap();
+        create_file(dir.path(), "c", 5);
+
+        let storage = Storage::open(dir.path(), 100).unwrap();
+        let mut names: Vec<_> = storage.residents.keys().cloned().collect();
+        names.sort();
+        assert_eq!(vec![PathBuf::from("a"), PathBuf::from("c")], names);
+        let a = &storage.residents[Path::new("a")];
+        assert_eq!(2, a.hits);
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(100), a.last_used);
+        assert_eq!(Some("id-a".to_string()), a.item_id);
+        // Users aren't persisted as no one uses files right after restart
+        assert_eq!(0, a.users);
+        assert_eq!(Entry::new(5), storage.residents[Path::new("c")]);
+        assert_eq!(15, storage.using());
+    }
+
+    #[test]
+    fn test_eviction_policy_from_str() {
+        assert_eq!(Ok(EvictionPolicy::Lru), "lru".parse());
+        assert_eq!(Ok(EvictionPolicy::Lfu), "lfu".parse());
+        assert_eq!(Ok(EvictionPolicy::SmallestFirst), "smallest-first".parse());
+        assert!("mru".parse::<EvictionPolicy>().is_err());
+    }
+
     #[test]
     fn test_acquire_multi_users() {
         let (mut storage, dir) = new_storage(20);
@@ -338,6 +677,9 @@ mod tests {
         create_file(dir.path(), "b", 5);
         // Result must not contain non-regular file entry
         fs::create_dir(dir.path().join("c")).unwrap();
+        // Result must not contain hidden files, and stale temporary files must be removed
+        create_file(dir.path(), INDEX_FILENAME, 3);
+        create_file(dir.path(), ".downloading-1-0.tmp", 3);
 
         let mut expected = HashMap::new();
         expected.insert(PathBuf::from("a"), Entry::new(10));
@@ -345,6 +687,8 @@ mod tests {
 
         let residents = Storage::scan_residents(dir.path()).unwrap();
         assert_eq!(expected, residents);
+        assert!(file_exists(dir.path(), INDEX_FILENAME));
+        assert!(!file_exists(dir.path(), ".downloading-1-0.tmp"));
     }
 
     #[test]